@@ -0,0 +1,257 @@
+//! Static analysis of program tapes, for use without running them.
+use crate::source::SPEC_MAPPING;
+use crate::{MAddr, MAX_PROGRAM_LEN};
+use alloc::vec::Vec;
+
+/// The broad category an opcode belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpcodeClass {
+    /// `<` and `>`
+    Pointer,
+    /// `-` and `+`
+    Cell,
+    /// `[` and `]`
+    Loop,
+    /// `.` and `,`
+    Io,
+    /// `{` and `}`
+    Stack,
+    /// `(`, `)` and `^`
+    Aux,
+    /// `!`, `&`, `/` and `\`
+    Bitwise,
+    /// Everything else, including the halt instruction and unimplemented opcodes
+    Other,
+}
+
+/// The number of variants of `OpcodeClass`.
+pub const OPCODE_CLASSES: usize = 8;
+
+impl OpcodeClass {
+    /// Every class, in the order used to index per-class counts.
+    pub const ALL: [OpcodeClass; OPCODE_CLASSES] = [
+        OpcodeClass::Pointer,
+        OpcodeClass::Cell,
+        OpcodeClass::Loop,
+        OpcodeClass::Io,
+        OpcodeClass::Stack,
+        OpcodeClass::Aux,
+        OpcodeClass::Bitwise,
+        OpcodeClass::Other,
+    ];
+
+    /// The class of the given opcode.
+    pub fn of(opcode: u8) -> OpcodeClass {
+        match opcode {
+            0 | 1 => OpcodeClass::Pointer,
+            2 | 3 => OpcodeClass::Cell,
+            4 | 5 => OpcodeClass::Loop,
+            6 | 7 => OpcodeClass::Io,
+            8 | 9 => OpcodeClass::Stack,
+            10..=12 => OpcodeClass::Aux,
+            13 | 14 | 16 | 17 => OpcodeClass::Bitwise,
+            _ => OpcodeClass::Other,
+        }
+    }
+
+    /// The position of this class in `OpcodeClass::ALL`.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// A table of weights for `SBrainVM::run_metered` which charges every opcode the cost of its
+    /// class, from `costs` indexed by `OpcodeClass::index`. For example, to make I/O cost 10, cell
+    /// arithmetic 2, and everything else 1:
+    ///
+    /// ```
+    /// # use sbrain::*;
+    /// let mut costs = [1; OPCODE_CLASSES];
+    /// costs[OpcodeClass::Io.index()] = 10;
+    /// costs[OpcodeClass::Cell.index()] = 2;
+    /// let weights = OpcodeClass::cost_table(&costs);
+    /// assert_eq!((weights[6], weights[3], weights[0]), (10, 2, 1));
+    /// ```
+    pub fn cost_table(costs: &[u64; OPCODE_CLASSES]) -> [u64; 32] {
+        let mut weights = [0; 32];
+        for (opcode, weight) in weights.iter_mut().enumerate() {
+            *weight = costs[OpcodeClass::of(opcode as u8).index()];
+        }
+        weights
+    }
+}
+
+/// Structural metrics of a program tape, as computed by `program_metrics`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramMetrics {
+    /// The number of instructions on the tape.
+    pub instructions: usize,
+    /// The deepest loop nesting reached; 0 for a program with no loops.
+    pub max_nesting: usize,
+    /// How many instructions fall into each `OpcodeClass`, indexed by `OpcodeClass::index`.
+    pub class_counts: [usize; OPCODE_CLASSES],
+    /// Whether every `[` has a matching `]` and vice versa.
+    pub balanced: bool,
+}
+
+impl ProgramMetrics {
+    /// How many instructions fall into the given class.
+    pub fn count(&self, class: OpcodeClass) -> usize {
+        self.class_counts[class.index()]
+    }
+}
+
+/// Compute structural metrics for a program tape in a single pass, without running it.
+pub fn program_metrics(tape: &[u8]) -> ProgramMetrics {
+    let mut class_counts = [0; OPCODE_CLASSES];
+    let mut depth: usize = 0;
+    let mut max_nesting = 0;
+    let mut balanced = true;
+
+    for &opcode in tape {
+        class_counts[OpcodeClass::of(opcode).index()] += 1;
+        match opcode {
+            4 => {
+                depth += 1;
+                max_nesting = max_nesting.max(depth);
+            }
+            5 => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => balanced = false,
+            },
+            _ => {}
+        }
+    }
+
+    ProgramMetrics {
+        instructions: tape.len(),
+        max_nesting,
+        class_counts,
+        balanced: balanced && depth == 0,
+    }
+}
+
+/// Find the bracket matching the one at `addr` in `tape`, scanning forward for a `[` and
+/// backward for a `]` and skipping over nested loops. Returns None if `addr` does not hold a
+/// bracket or if the scan reaches either end of the tape without finding a match. Only the first
+/// `MAX_PROGRAM_LEN` opcodes of the tape are considered, since no machine can address the rest.
+pub fn matching_bracket(tape: &[u8], addr: MAddr) -> Option<MAddr> {
+    let tape = &tape[..tape.len().min(MAX_PROGRAM_LEN)];
+    let forward = match tape.get(addr as usize) {
+        Some(&4) => true,
+        Some(&5) => false,
+        _ => return None,
+    };
+    let (open, close) = if forward { (4, 5) } else { (5, 4) };
+    let mut nest_level = 1;
+    let mut p = addr;
+    while nest_level > 0 {
+        p = if forward {
+            p.checked_add(1)?
+        } else {
+            p.checked_sub(1)?
+        };
+        match tape.get(p as usize) {
+            Some(&opcode) if opcode == open => nest_level += 1,
+            Some(&opcode) if opcode == close => nest_level -= 1,
+            Some(_) => {}
+            None => return None,
+        }
+    }
+    Some(p)
+}
+
+/// Find the first occurrence of `opcode` in `tape` at or after `from`, returning its address.
+/// Returns None if there is none before the end of the tape. Like `matching_bracket`, only the
+/// first `MAX_PROGRAM_LEN` opcodes of the tape are considered.
+pub fn scan_forward(tape: &[u8], from: MAddr, opcode: u8) -> Option<MAddr> {
+    let tape = &tape[..tape.len().min(MAX_PROGRAM_LEN)];
+    tape.get(from as usize..)?
+        .iter()
+        .position(|&o| o == opcode)
+        .map(|offset| from + offset as MAddr)
+}
+
+/// A problem with a program tape found by `verify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The program has `len` instructions, more than `MAX_PROGRAM_LEN`.
+    TooLong { len: usize },
+    /// The bracket at `addr` has no matching bracket.
+    UnbalancedBracket { addr: usize },
+    /// The opcode at `addr` is not one the machine implements, so it would do nothing.
+    UnknownOpcode { addr: usize, opcode: u8 },
+}
+
+/// Check a program tape before running it: that it fits on the machine's tape, that its brackets
+/// are balanced, and that it uses only implemented opcodes. Every problem is reported, not just
+/// the first; a length problem comes first, followed by the rest in address order.
+pub fn verify(tape: &[u8]) -> Result<(), Vec<VerifyError>> {
+    let mut errors = Vec::new();
+    if tape.len() > MAX_PROGRAM_LEN {
+        errors.push(VerifyError::TooLong { len: tape.len() });
+    }
+
+    let mut by_addr = Vec::new();
+    let mut open = Vec::new();
+    for (addr, &opcode) in tape.iter().enumerate() {
+        match opcode {
+            4 => open.push(addr),
+            5 => match open.pop() {
+                Some(_) => {}
+                None => by_addr.push((addr, VerifyError::UnbalancedBracket { addr })),
+            },
+            _ if opcode as usize >= SPEC_MAPPING.len() => {
+                by_addr.push((addr, VerifyError::UnknownOpcode { addr, opcode }));
+            }
+            _ => {}
+        }
+    }
+    by_addr.extend(
+        open.into_iter()
+            .map(|addr| (addr, VerifyError::UnbalancedBracket { addr })),
+    );
+    by_addr.sort_by_key(|&(addr, _)| addr);
+    errors.extend(by_addr.into_iter().map(|(_, error)| error));
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Whether a program can halt with `@`, as estimated by `can_halt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reachability {
+    /// Every run executes a halt instruction, unless it is stopped first from outside (by a cycle
+    /// limit, for example) or by an option which stops the machine early.
+    DefinitelyReachesHalt,
+    /// The program has a halt instruction, but some runs may never execute it.
+    MayNotHalt,
+    /// The program has no halt instruction, so it can never halt with an exit code.
+    NoHaltPresent,
+}
+
+/// Estimate, without running it, whether `tape` always reaches a halt instruction.
+///
+/// The answer is conservative: `DefinitelyReachesHalt` is only given when it is certain, which is
+/// when the first `@` is preceded by straight-line code alone. Any loop, restart, skip or code
+/// write before it gives `MayNotHalt`, even if the program would in fact always halt. Like
+/// `matching_bracket`, only the first `MAX_PROGRAM_LEN` opcodes of the tape are considered.
+pub fn can_halt(tape: &[u8]) -> Reachability {
+    for &opcode in tape.iter().take(MAX_PROGRAM_LEN) {
+        match opcode {
+            15 => return Reachability::DefinitelyReachesHalt,
+            // `[`, `]`, `~`, `;` and `?` can keep execution from getting any further
+            4 | 5 | 19 | 21 | 25 => {
+                return if tape.iter().take(MAX_PROGRAM_LEN).any(|&op| op == 15) {
+                    Reachability::MayNotHalt
+                } else {
+                    Reachability::NoHaltPresent
+                };
+            }
+            _ => {}
+        }
+    }
+    Reachability::NoHaltPresent
+}
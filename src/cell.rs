@@ -0,0 +1,86 @@
+//! The types a machine's data cells can have.
+use alloc::fmt;
+
+/// The type of a machine's data cells, stack values and auxiliary register. This is implemented
+/// for `u8`, which is `MData` and the default, and for `u16`, `u32` and `u64`.
+///
+/// The specification describes 32-bit cells, so `SBrainVM<u32>` follows it most closely. 8-bit
+/// cells remain the default, since input and output are bytes either way. Arithmetic wraps (or
+/// saturates) at the width of the cell, so a program can behave differently with different cell
+/// types.
+pub trait Cell: Copy + Default + Ord + fmt::Debug + fmt::Display {
+    /// The cell holding the low bits of `value`.
+    fn from_u64(value: u64) -> Self;
+    /// The cell's value, zero-extended.
+    fn to_u64(self) -> u64;
+    /// The cell's value read as two's complement, sign-extended.
+    fn to_i64(self) -> i64;
+    /// Add, wrapping around at the limits of the cell.
+    fn wrapping_add(self, other: Self) -> Self;
+    /// Subtract, wrapping around at the limits of the cell.
+    fn wrapping_sub(self, other: Self) -> Self;
+    /// Add, sticking at the maximum.
+    fn saturating_add(self, other: Self) -> Self;
+    /// Subtract, sticking at zero.
+    fn saturating_sub(self, other: Self) -> Self;
+    /// Rotate the bits left by `n`.
+    fn rotate_left(self, n: u32) -> Self;
+    /// Rotate the bits right by `n`.
+    fn rotate_right(self, n: u32) -> Self;
+    /// Bitwise NOT.
+    fn bit_not(self) -> Self;
+    /// Bitwise AND.
+    fn bit_and(self, other: Self) -> Self;
+}
+
+macro_rules! impl_cell {
+    ($($t:ty => $signed:ty),*) => {$(
+        impl Cell for $t {
+            fn from_u64(value: u64) -> $t {
+                value as $t
+            }
+
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+
+            fn to_i64(self) -> i64 {
+                self as $signed as i64
+            }
+
+            fn wrapping_add(self, other: $t) -> $t {
+                <$t>::wrapping_add(self, other)
+            }
+
+            fn wrapping_sub(self, other: $t) -> $t {
+                <$t>::wrapping_sub(self, other)
+            }
+
+            fn saturating_add(self, other: $t) -> $t {
+                <$t>::saturating_add(self, other)
+            }
+
+            fn saturating_sub(self, other: $t) -> $t {
+                <$t>::saturating_sub(self, other)
+            }
+
+            fn rotate_left(self, n: u32) -> $t {
+                <$t>::rotate_left(self, n)
+            }
+
+            fn rotate_right(self, n: u32) -> $t {
+                <$t>::rotate_right(self, n)
+            }
+
+            fn bit_not(self) -> $t {
+                !self
+            }
+
+            fn bit_and(self, other: $t) -> $t {
+                self & other
+            }
+        }
+    )*};
+}
+
+impl_cell!(u8 => i8, u16 => i16, u32 => i32, u64 => i64);
@@ -0,0 +1,138 @@
+//! Human-readable listings of program tapes.
+use crate::source::Transliterator;
+use alloc::fmt::Write;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// A short name for each implemented opcode, indexed by opcode.
+const MNEMONICS: [&str; 27] = [
+    "pointer-left",
+    "pointer-right",
+    "decrement",
+    "increment",
+    "loop-begin",
+    "loop-end",
+    "output",
+    "input",
+    "push",
+    "pop",
+    "aux-load",
+    "aux-store",
+    "aux-clear",
+    "aux-not",
+    "aux-and",
+    "halt",
+    "aux-rotate-left",
+    "aux-rotate-right",
+    "random",
+    "restart",
+    "code-read",
+    "code-write",
+    "equal",
+    "less",
+    "greater",
+    "skip-if-zero",
+    "select-channel",
+];
+
+/// For each address on the tape, the address of the matching bracket, if it holds a matched one.
+fn bracket_pairs(tape: &[u8]) -> Vec<Option<usize>> {
+    let mut pairs = vec![None; tape.len()];
+    let mut open = Vec::new();
+    for (addr, &opcode) in tape.iter().enumerate() {
+        match opcode {
+            4 => open.push(addr),
+            5 => {
+                if let Some(start) = open.pop() {
+                    pairs[start] = Some(addr);
+                    pairs[addr] = Some(start);
+                }
+            }
+            _ => {}
+        }
+    }
+    pairs
+}
+
+/// Produce a listing of a program tape, one instruction per line: its address in hexadecimal, its
+/// source character, and a short description. Loop bodies are indented, and each bracket notes
+/// the address of its match, or that it has none. For example, `[+>]` disassembles to:
+///
+/// ```text
+/// 0000  [  loop-begin -> 0003
+/// 0001    +  increment
+/// 0002    >  pointer-right
+/// 0003  ]  loop-end -> 0000
+/// ```
+///
+/// Opcodes the machine does not implement are listed as unknown, with their number.
+pub fn disassemble(tape: &[u8]) -> String {
+    Transliterator::new().disassemble(tape)
+}
+
+/// Convert a program tape back into source code, one character per instruction, so that
+/// `source_to_tape` gives back the same tape. Opcodes the machine does not implement have no
+/// character, so each is written as its number inside a comment, such as `#31#`; these are shown,
+/// but dropped again when the source is transliterated.
+///
+/// ```
+/// # use sbrain::*;
+/// assert_eq!(tape_to_source(&[3, 3, 4, 2, 5, 31, 15]), "++[-]#31#@");
+/// ```
+pub fn tape_to_source(tape: &[u8]) -> String {
+    Transliterator::new().tape_to_source(tape)
+}
+
+impl Transliterator {
+    /// Produce a listing of a program tape like `disassemble`, showing each instruction's
+    /// character under this transliterator's mapping.
+    pub fn disassemble(&self, tape: &[u8]) -> String {
+        let pairs = bracket_pairs(tape);
+        let mut listing = String::new();
+        let mut depth: usize = 0;
+
+        for (addr, &opcode) in tape.iter().enumerate() {
+            if opcode == 5 && pairs[addr].is_some() {
+                depth -= 1;
+            }
+            let character = self.character(opcode).unwrap_or(' ');
+            let _ = write!(listing, "{:04x}  {:2$}", addr, "", depth * 2);
+            match MNEMONICS.get(opcode as usize) {
+                Some(mnemonic) => {
+                    let _ = write!(listing, "{}  {}", character, mnemonic);
+                }
+                None => {
+                    let _ = write!(listing, "{}  unknown opcode {}", character, opcode);
+                }
+            }
+            match (opcode, pairs[addr]) {
+                (4, Some(other)) | (5, Some(other)) => {
+                    let _ = write!(listing, " -> {:04x}", other);
+                }
+                (4, None) | (5, None) => listing.push_str(" (unmatched)"),
+                _ => {}
+            }
+            listing.push('\n');
+            if opcode == 4 && pairs[addr].is_some() {
+                depth += 1;
+            }
+        }
+        listing
+    }
+
+    /// Convert a program tape back into source code like `tape_to_source`, using this
+    /// transliterator's mapping, so that `compile` with the same transliterator gives back the
+    /// same tape.
+    pub fn tape_to_source(&self, tape: &[u8]) -> String {
+        let mut source = String::with_capacity(tape.len());
+        for &opcode in tape {
+            match self.character(opcode) {
+                Some(character) => source.push(character),
+                None => {
+                    let _ = write!(source, "#{}#", opcode);
+                }
+            }
+        }
+        source
+    }
+}
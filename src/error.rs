@@ -0,0 +1,72 @@
+//! Errors from building, loading and running machines.
+use crate::io;
+use alloc::fmt;
+
+/// A reason a machine could not be built, loaded, restored or run.
+#[derive(Debug)]
+pub enum SBrainError {
+    /// The program has `len` instructions, more than the `max` the exec tape holds.
+    ProgramTooLong { len: usize, max: usize },
+    /// `len` cells of data starting at `offset` run past the end of the data tape.
+    DataTooLong { offset: usize, len: usize },
+    /// A tape of `len` cells was asked for, but tapes hold from 1 to 65536.
+    TapeLength { len: usize },
+    /// A snapshot's data pointer or cells are beyond the machine's `AddressWidth`.
+    AddressTooWide,
+    /// Reading input or writing output failed.
+    Io(io::Error),
+    /// JSON given to `from_json` could not be decoded.
+    #[cfg(feature = "serde")]
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for SBrainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SBrainError::ProgramTooLong { len, max } => write!(
+                f,
+                "Program has {} instructions, but at most {} can be loaded.",
+                len, max
+            ),
+            SBrainError::DataTooLong { offset, len } => write!(
+                f,
+                "{} cells of data at {} exceed the data tape length.",
+                len, offset
+            ),
+            SBrainError::TapeLength { len } => {
+                write!(f, "Tape length {} is not between 1 and 65536.", len)
+            }
+            SBrainError::AddressTooWide => {
+                write!(f, "Snapshot needs a wider address width than the VM has.")
+            }
+            SBrainError::Io(ref e) => write!(f, "{}", e),
+            #[cfg(feature = "serde")]
+            SBrainError::Json(ref e) => write!(f, "Invalid JSON: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for SBrainError {
+    fn from(e: io::Error) -> SBrainError {
+        SBrainError::Io(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<serde_json::Error> for SBrainError {
+    fn from(e: serde_json::Error) -> SBrainError {
+        SBrainError::Json(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SBrainError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            SBrainError::Io(ref e) => Some(e),
+            #[cfg(feature = "serde")]
+            SBrainError::Json(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
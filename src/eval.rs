@@ -0,0 +1,54 @@
+//! Running a program against in-memory input in one call.
+use crate::cell::Cell;
+use crate::error::SBrainError;
+use crate::{HaltReason, MData, SBrainVM};
+use alloc::vec::Vec;
+
+/// The outcome of running a program with `evaluate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalResult {
+    /// Everything the program wrote to its output.
+    pub output: Vec<MData>,
+    /// The number of cycles run.
+    pub cycles: u32,
+    /// Why the program stopped.
+    pub halt_reason: HaltReason,
+    /// Whether the program stopped by executing a halt instruction, rather than being cut off.
+    pub halted: bool,
+    /// The exit code, if the program halted; None if it was cut off. A program which halts with
+    /// code 0 has `Some(0)` here.
+    pub exit_code: Option<u8>,
+}
+
+/// Run `program` on a fresh machine with the given input, for at most `limit` cycles, and collect
+/// its output.
+/// Fails if the program is too long to load.
+pub fn evaluate(
+    program: &[u8],
+    input: &[MData],
+    limit: Option<u32>,
+) -> Result<EvalResult, SBrainError> {
+    let mut input = input;
+    let mut machine = SBrainVM::new_buffered(Some(&mut input), program)?;
+    // Reading from a slice and writing to a buffer cannot fail.
+    let (cycles, halt_reason) = machine.run(limit)?;
+    Ok(EvalResult::collect(&machine, cycles, halt_reason))
+}
+
+impl EvalResult {
+    /// The result of a run on a buffered `machine` which took `cycles` cycles and stopped for
+    /// `halt_reason`.
+    pub(crate) fn collect<C: Cell>(
+        machine: &SBrainVM<C>,
+        cycles: u32,
+        halt_reason: HaltReason,
+    ) -> EvalResult {
+        EvalResult {
+            output: machine.output().to_vec(),
+            cycles,
+            halt_reason,
+            halted: halt_reason.exit_code().is_some(),
+            exit_code: halt_reason.exit_code(),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Tools for genetic programming with SBrain.
+use crate::error::SBrainError;
+use crate::eval::evaluate;
+use crate::MAX_PROGRAM_LEN;
+use alloc::vec::Vec;
+
+pub use crate::rng::{Rng, SplitMix64};
+
+/// Run `program` on `input` for at most `limit` cycles and return everything it printed.
+fn outputs_for(program: &[u8], input: &[u8], limit: Option<u32>) -> Result<Vec<u8>, SBrainError> {
+    evaluate(program, input, limit).map(|result| result.output)
+}
+
+/// Find the `]` matching the `[` at `addr`, if any.
+fn closing_bracket(program: &[u8], addr: usize) -> Option<usize> {
+    let mut nest_level = 0;
+    for (i, &opcode) in program.iter().enumerate().skip(addr) {
+        if opcode == 4 {
+            nest_level += 1;
+        } else if opcode == 5 {
+            nest_level -= 1;
+            if nest_level == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// Remove the instruction at `addr`. A `[` is removed along with its matching `]`.
+/// Returns None if `addr` holds a matched `]`, which is only removed along with its `[`.
+fn remove_instruction(program: &[u8], addr: usize) -> Option<Vec<u8>> {
+    let mut candidate = program.to_vec();
+    match program[addr] {
+        4 => {
+            if let Some(close) = closing_bracket(program, addr) {
+                candidate.remove(close);
+            }
+        }
+        5 => {
+            let matched = (0..addr)
+                .rev()
+                .filter(|&i| program[i] == 4)
+                .any(|i| closing_bracket(program, i) == Some(addr));
+            if matched {
+                return None;
+            }
+        }
+        _ => {}
+    }
+    candidate.remove(addr);
+    Some(candidate)
+}
+
+/// Shrink a program by greedily removing instructions, keeping each removal only if the program's
+/// output is unchanged on every one of `inputs`. Brackets are removed in matched pairs so that a
+/// balanced program stays balanced. Each run is limited to `limit` cycles; with `None`, a removal
+/// which makes the program loop forever will hang the minimizer, so a limit is recommended.
+///
+/// The result is a local minimum: no single instruction (or bracket pair) can be removed from it
+/// without changing its output. It is only guaranteed to behave like the original on the provided
+/// inputs; on any other input its output may differ.
+///
+/// Fails if the program is longer than `MAX_PROGRAM_LEN`, since it could not be run.
+pub fn minimize(
+    program: &[u8],
+    inputs: &[&[u8]],
+    limit: Option<u32>,
+) -> Result<Vec<u8>, SBrainError> {
+    if program.len() > MAX_PROGRAM_LEN {
+        return Err(SBrainError::ProgramTooLong {
+            len: program.len(),
+            max: MAX_PROGRAM_LEN,
+        });
+    }
+    let expected = inputs
+        .iter()
+        .map(|input| outputs_for(program, input, limit))
+        .collect::<Result<Vec<_>, _>>()?;
+    // Every candidate is shorter than the program, so it can always be run
+    let preserves_output = |candidate: &[u8]| {
+        inputs.iter().zip(expected.iter()).all(|(input, expected)| {
+            outputs_for(candidate, input, limit).ok().as_ref() == Some(expected)
+        })
+    };
+
+    let mut current = program.to_vec();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut addr = 0;
+        while addr < current.len() {
+            match remove_instruction(&current, addr) {
+                Some(ref candidate) if preserves_output(candidate) => {
+                    current = candidate.clone();
+                    changed = true;
+                }
+                _ => addr += 1,
+            }
+        }
+    }
+    Ok(current)
+}
+
+/// The kinds of input `random_input` can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputDist {
+    /// Any byte, all equally likely.
+    Uniform,
+    /// Printable ASCII characters, from space (0x20) to tilde (0x7e).
+    Printable,
+    /// Values from `low` to `high`, inclusive. If `low` is greater than `high`, every value is
+    /// `low`.
+    Range { low: u8, high: u8 },
+}
+
+/// Generate `len` bytes of input for testing candidate programs, drawn from `distribution`.
+/// The same generator state always produces the same input.
+pub fn random_input(rng: &mut impl Rng, len: usize, distribution: InputDist) -> Vec<u8> {
+    let (low, high) = match distribution {
+        InputDist::Uniform => (0, 255),
+        InputDist::Printable => (0x20, 0x7e),
+        InputDist::Range { low, high } => (low, high.max(low)),
+    };
+    let span = (high - low) as u64 + 1;
+    (0..len)
+        .map(|_| low + (rng.next_u64() % span) as u8)
+        .collect()
+}
@@ -0,0 +1,122 @@
+//! The I/O traits the machine reads input and writes output through.
+//!
+//! With the default `std` feature this is simply `std::io`. Without it, this module provides a
+//! minimal stand-in with `Read` implemented for byte slices and `Write` for `Vec<u8>`, so that
+//! machines can run against in-memory buffers on targets without an operating system.
+
+#[cfg(feature = "std")]
+pub use std::io::*;
+
+#[cfg(not(feature = "std"))]
+pub use self::bare::*;
+
+#[cfg(not(feature = "std"))]
+mod bare {
+    use alloc::fmt;
+    use alloc::vec::Vec;
+
+    /// The kinds of error the bare I/O traits can report.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ErrorKind {
+        /// The operation was interrupted and can be retried.
+        Interrupted,
+        /// A writer accepted no more bytes.
+        WriteZero,
+        /// Any other error.
+        Other,
+    }
+
+    /// An I/O error.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Error {
+        kind: ErrorKind,
+    }
+
+    impl Error {
+        pub fn new(kind: ErrorKind) -> Error {
+            Error { kind }
+        }
+
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Error {
+            Error::new(kind)
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "I/O error: {:?}", self.kind)
+        }
+    }
+
+    pub type Result<T> = ::core::result::Result<T, Error>;
+
+    /// A source of bytes, like `std::io::Read`.
+    pub trait Read {
+        /// Read some bytes into `buf`, returning how many were read; 0 means end of input.
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+    }
+
+    /// A sink for bytes, like `std::io::Write`.
+    pub trait Write {
+        /// Write some bytes from `buf`, returning how many were written.
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+        /// Flush any buffered bytes to their destination.
+        fn flush(&mut self) -> Result<()>;
+
+        /// Write all of `buf`, retrying until it has been accepted.
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.write(buf) {
+                    Ok(0) => return Err(Error::new(ErrorKind::WriteZero)),
+                    Ok(n) => buf = &buf[n..],
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let n = buf.len().min(self.len());
+            buf[..n].copy_from_slice(&self[..n]);
+            *self = &self[n..];
+            Ok(n)
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+}
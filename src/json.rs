@@ -0,0 +1,30 @@
+//! A language-agnostic JSON format for a program and its initial data tape.
+use crate::error::SBrainError;
+use crate::MData;
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct JsonProgram {
+    code: Vec<u8>,
+    data: Vec<MData>,
+}
+
+/// Encode a program tape and a data tape as compact JSON of the form
+/// `{"code":[...],"data":[...]}`, with each tape as a flat array of numbers.
+pub fn to_json(code: &[u8], data: &[MData]) -> String {
+    let program = JsonProgram {
+        code: code.to_vec(),
+        data: data.to_vec(),
+    };
+    serde_json::to_string(&program).expect("Serializing integer arrays cannot fail")
+}
+
+/// Decode a program tape and a data tape from JSON produced by `to_json`. Fails with
+/// `SBrainError::Json` if the JSON is malformed, is not of that form, or holds a number too large
+/// for an opcode or a cell.
+pub fn from_json(json: &str) -> Result<(Vec<u8>, Vec<MData>), SBrainError> {
+    let program: JsonProgram = serde_json::from_str(json)?;
+    Ok((program.code, program.data))
+}
@@ -9,6 +9,8 @@
 //!
 //! ```
 //! # use sbrain::*;
+//! # #[cfg(feature = "std")]
+//! # fn main() {
 //! let program = source_to_tape(",[.,]");
 //! let mut input = make_input_vec(b"Hello, world!");
 //! let mut output = make_output_vec();
@@ -18,34 +20,153 @@
 //!
 //! let output = output.into_inner();
 //! assert_eq!(&output, b"Hello, world!")
+//! # }
+//! # #[cfg(not(feature = "std"))]
+//! # fn main() {}
 //! ```
+//!
+//! ## Features
+//!
+//! * `std` (default): use `std::io` for input and output and provide the helpers which use
+//!   standard in and standard out. Without it, the crate is `no_std` (but needs `alloc`), and
+//!   machines read and write through the minimal traits in the `io` module.
+//! * `serde`: save and load `ReplayCase`s and `VmSnapshot`s as JSON, and convert programs to and
+//!   from JSON with `to_json` and `from_json`. Implies `std`.
+//! * `jit`: compile programs which run for a long time to native code with Cranelift; see
+//!   `SBrainVM::set_jit`. Implies `std`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
+// Only `no_std` builds get `core` at the crate root for free
+#[cfg(feature = "std")]
+extern crate core;
+
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
 
+#[cfg(feature = "jit")]
+extern crate cranelift_codegen;
+#[cfg(feature = "jit")]
+extern crate cranelift_frontend;
+#[cfg(feature = "jit")]
+extern crate cranelift_jit;
+#[cfg(feature = "jit")]
+extern crate cranelift_module;
+
+mod analysis;
+mod cell;
+mod disasm;
+mod error;
+mod eval;
+pub mod gp;
+pub mod io;
+#[cfg(feature = "serde")]
+mod json;
+mod lockstep;
 mod machine;
+mod program;
+mod replay;
+mod rng;
 mod source;
 pub mod specification;
+#[cfg(feature = "std")]
+mod stdin;
 mod tapes;
+mod wasm;
 
+pub use analysis::{
+    can_halt, matching_bracket, program_metrics, scan_forward, verify, OpcodeClass,
+    ProgramMetrics, Reachability, VerifyError, OPCODE_CLASSES,
+};
+pub use cell::Cell;
+pub use disasm::{disassemble, tape_to_source};
+pub use error::SBrainError;
+pub use eval::{evaluate, EvalResult};
+#[cfg(feature = "serde")]
+pub use json::{from_json, to_json};
+pub use lockstep::evaluate_lockstep;
 pub use machine::*;
-pub use source::source_to_tape;
-pub use tapes::{make_input_vec, make_output_vec, tape_to_string};
-
-use std::io;
+pub use program::Program;
+pub use replay::ReplayCase;
+pub use source::{
+    compile, compile_limited, compile_many, compile_reader, parse, source_to_tape,
+    source_to_tape_with_map, CompileError, Diagnostic, SourceMap, SourcePosition, Transliterator,
+    DEFAULT_SEPARATOR,
+};
+#[cfg(feature = "std")]
+pub use source::compile_file;
+pub use tapes::{narrow_data, tape_to_string, widen_data, NarrowError};
+pub use wasm::to_wasm;
+#[cfg(feature = "std")]
+pub use tapes::{make_input_vec, make_output_vec};
 
-/// The type of a data cell
+/// The type of a data cell, unless a machine is built with another `Cell` type.
 pub type MData = u8;
 /// The type of a pointer to a cell.
 pub type MAddr = u16;
+/// The length of the longest program a machine can load.
+pub const MAX_PROGRAM_LEN: usize = 65536;
 
+#[cfg(feature = "std")]
 /// Converts the given source code to a SBrain executable and runs it, taking input from stdin and doing output on stdout.
 ///
 /// # Panics
 /// Panics if there is an I/O error with standard in or standard out.
 pub fn simple_run(source: &str) -> u8 {
+    run_stdio(source, None).expect("Program did not terminate")
+}
+
+#[cfg(feature = "std")]
+/// Like `simple_run`, but runs for at most `cycles` cycles. Returns `Some(code)` if the program
+/// halted and `None` if it ran out of cycles first.
+///
+/// # Panics
+/// Panics if there is an I/O error with standard in or standard out.
+pub fn simple_run_limited(source: &str, cycles: u32) -> Option<u8> {
+    run_stdio(source, Some(cycles))
+}
+
+#[cfg(feature = "std")]
+/// Like `simple_run_limited`, but never blocks forever waiting for input: if a read from stdin
+/// waits longer than `wait` for input, it reads 0 as if stdin were at EOF. An empty or closed
+/// stdin reads as EOF right away. Unlike `simple_run`, this is safe to use in automated harnesses
+/// where stdin may be an idle terminal or a pipe that is never closed. `cycles` may be None to
+/// run until the program halts.
+///
+/// Stdin is read by a background thread, which is started on the first call and keeps running
+/// until stdin is closed. Input it has read but no program has consumed is kept for the next call.
+///
+/// # Panics
+/// Panics if there is an I/O error with standard out.
+pub fn simple_run_with_timeout(
+    source: &str,
+    cycles: Option<u32>,
+    wait: std::time::Duration,
+) -> Option<u8> {
+    let program = source_to_tape(source);
+    SBrainVM::new(
+        Some(&mut stdin::TimeoutStdin::new(wait)),
+        Some(&mut io::stdout()),
+        &program,
+    )
+    .expect("Could not build machine")
+    .run(cycles)
+    .expect("Unable to run program")
+    .1
+    .exit_code()
+}
+
+#[cfg(feature = "std")]
+fn run_stdio(source: &str, cycles: Option<u32>) -> Option<u8> {
     let program = source_to_tape(source);
     SBrainVM::new(Some(&mut io::stdin()), Some(&mut io::stdout()), &program)
         .expect("Could not build machine")
-        .run(None)
+        .run(cycles)
         .expect("Unable to run program")
         .1
-        .expect("Program did not terminate")
+        .exit_code()
 }
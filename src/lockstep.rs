@@ -0,0 +1,198 @@
+//! Running many programs side by side, in lockstep.
+use crate::analysis::matching_bracket;
+use crate::error::SBrainError;
+use crate::eval::EvalResult;
+use crate::{HaltReason, MAddr, MData, MAX_PROGRAM_LEN};
+use alloc::vec::Vec;
+
+/// How many instructions of one program run before moving on to the next. Running a few at a
+/// time keeps each program's state in registers, without letting any program get far ahead.
+const ROUND: u32 = 256;
+
+/// The state of every program in a batch, kept field by field rather than machine by machine, so
+/// that each round over the batch works through a few dense arrays.
+struct Lanes {
+    /// Every program, one after another, each instruction with the address of its matching
+    /// bracket if it is a matched bracket
+    code: Vec<(u8, Option<MAddr>)>,
+    /// Where each program starts in `code`, and its length
+    starts: Vec<usize>,
+    lens: Vec<usize>,
+    /// How many cells of each data tape are kept; a power of two
+    window: usize,
+    /// Every data tape, one after another
+    tapes: Vec<MData>,
+    data_p: Vec<MAddr>,
+    inst_p: Vec<MAddr>,
+    auxi_r: Vec<MData>,
+    stacks: Vec<Vec<MData>>,
+    /// How much of the input each program has read
+    input_pos: Vec<usize>,
+    /// Whether each program has selected an output channel other than 0
+    muted: Vec<bool>,
+    outputs: Vec<Vec<MData>>,
+}
+
+impl Lanes {
+    fn new(programs: &[&[u8]], limit: u32) -> Lanes {
+        let count = programs.len();
+        let mut code = Vec::with_capacity(programs.iter().map(|p| p.len()).sum());
+        let mut starts = Vec::with_capacity(count);
+        for program in programs {
+            starts.push(code.len());
+            code.extend(
+                (0..program.len())
+                    .map(|addr| (program[addr], matching_bracket(program, addr as MAddr))),
+            );
+        }
+        // A program can only reach cells within `limit` of cell 0, on either side, so a window
+        // of more than twice that many cells holds every cell it can touch, with none sharing a
+        // slot. Since the window divides the tape length, addresses wrap within it unchanged.
+        let reach = 2 * u64::from(limit.max(1)) + 1;
+        let window = reach.next_power_of_two().min(1 << 16) as usize;
+        Lanes {
+            code,
+            starts,
+            lens: programs.iter().map(|p| p.len()).collect(),
+            window,
+            tapes: vec![0; window * count],
+            data_p: vec![0; count],
+            inst_p: vec![0; count],
+            auxi_r: vec![0; count],
+            stacks: vec![Vec::new(); count],
+            input_pos: vec![0; count],
+            muted: vec![false; count],
+            outputs: vec![Vec::new(); count],
+        }
+    }
+
+    /// Run up to `steps` instructions of the program in `lane`. If it halts, return the number of
+    /// cycles it ran first and its exit code.
+    fn run(&mut self, lane: usize, steps: u32, input: &[MData]) -> Option<(u32, u8)> {
+        let code = &self.code[self.starts[lane]..self.starts[lane] + self.lens[lane]];
+        let tape = &mut self.tapes[lane * self.window..(lane + 1) * self.window];
+        let mask = self.window - 1;
+        let stack = &mut self.stacks[lane];
+        let output = &mut self.outputs[lane];
+        let mut ip = self.inst_p[lane];
+        let mut dp = self.data_p[lane];
+        let mut aux = self.auxi_r[lane];
+        let mut input_pos = self.input_pos[lane];
+        let mut muted = self.muted[lane];
+
+        let mut halted = None;
+        for ran in 0..steps {
+            // Past the end of the program, the tape is zeroed
+            let (opcode, target) = code.get(ip as usize).cloned().unwrap_or((0, None));
+            let cell = &mut tape[dp as usize & mask];
+            let value = *cell;
+            match opcode {
+                0 => dp = dp.wrapping_sub(1),
+                1 => dp = dp.wrapping_add(1),
+                2 => *cell = value.wrapping_sub(1),
+                3 => *cell = value.wrapping_add(1),
+                // An unmatched bracket doesn't jump
+                4 | 5 if (value == 0) == (opcode == 4) => ip = target.unwrap_or(ip),
+                6 if !muted => output.push(value),
+                7 => {
+                    // Past the end of the input, reads read 0
+                    *cell = match input.get(input_pos) {
+                        Some(&byte) => {
+                            input_pos += 1;
+                            byte
+                        }
+                        None => 0,
+                    };
+                }
+                8 => stack.push(value),
+                9 => *cell = stack.pop().unwrap_or(0),
+                10 => aux = value,
+                11 => *cell = aux,
+                12 => aux = 0,
+                13 => aux = !aux,
+                14 => aux &= value,
+                15 => {
+                    halted = Some((ran, aux));
+                    break;
+                }
+                16 => aux = aux.rotate_left(1),
+                17 => aux = aux.rotate_right(1),
+                // Stepping past the end of the tape wraps this around to 0
+                19 => ip = MAddr::MAX,
+                22 => *cell = (value == aux) as MData,
+                23 => *cell = (value < aux) as MData,
+                24 => *cell = (aux < value) as MData,
+                25 if value == 0 => ip = ip.wrapping_add(1),
+                26 => muted = value != 0,
+                _ => {}
+            }
+            ip = ip.wrapping_add(1);
+        }
+
+        self.inst_p[lane] = ip;
+        self.data_p[lane] = dp;
+        self.auxi_r[lane] = aux;
+        self.input_pos[lane] = input_pos;
+        self.muted[lane] = muted;
+        halted
+    }
+}
+
+/// Experimental: run every one of `programs` on `input` for at most `limit` cycles, as `evaluate`
+/// would one at a time, and collect their results in the same order.
+///
+/// Rather than building a machine for each program, this steps all of them together, a round of a
+/// few hundred instructions of each at a time, over state kept in shared arrays. Since no program
+/// can reach further than `limit` cells from where it started, each one only gets a data tape big
+/// enough for that, which makes building the batch far cheaper than building a machine per
+/// program. This suits the large populations of short-lived programs of genetic programming, where
+/// building machines otherwise dominates.
+///
+/// The results are exactly those of `evaluate`, with a machine as the specification describes it.
+/// Fails if any program is longer than `MAX_PROGRAM_LEN`.
+pub fn evaluate_lockstep(
+    programs: &[&[u8]],
+    input: &[MData],
+    limit: u32,
+) -> Result<Vec<EvalResult>, SBrainError> {
+    if let Some(program) = programs.iter().find(|p| p.len() > MAX_PROGRAM_LEN) {
+        return Err(SBrainError::ProgramTooLong {
+            len: program.len(),
+            max: MAX_PROGRAM_LEN,
+        });
+    }
+    let mut lanes = Lanes::new(programs, limit);
+    let mut stopped = vec![(0, HaltReason::CycleLimit); programs.len()];
+    let mut running: Vec<usize> = (0..programs.len()).collect();
+    let mut cycles: u32 = 0;
+    while !running.is_empty() {
+        // Like `SBrainVM::run`, this runs at least one cycle, even with a limit of 0
+        let steps = limit.saturating_sub(cycles).clamp(1, ROUND);
+        running.retain(|&lane| match lanes.run(lane, steps, input) {
+            Some((ran, code)) => {
+                stopped[lane] = (cycles + ran, HaltReason::Halted(code));
+                false
+            }
+            None => true,
+        });
+        cycles += steps;
+        if cycles >= limit {
+            break;
+        }
+    }
+    for lane in running {
+        stopped[lane] = (cycles, HaltReason::CycleLimit);
+    }
+
+    Ok(stopped
+        .into_iter()
+        .zip(lanes.outputs)
+        .map(|((cycles, halt_reason), output)| EvalResult {
+            output,
+            cycles,
+            halt_reason,
+            halted: halt_reason.exit_code().is_some(),
+            exit_code: halt_reason.exit_code(),
+        })
+        .collect())
+}
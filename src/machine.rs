@@ -1,237 +1,1740 @@
 //! The implementation of the SBrain VM.
-use crate::{MAddr, MData};
-use std::io;
-use std::io::{Read, Write};
-use std::u16::MAX as u16MAX;
+use crate::analysis::matching_bracket;
+use crate::cell::Cell;
+use crate::error::SBrainError;
+use crate::rng::SplitMix64;
+use crate::tapes::tape_to_string;
+use crate::io;
+use crate::io::{Read, Write};
+use crate::{MAddr, MData, MAX_PROGRAM_LEN};
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::fmt;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+mod address;
+mod breakpoints;
+mod builder;
+#[cfg(feature = "std")]
+mod cancel;
+mod decode;
+mod diff;
+mod fusion;
+#[cfg(feature = "jit")]
+mod jit;
+mod loop_detect;
+mod observer;
+mod pool;
+mod report;
+mod snapshot;
+mod stats;
+mod undo;
+
+pub use self::address::{AddressWidth, GROWTH_SEGMENT};
+pub use self::breakpoints::Watch;
+pub use self::builder::VmBuilder;
+#[cfg(feature = "std")]
+pub use self::cancel::CancelToken;
+pub use self::diff::{CellChange, StateDiff};
+pub use self::observer::{ControlFlow, Observer, OutputEvent};
+pub use self::pool::VmPool;
+pub use self::report::RunReport;
+pub use self::snapshot::VmSnapshot;
+pub use self::stats::RunStats;
+pub use self::undo::UndoError;
+use self::decode::Instruction;
+
+/// The reason a call to `SBrainVM::run` stopped executing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    /// The program executed a halt instruction. The exit code is the value of `auxi_r`, or its
+    /// low byte for cells wider than `MData`.
+    Halted(u8),
+    /// The program ran for the requested number of cycles without halting.
+    CycleLimit,
+    /// In `BracketMode::Strict`, the bracket at `addr` has no matching bracket.
+    UnbalancedBracket { addr: MAddr },
+    /// The no-progress heuristic decided the program is stuck.
+    /// See `SBrainVM::set_no_progress_detection`.
+    NoProgress,
+    /// `run_metered` ran out of gas before the next instruction could be paid for.
+    OutOfGas,
+    /// The observer passed to `run_observed` asked the machine to stop.
+    Aborted,
+    /// The program tried to read past the end of its input.
+    /// See `SBrainVM::set_halt_on_exhausted_input`.
+    InputExhausted,
+    /// Execution passed the end of the loaded program, or with strict bounds, the end of the
+    /// exec tape. See `SBrainVM::set_halt_at_program_end` and `SBrainVM::set_strict_bounds`.
+    RanOffTape,
+    /// In `UnderflowMode::Trap`, the program tried to pop from an empty stack.
+    StackUnderflow,
+    /// The program tried to move the data pointer left of cell 0, in `AddressWidth::Growable`
+    /// or with strict bounds. See `SBrainVM::set_strict_bounds`.
+    PointerUnderflow,
+    /// With strict bounds, the program tried to move the data pointer right of the last cell, or
+    /// in `AddressWidth::Growable`, right of the last cell the tape can grow to. See
+    /// `SBrainVM::set_strict_bounds` and `VmConfig::max_data_len`.
+    PointerOverflow,
+    /// The predicate passed to `run_until` returned true.
+    PredicateMet,
+    /// The run took longer than the machine's time limit. See `SBrainVM::set_time_limit`.
+    TimedOut,
+    /// The machine's cancel token was cancelled. See `SBrainVM::set_cancel_token`.
+    Cancelled,
+    /// Loop detection found the machine back in a state it had already been in.
+    /// See `SBrainVM::set_loop_detection`.
+    LoopDetected,
+    /// The next instruction, at `addr`, has a breakpoint. See `SBrainVM::set_breakpoint`.
+    Breakpoint { addr: MAddr },
+    /// The last instruction wrote the data cell at `addr`, which has a watchpoint.
+    /// See `SBrainVM::set_watchpoint`.
+    Watchpoint { addr: MAddr },
+}
+
+impl HaltReason {
+    /// The exit code if the program halted, or None if it was stopped for any other reason.
+    pub fn exit_code(&self) -> Option<u8> {
+        match *self {
+            HaltReason::Halted(code) => Some(code),
+            _ => None,
+        }
+    }
+}
+
+/// How the machine treats a `[` or `]` with no matching bracket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BracketMode {
+    /// An unmatched bracket is a NOP. This is the behavior described by the specification.
+    Lenient,
+    /// Executing an unmatched bracket halts the machine with `HaltReason::UnbalancedBracket`,
+    /// whether or not the jump would have been taken.
+    Strict,
+}
+
+/// How the machine finds its way back to the start of a loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopMode {
+    /// `]` scans backward through the program for its matching `[`.
+    Rescan,
+    /// `[` pushes its address onto a jump stack when entering a loop, `]` jumps back to the
+    /// address on top of the stack while looping, and pops it when leaving the loop. This
+    /// matches the jump-stack wording of older versions of the specification. `[` still scans
+    /// forward to skip a loop, since nothing has been pushed for it.
+    JumpStack,
+}
+
+/// How the cell increment and decrement instructions behave at the limits of a cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithMode {
+    /// Values wrap around, so the maximum plus one is zero. This is the behavior described by the
+    /// specification.
+    Wrapping,
+    /// Values stick at zero and the maximum. This deviates from the specification.
+    Saturating,
+}
+
+/// How `}` behaves when the data stack is empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnderflowMode {
+    /// Popping an empty stack produces 0, as if the stack were filled with zeroes. This is
+    /// consistent with the zero-filled stack described by the specification.
+    Zero,
+    /// Popping an empty stack halts the machine with `HaltReason::StackUnderflow`, leaving the
+    /// cell unchanged. This catches programs which pop more than they push.
+    Trap,
+}
+
+/// How `.` encodes a cell as bytes of output.
+///
+/// Every multi-byte encoding names its byte order; there is deliberately no native-endian
+/// encoding, so a program's output is byte-for-byte the same on every platform. The default byte
+/// order, used by `OutputEncoding::wide`, is little-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputEncoding {
+    /// One byte per cell. This is the behavior described by the specification.
+    #[default]
+    Byte,
+    /// The cell's value as the given number of bytes (at most 8), least significant first.
+    /// Values are zero-extended to the width, or truncated if the width is smaller than a cell.
+    LittleEndian(usize),
+    /// The cell's value as the given number of bytes (at most 8), most significant first.
+    /// Values are zero-extended to the width, or truncated if the width is smaller than a cell.
+    BigEndian(usize),
+}
+
+impl OutputEncoding {
+    /// A multi-byte encoding of the given width in the default byte order, little-endian.
+    pub fn wide(width: usize) -> OutputEncoding {
+        OutputEncoding::LittleEndian(width)
+    }
+
+    /// The number of bytes written for each cell output.
+    pub fn width(&self) -> usize {
+        match *self {
+            OutputEncoding::Byte => 1,
+            OutputEncoding::LittleEndian(width) | OutputEncoding::BigEndian(width) => width.min(8),
+        }
+    }
+}
+
+/// What the data tape is filled with by `SBrainVM::init_data`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitPattern {
+    /// Every cell is zero. This is the behavior described by the specification.
+    Zero,
+    /// Every cell holds the given value.
+    Fill(MData),
+    /// Every cell holds a pseudo-random value. The same seed always gives the same tape.
+    Random { seed: u64 },
+}
+
+/// Configuration for the no-progress heuristic. See `SBrainVM::set_no_progress_detection`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoProgressConfig {
+    /// Take a fingerprint of the machine state every `interval` cycles.
+    pub interval: u32,
+    /// Halt once the same fingerprint has been seen this many times with no output in between.
+    pub repeats: u32,
+}
+
+impl Default for NoProgressConfig {
+    fn default() -> NoProgressConfig {
+        NoProgressConfig {
+            interval: 64,
+            repeats: 3,
+        }
+    }
+}
+
+/// How much space is reserved for the data stack by default. This is only a performance hint;
+/// the stack can always grow past it.
+const DEFAULT_STACK_CAPACITY: usize = 256;
+
+/// The most cells an `AddressWidth::Growable` data tape grows to by default.
+const DEFAULT_MAX_DATA_LEN: usize = 1 << 24;
+
+/// The sizes of a machine's tapes and stack, for `SBrainVM::new_with_config`. The default is the
+/// machine the specification describes, with 65536 cells on each tape.
+///
+/// Smaller tapes make a machine cheaper to build and keep, for example to run many at once. The
+/// data pointer wraps around at the end of a smaller data tape just as at the end of a full one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmConfig {
+    /// The number of cells on the data tape, from 1 to 65536.
+    pub data_len: usize,
+    /// The number of instructions the exec tape holds, from 1 to `MAX_PROGRAM_LEN`. This is the
+    /// longest program the machine can load.
+    pub exec_len: usize,
+    /// How many values to reserve room for on the data stack. This affects only performance; see
+    /// `SBrainVM::with_stack_capacity`.
+    pub stack_capacity: usize,
+    /// The most cells an `AddressWidth::Growable` data tape grows to, 16777216 by default. A
+    /// program which tries to move right of the last of them halts with
+    /// `HaltReason::PointerOverflow`, rather than using ever more memory. Other address widths
+    /// ignore this.
+    pub max_data_len: usize,
+}
+
+impl Default for VmConfig {
+    fn default() -> VmConfig {
+        VmConfig {
+            data_len: 1 << 16,
+            exec_len: MAX_PROGRAM_LEN,
+            stack_capacity: DEFAULT_STACK_CAPACITY,
+            max_data_len: DEFAULT_MAX_DATA_LEN,
+        }
+    }
+}
+
+/// Check that both tape lengths in `config` are in range.
+fn check_config(config: &VmConfig) -> Result<(), SBrainError> {
+    for &len in &[config.data_len, config.exec_len] {
+        if len == 0 || len > 1 << 16 {
+            return Err(SBrainError::TapeLength { len });
+        }
+    }
+    Ok(())
+}
+
+/// The most bytes read from an input `Read` at once.
+const INPUT_CHUNK: usize = 4096;
+/// How many bytes of output are held back before they are written to an output `Write`.
+const OUTPUT_CHUNK: usize = 4096;
+
+/// How many cycles run between checks of the time limit.
+#[cfg(feature = "std")]
+const DEADLINE_INTERVAL: u32 = 4096;
+
+/// How many cells on either side of `data_p` are shown when formatting a machine.
+const WINDOW_RADIUS: MAddr = 8;
+/// How many values from the top of the stack are shown when formatting a machine.
+const STACK_SHOWN: usize = 8;
+
+/// A cheap summary of the machine state: inst_p, data_p, auxi_r, the top of the stack, and the
+/// current cell.
+type Fingerprint<C> = (MAddr, u32, C, Option<C>, C);
+
+/// Where the machine's input comes from.
+enum InputTape<'a> {
+    /// Input is read from a borrowed reader
+    Reader(&'a mut dyn Read),
+    /// Input is pulled from an iterator
+    Iter(Box<dyn Iterator<Item = MData> + 'a>),
+}
+
+/// Where the machine's output goes.
+enum OutputTape<'a> {
+    /// Output is written to a borrowed writer
+    Writer(&'a mut dyn Write),
+    /// Output is collected in a buffer owned by the machine
+    Buffer(Vec<MData>),
+    /// Output is written to whichever of several writers is selected
+    Channels(Vec<Box<dyn Write + 'a>>),
+    /// Output is passed to a function a byte at a time
+    Callback(Box<dyn FnMut(MData) + 'a>),
+}
+
+impl<'a> OutputTape<'a> {
+    /// The writer for the given output channel, if there is one. A machine with a single writer
+    /// has only channel 0.
+    fn writer(&mut self, channel: usize) -> Option<&mut (dyn Write + 'a)> {
+        match *self {
+            OutputTape::Writer(ref mut w) if channel == 0 => Some(&mut **w),
+            OutputTape::Channels(ref mut ws) => ws.get_mut(channel).map(|w| &mut **w),
+            _ => None,
+        }
+    }
+
+    /// Whether output to the given channel goes anywhere, rather than being discarded.
+    fn has_channel(&self, channel: usize) -> bool {
+        match *self {
+            OutputTape::Channels(ref ws) => channel < ws.len(),
+            _ => channel == 0,
+        }
+    }
+}
 
 /// A virtual machine modelling the SBrain Turing machine.
-/// This machine implements the specification relatively strictly, providing exactly 2^16 (65536)
-/// data and instruction cells. Thus, all pointers are 16 bits and all data is 8 bits.
+/// This machine implements the specification relatively strictly, providing by default exactly
+/// 2^16 (65536) data and instruction cells (see `VmConfig` for smaller tapes). Thus, all pointers
+/// are 16 bits and data is 8 bits, unless another `Cell` type is chosen with `new_wide`.
 /// The main deviation from the minimum specification is the jump stack, which is indefinitely
 /// expandable.
-
-pub struct SBrainVM<'a> {
+pub struct SBrainVM<'a, C: Cell = MData> {
     // Data containers
     /// The data tape contains the primary data on which the program will operate
     /// 16-bit addresses with a single dead address
-    data_tape: [MData; 65536],
-    /// The data stack allows the position-independent storage of data
-    data_stack: Vec<MData>,
+    data_tape: Vec<C>,
+    /// Cells past the data tape which have been written, with a wider `AddressWidth`
+    far_cells: BTreeMap<u32, C>,
+    /// The data stack allows the position-independent storage of data.
+    /// It starts empty; popping an empty stack produces 0, which is indistinguishable from the
+    /// zero-filled stack the specification describes.
+    data_stack: Vec<C>,
     /// Auxiliary register (auxi_r)
-    auxi_r: MData,
+    auxi_r: C,
 
     // Machine Internals
     /// The instruction tape contains instructions. This VM uses the recommended 6-bit binary
-    /// format, but Rust does not have a 6-bit datatype, so u8 is used instead
-    exec_tape: [u8; 65536],
+    /// format, but Rust does not have a 6-bit datatype, so u8 is used instead.
+    /// Only the loaded program, and anything written past its end with opcode 21, is stored; the
+    /// rest of the tape reads as zeroes, so its length is the length of the program.
+    exec_tape: Vec<u8>,
+    /// The number of instructions the exec tape holds
+    exec_len: usize,
     /// Pointer to the current data cell
     data_p: MAddr,
+    /// The bits of the data pointer beyond `data_p`, with a wider `AddressWidth`
+    data_page: MAddr,
     /// Pointer to the current instruction
     inst_p: MAddr,
+    /// Addresses of the loops currently being executed, in `LoopMode::JumpStack`.
+    /// The jump stack pointer, `jump_p`, is its length.
+    jump_stack: Vec<MAddr>,
+    /// Whether the last instruction executed was a halt
+    halted: bool,
+    /// Whether execution has wrapped around from the end of the exec tape to the start
+    wrapped: bool,
 
     // I/O Tapes
-    input_t: Option<&'a mut dyn Read>,
-    output_t: Option<&'a mut dyn Write>,
+    input_t: Option<InputTape<'a>>,
+    /// Bytes read ahead from an `InputTape::Reader` and not yet consumed, from `input_pos` on
+    input_buf: Vec<MData>,
+    input_pos: usize,
+    /// Input supplied with `feed_input`, which is read before `input_t`
+    input_queue: VecDeque<MData>,
+    /// Whether the program has tried to read past the end of its input
+    input_exhausted: bool,
+    output_t: Option<OutputTape<'a>>,
+    /// Output not yet written to the selected channel's writer
+    output_pending: Vec<MData>,
+    /// The output channel `.` writes to
+    channel: usize,
+
+    // Configuration
+    init_pattern: InitPattern,
+    address_width: AddressWidth,
+    /// The most cells a growable data tape grows to
+    max_data_len: usize,
+    bracket_mode: BracketMode,
+    loop_mode: LoopMode,
+    arith_mode: ArithMode,
+    underflow_mode: UnderflowMode,
+    output_encoding: OutputEncoding,
+    /// Whether comparisons treat cells and auxi_r as two's complement signed values
+    signed: bool,
+    no_progress: Option<NoProgressConfig>,
+    /// Whether opcodes 20 and 21 may access the exec tape
+    self_modifying: bool,
+    /// Whether reading past the end of the input halts the machine
+    halt_on_exhausted_input: bool,
+    /// Whether running past the end of the program halts the machine
+    halt_at_program_end: bool,
+    /// Whether pointers crossing the ends of their tapes halt the machine
+    strict_bounds: bool,
+    /// The cycle limit for runs which don't give one
+    cycle_limit: Option<u32>,
+    /// How long each run may take, and when the current run must stop
+    #[cfg(feature = "std")]
+    time_limit: Option<Duration>,
+    #[cfg(feature = "std")]
+    deadline: Option<Instant>,
+    /// Stops the machine when cancelled from outside
+    #[cfg(feature = "std")]
+    cancel: Option<CancelToken>,
+
+    /// Source of randomness for opcode 18; None until seeded, which makes the opcode a NOP
+    rng: Option<SplitMix64>,
+
+    // Bookkeeping
+    stats: RunStats,
+    /// For each address on the exec tape, whether it has been executed; None unless enabled
+    coverage: Option<Vec<bool>>,
+    /// How many times each loop has jumped back, by the address of its `[`; None unless enabled
+    loop_counts: Option<BTreeMap<MAddr, u64>>,
+    /// Addresses on the exec tape to stop at
+    breakpoints: BTreeSet<MAddr>,
+    /// Data cells to stop after writing
+    watchpoints: BTreeMap<MAddr, Watch<C>>,
+    /// Whether the last instruction wrote to the data tape
+    cell_written: bool,
+    /// Fingerprints seen since the last output, for the no-progress heuristic
+    fingerprints: BTreeMap<Fingerprint<C>, u32>,
+    /// How often to hash the whole state for loop detection; None unless enabled
+    loop_detection: Option<u32>,
+    /// Hashes of the states seen since the last input, for loop detection
+    seen_states: BTreeSet<u64>,
+    /// The most recent instructions' effects, for `undo_last`; None unless enabled
+    undo: Option<undo::UndoLog<C>>,
+    /// Whether `run` may use fused operations, and the program compiled into them; None until
+    /// needed, and again whenever the exec tape changes
+    fusion: bool,
+    bytecode: Option<Vec<fusion::FusedOp>>,
+    /// How many cycles the program runs before it is compiled to native code, how many it has
+    /// run so far, and the compiled code
+    #[cfg(feature = "jit")]
+    jit_threshold: Option<u64>,
+    #[cfg(feature = "jit")]
+    jit_warmup: u64,
+    #[cfg(feature = "jit")]
+    jit: jit::Jit,
 }
 
 impl<'a> SBrainVM<'a> {
+    /// Start building a machine one option at a time. See `VmBuilder`.
+    pub fn builder() -> VmBuilder<'a> {
+        VmBuilder::new()
+    }
+
     /// Return a new SBrainVM, with no data in any tapes.
-    /// If given a `None` `input`, all reads read 0.
+    /// If given a `None` `input`, all reads read 0. Input is read ahead in chunks rather than a
+    /// byte at a time, so the machine may consume more of `input` than the program reads.
     /// If given a `None` `output`, all writes are discarded.
+    /// The program may be given as a `&Program`, checked ahead of time, as well as a plain tape.
     pub fn new(
         input: Option<&'a mut dyn Read>,
         output: Option<&'a mut dyn Write>,
         program: &[u8],
-    ) -> Result<SBrainVM<'a>, String> {
-        let mut new = SBrainVM {
-            data_tape: [0; 65536],
-            data_stack: vec![0; 256],
-            auxi_r: 0,
-            exec_tape: [0; 65536],
+    ) -> Result<SBrainVM<'a>, SBrainError> {
+        SBrainVM::new_with_config(input, output, program, VmConfig::default())
+    }
+
+    /// Return a new SBrainVM like `new`, but with the tape and stack sizes given by `config`.
+    /// Fails if either tape length is out of range, or if the program does not fit on the exec
+    /// tape.
+    pub fn new_with_config(
+        input: Option<&'a mut dyn Read>,
+        output: Option<&'a mut dyn Write>,
+        program: &[u8],
+        config: VmConfig,
+    ) -> Result<SBrainVM<'a>, SBrainError> {
+        SBrainVM::new_wide(input, output, program, config)
+    }
+
+    /// Return a new SBrainVM which collects its output in an internal buffer instead of writing
+    /// it to a `Write`. The output can be read with `output` or `output_string`.
+    /// If given a `None` `input`, all reads read 0.
+    ///
+    /// ```
+    /// # use sbrain::*;
+    /// let program = source_to_tape(",[.,]");
+    /// let mut input: &[u8] = b"Hello, world!";
+    /// let mut machine = SBrainVM::new_buffered(Some(&mut input), &program)
+    ///     .expect("Could not build machine");
+    /// machine.run(Some(1000)).expect("I/O failed");
+    ///
+    /// assert_eq!(machine.output_string(), "Hello, world!");
+    /// ```
+    pub fn new_buffered(
+        input: Option<&'a mut dyn Read>,
+        program: &[u8],
+    ) -> Result<SBrainVM<'a>, SBrainError> {
+        let mut new = SBrainVM::new(input, None, program)?;
+        new.output_t = Some(OutputTape::Buffer(Vec::new()));
+        Ok(new)
+    }
+
+    /// Return a new SBrainVM with several output channels, numbered from 0 in the order of
+    /// `outputs`. `.` writes to the selected channel, which starts as channel 0 and is changed by
+    /// `$`; output to a channel with no writer is discarded. Machines built any other way have a
+    /// single channel, 0.
+    /// If given a `None` `input`, all reads read 0.
+    pub fn new_multi_output(
+        input: Option<&'a mut dyn Read>,
+        outputs: Vec<Box<dyn Write + 'a>>,
+        program: &[u8],
+    ) -> Result<SBrainVM<'a>, SBrainError> {
+        let mut new = SBrainVM::new(input, None, program)?;
+        new.output_t = Some(OutputTape::Channels(outputs));
+        Ok(new)
+    }
+
+    /// Return a new SBrainVM which pulls its input from an iterator instead of a `Read`.
+    /// Once the iterator is exhausted, all reads read 0.
+    /// If given a `None` `output`, all writes are discarded.
+    pub fn new_from_iter<I>(
+        input: I,
+        output: Option<&'a mut dyn Write>,
+        program: &[u8],
+    ) -> Result<SBrainVM<'a>, SBrainError>
+    where
+        I: IntoIterator<Item = MData>,
+        I::IntoIter: 'a,
+    {
+        let mut new = SBrainVM::new(None, output, program)?;
+        new.input_t = Some(InputTape::Iter(Box::new(input.into_iter())));
+        Ok(new)
+    }
+}
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// Return a new SBrainVM like `new_with_config`, but whose cells are of type `C`, for example
+    /// `SBrainVM::<u32>::new_wide` for the 32-bit cells of the specification. Input and output
+    /// are still bytes: `,` reads a byte into the low bits of a cell, and `.` writes a cell as
+    /// set by `set_output_encoding`.
+    pub fn new_wide(
+        input: Option<&'a mut dyn Read>,
+        output: Option<&'a mut dyn Write>,
+        program: &[u8],
+        config: VmConfig,
+    ) -> Result<SBrainVM<'a, C>, SBrainError> {
+        check_config(&config)?;
+        let mut new = SBrainVM::from_tapes(
+            input,
+            output,
+            vec![C::default(); config.data_len],
+            Vec::new(),
+            config.exec_len,
+            Vec::with_capacity(config.stack_capacity),
+        );
+        new.max_data_len = config.max_data_len;
+        new.load_program(program)?;
+        Ok(new)
+    }
+
+    /// Build a machine in its initial state around the given tapes and stack, which must be
+    /// zeroed and empty, with no program loaded and room for `exec_len` instructions.
+    fn from_tapes(
+        input: Option<&'a mut dyn Read>,
+        output: Option<&'a mut dyn Write>,
+        data_tape: Vec<C>,
+        exec_tape: Vec<u8>,
+        exec_len: usize,
+        data_stack: Vec<C>,
+    ) -> SBrainVM<'a, C> {
+        SBrainVM {
+            data_tape,
+            far_cells: BTreeMap::new(),
+            data_stack,
+            auxi_r: C::default(),
+            exec_tape,
+            exec_len,
             data_p: 0,
+            data_page: 0,
             inst_p: 0,
+            jump_stack: Vec::new(),
+            halted: false,
+            wrapped: false,
+
+            input_t: input.map(InputTape::Reader),
+            input_buf: Vec::new(),
+            input_pos: 0,
+            input_queue: VecDeque::new(),
+            input_exhausted: false,
+            output_t: output.map(OutputTape::Writer),
+            output_pending: Vec::new(),
+            channel: 0,
+
+            init_pattern: InitPattern::Zero,
+            address_width: AddressWidth::Bits16,
+            max_data_len: DEFAULT_MAX_DATA_LEN,
+            bracket_mode: BracketMode::Lenient,
+            loop_mode: LoopMode::Rescan,
+            arith_mode: ArithMode::Wrapping,
+            underflow_mode: UnderflowMode::Zero,
+            output_encoding: OutputEncoding::Byte,
+            signed: false,
+            no_progress: None,
+            self_modifying: false,
+            halt_on_exhausted_input: false,
+            halt_at_program_end: false,
+            strict_bounds: false,
+            cycle_limit: None,
+            #[cfg(feature = "std")]
+            time_limit: None,
+            #[cfg(feature = "std")]
+            deadline: None,
+            #[cfg(feature = "std")]
+            cancel: None,
+            rng: None,
+
+            stats: RunStats::default(),
+            coverage: None,
+            loop_counts: None,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            cell_written: false,
+            fingerprints: BTreeMap::new(),
+            loop_detection: None,
+            seen_states: BTreeSet::new(),
+            undo: None,
+            fusion: true,
+            bytecode: None,
+            #[cfg(feature = "jit")]
+            jit_threshold: None,
+            #[cfg(feature = "jit")]
+            jit_warmup: 0,
+            #[cfg(feature = "jit")]
+            jit: jit::Jit::Cold,
+        }
+    }
+
+    /// The output collected so far by a machine built with `new_buffered`.
+    /// Machines which write to a `Write` or discard their output return an empty slice.
+    pub fn output(&self) -> &[MData] {
+        match self.output_t {
+            Some(OutputTape::Buffer(ref buffer)) => buffer,
+            _ => &[],
+        }
+    }
+
+    /// The output collected so far, lossily decoded as UTF-8 in the same way as `tape_to_string`.
+    /// Machines which write to a `Write` or discard their output return an empty string.
+    pub fn output_string(&self) -> Cow<'_, str> {
+        tape_to_string(self.output())
+    }
 
-            input_t: input,
-            output_t: output,
+    /// Reserve room for at least `capacity` values on the data stack. This affects only
+    /// performance: the stack always starts empty and can grow past its capacity.
+    pub fn with_stack_capacity(mut self, capacity: usize) -> SBrainVM<'a, C> {
+        self.data_stack.reserve(capacity);
+        self
+    }
+
+    /// Start with the data pointer at `addr` instead of 0, for example to reconstruct a machine
+    /// from a snapshot along with `load_data_at`. On a full data tape every `MAddr` is a cell, so
+    /// no address can be out of bounds; on a smaller one, addresses past the end wrap around.
+    pub fn data_pointer(mut self, addr: MAddr) -> SBrainVM<'a, C> {
+        self.set_data_address(u32::from(addr));
+        self
+    }
+
+    /// Start executing at `addr` instead of 0. On a full exec tape every `MAddr` is an address,
+    /// so no address can be out of bounds; on a smaller one, addresses past the end wrap around.
+    /// An address past the end of the program runs the zeroed rest of the tape, as running off
+    /// the end does.
+    pub fn instruction_pointer(mut self, addr: MAddr) -> SBrainVM<'a, C> {
+        self.inst_p = (addr as usize % self.exec_len) as MAddr;
+        self
+    }
+
+    /// Start with `value` in the auxiliary register instead of 0.
+    pub fn aux(mut self, value: C) -> SBrainVM<'a, C> {
+        self.auxi_r = value;
+        self
+    }
+
+    /// Pass the machine's output to `f` a byte at a time, as it is produced, instead of writing
+    /// it to a `Write`. This replaces any output the machine was constructed with. As with a
+    /// single `Write`, output to channels other than 0 is discarded.
+    pub fn output_fn(mut self, f: impl FnMut(MData) + 'a) -> SBrainVM<'a, C> {
+        self.output_t = Some(OutputTape::Callback(Box::new(f)));
+        self
+    }
+
+    /// Make an independent copy of the machine, with its own tapes, stack, registers, pending
+    /// input, output and configuration, for example to start many machines from one configured
+    /// template. Only machines that own their I/O can be copied: those without input (other than
+    /// input queued with `feed_input`) and with either no output or the buffer of
+    /// `new_buffered`. For a machine borrowing a `Read` or `Write`, reading from an iterator, or
+    /// writing to a function, this returns None.
+    pub fn try_clone(&self) -> Option<SBrainVM<'a, C>> {
+        if self.input_t.is_some() {
+            return None;
+        }
+        let output_t = match self.output_t {
+            Some(OutputTape::Buffer(ref buffer)) => Some(OutputTape::Buffer(buffer.clone())),
+            Some(OutputTape::Writer(_))
+            | Some(OutputTape::Channels(_))
+            | Some(OutputTape::Callback(_)) => return None,
+            None => None,
         };
-        new.load_program(program)?;
-        Ok(new)
+        Some(SBrainVM {
+            data_tape: self.data_tape.clone(),
+            far_cells: self.far_cells.clone(),
+            data_stack: self.data_stack.clone(),
+            auxi_r: self.auxi_r,
+            exec_tape: self.exec_tape.clone(),
+            exec_len: self.exec_len,
+            data_p: self.data_p,
+            data_page: self.data_page,
+            inst_p: self.inst_p,
+            jump_stack: self.jump_stack.clone(),
+            halted: self.halted,
+            wrapped: self.wrapped,
+
+            input_t: None,
+            input_buf: Vec::new(),
+            input_pos: 0,
+            input_queue: self.input_queue.clone(),
+            input_exhausted: self.input_exhausted,
+            output_t,
+            output_pending: Vec::new(),
+            channel: self.channel,
+
+            init_pattern: self.init_pattern,
+            address_width: self.address_width,
+            max_data_len: self.max_data_len,
+            bracket_mode: self.bracket_mode,
+            loop_mode: self.loop_mode,
+            arith_mode: self.arith_mode,
+            underflow_mode: self.underflow_mode,
+            output_encoding: self.output_encoding,
+            signed: self.signed,
+            no_progress: self.no_progress,
+            self_modifying: self.self_modifying,
+            halt_on_exhausted_input: self.halt_on_exhausted_input,
+            halt_at_program_end: self.halt_at_program_end,
+            strict_bounds: self.strict_bounds,
+            cycle_limit: self.cycle_limit,
+            #[cfg(feature = "std")]
+            time_limit: self.time_limit,
+            #[cfg(feature = "std")]
+            deadline: None,
+            #[cfg(feature = "std")]
+            cancel: self.cancel.clone(),
+            rng: self.rng.clone(),
+
+            stats: self.stats.clone(),
+            coverage: self.coverage.clone(),
+            loop_counts: self.loop_counts.clone(),
+            breakpoints: self.breakpoints.clone(),
+            watchpoints: self.watchpoints.clone(),
+            cell_written: self.cell_written,
+            fingerprints: self.fingerprints.clone(),
+            loop_detection: self.loop_detection,
+            seen_states: self.seen_states.clone(),
+            undo: self.undo.clone(),
+            fusion: self.fusion,
+            bytecode: self.bytecode.clone(),
+            #[cfg(feature = "jit")]
+            jit_threshold: self.jit_threshold,
+            #[cfg(feature = "jit")]
+            jit_warmup: self.jit_warmup,
+            #[cfg(feature = "jit")]
+            jit: jit::Jit::Cold,
+        })
     }
 
     /// Load a program tape: copy data from the given slice into the executable tape,
-    /// starting at address zero.
-    /// On error, the Err(s) return will contain a message describing the error.
-    pub fn load_program(&mut self, program: &[u8]) -> Result<(), String> {
+    /// starting at address zero. Any loops recorded on the jump stack belong to the old program,
+    /// so the jump stack is cleared.
+    pub fn load_program(&mut self, program: &[u8]) -> Result<(), SBrainError> {
         // No program can be longer than the tape the VM stores programs on.
-        if program.len() > 65536 {
-            return Err(String::from("Provided program exceeds VM tape length."));
+        if program.len() > self.exec_len {
+            return Err(SBrainError::ProgramTooLong {
+                len: program.len(),
+                max: self.exec_len,
+            });
+        }
+
+        // Whatever is left of a longer program loaded earlier goes with it
+        self.exec_tape.clear();
+        self.exec_tape.extend_from_slice(program);
+        self.jump_stack.clear();
+        self.halted = false;
+        self.wrapped = false;
+        self.forget_states();
+        self.discard_bytecode();
+        Ok(())
+    }
+
+    /// Replace the program while keeping the data tape, stack, and registers, for example to swap
+    /// programs while the machine is paused between calls to `run`. If `reset_ip` is true,
+    /// execution resumes at the start of the new program; otherwise it resumes at the current
+    /// value of `inst_p`. As with `load_program`, the jump stack is cleared. Breakpoints past the
+    /// end of the new program are removed. On error, the machine is unchanged.
+    pub fn reload_program(&mut self, program: &[u8], reset_ip: bool) -> Result<(), SBrainError> {
+        self.load_program(program)?;
+        let len = program.len();
+        self.breakpoints.retain(|&addr| (addr as usize) < len);
+        if reset_ip {
+            self.inst_p = 0;
+        }
+        Ok(())
+    }
+
+    /// Return the machine to the state it was built in, so that it can run another program (or
+    /// the same one again) without allocating new tapes. If `program` is given, it is loaded;
+    /// otherwise the current exec tape is kept as it is. The data tape is refilled with the
+    /// pattern last passed to `init_data` (zeroes by default), and the stack, `auxi_r`, both
+    /// pointers, the output channel, queued input and any collected output are cleared, as is the
+    /// undo history.
+    ///
+    /// The configuration, input and output connections, breakpoints and watchpoints, the random
+    /// number generator, and the counters from `stats`, `coverage` and `loop_counts` are kept; see
+    /// `reset_stats` for the counters. On error, the machine is unchanged.
+    pub fn reset(&mut self, program: Option<&[u8]>) -> Result<(), SBrainError> {
+        match program {
+            Some(program) => self.load_program(program)?,
+            None => {
+                self.jump_stack.clear();
+                self.halted = false;
+                self.wrapped = false;
+            }
+        }
+        let pattern = self.init_pattern;
+        self.init_data(pattern);
+        self.data_stack.clear();
+        self.auxi_r = C::default();
+        self.data_p = 0;
+        self.data_page = 0;
+        self.inst_p = 0;
+
+        self.input_queue.clear();
+        self.input_exhausted = false;
+        if let Some(OutputTape::Buffer(ref mut buffer)) = self.output_t {
+            buffer.clear();
+        }
+        self.output_pending.clear();
+        self.channel = 0;
+
+        self.cell_written = false;
+        self.fingerprints.clear();
+        self.forget_states();
+        self.clear_undo();
+        Ok(())
+    }
+
+    /// Overwrite the whole data tape with the given pattern. The pattern is remembered, so it is
+    /// also used whenever the machine is reset. Anything other than `InitPattern::Zero` deviates
+    /// from the specification, which requires cells to start at zero.
+    pub fn init_data(&mut self, pattern: InitPattern) {
+        self.init_pattern = pattern;
+        self.far_cells.clear();
+        match pattern {
+            InitPattern::Zero => self
+                .data_tape
+                .iter_mut()
+                .for_each(|cell| *cell = C::default()),
+            InitPattern::Fill(value) => {
+                let value = C::from_u64(u64::from(value));
+                self.data_tape.iter_mut().for_each(|cell| *cell = value);
+            }
+            InitPattern::Random { seed } => {
+                let mut rng = SplitMix64::new(seed);
+                self.data_tape
+                    .iter_mut()
+                    .for_each(|cell| *cell = C::from_u64(rng.next_u64()));
+            }
+        }
+    }
+
+    /// Set how unmatched brackets are handled at runtime. The default is `BracketMode::Lenient`.
+    pub fn set_bracket_mode(&mut self, mode: BracketMode) {
+        self.bracket_mode = mode;
+    }
+
+    /// Set how loops are executed. The default is `LoopMode::Rescan`. Both modes behave the same
+    /// on programs with balanced brackets.
+    pub fn set_loop_mode(&mut self, mode: LoopMode) {
+        self.loop_mode = mode;
+        self.jump_stack.clear();
+    }
+
+    /// The addresses of the `[` instructions of the loops currently being executed, innermost
+    /// last. This is always empty unless the machine is in `LoopMode::JumpStack`.
+    pub fn jump_stack(&self) -> &[MAddr] {
+        &self.jump_stack
+    }
+
+    /// The whole data tape. With a wider `AddressWidth`, cells past the end of the tape are not
+    /// included.
+    pub fn data_tape(&self) -> &[C] {
+        &self.data_tape
+    }
+
+    /// The values on the data stack, bottom first, so the top of the stack is last.
+    pub fn data_stack(&self) -> &[C] {
+        &self.data_stack
+    }
+
+    /// The value of the auxiliary register.
+    pub fn auxi_r(&self) -> C {
+        self.auxi_r
+    }
+
+    /// The address of the current data cell. With a wider `AddressWidth`, this is only the low 16
+    /// bits; see `data_address`.
+    pub fn data_p(&self) -> MAddr {
+        self.data_p
+    }
+
+    /// The address of the next instruction to execute.
+    pub fn inst_p(&self) -> MAddr {
+        self.inst_p
+    }
+
+    /// Set how `+` and `-` behave at the limits of a cell. The default, `ArithMode::Wrapping`,
+    /// follows the specification; `ArithMode::Saturating` is an opt-in deviation from it.
+    pub fn set_arithmetic_mode(&mut self, mode: ArithMode) {
+        self.arith_mode = mode;
+    }
+
+    /// Set how `}` behaves when the data stack is empty. The default, `UnderflowMode::Zero`,
+    /// pops 0; `UnderflowMode::Trap` halts the machine instead.
+    pub fn set_underflow_mode(&mut self, mode: UnderflowMode) {
+        self.underflow_mode = mode;
+    }
+
+    /// Set whether the ordering comparisons (`'` and `"`) treat the current cell and `auxi_r` as
+    /// two's complement signed values, so that, for example, 255 is -1 and less than 0. Only the
+    /// interpretation changes; cells store the same bits either way. The default is unsigned.
+    pub fn set_signedness(&mut self, signed: bool) {
+        self.signed = signed;
+    }
+
+    /// Set how `.` encodes the current cell as output. The default, `OutputEncoding::Byte`,
+    /// follows the specification.
+    pub fn set_output_encoding(&mut self, enc: OutputEncoding) {
+        self.output_encoding = enc;
+    }
+
+    /// Seed the machine's pseudo-random number generator, enabling the random instruction (`%`).
+    /// Until this is called, the random instruction is a NOP so that programs remain fully
+    /// deterministic. The same seed, program, and input always produce the same output.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Some(SplitMix64::new(seed));
+    }
+
+    /// Allow or forbid programs to read and rewrite their own code. When allowed, `:` sets the
+    /// cell at `data_p` to the opcode at the same address on the exec tape, and `;` writes the
+    /// cell at `data_p` to the exec tape as the opcode at that address. When forbidden, which is
+    /// the default, both are NOPs and the exec tape is read-only as in the specification.
+    ///
+    /// This is a non-spec extension. Rewriting a bracket changes the program's loop structure. In
+    /// `LoopMode::JumpStack`, a `]` whose recorded `[` has been overwritten finds its match by
+    /// scanning instead, as in `LoopMode::Rescan`.
+    pub fn set_self_modifying(&mut self, enabled: bool) {
+        self.self_modifying = enabled;
+    }
+
+    /// Enable or disable the no-progress heuristic. When enabled, every `config.interval` cycles
+    /// the machine records a fingerprint of `inst_p`, `data_p`, `auxi_r`, the top of the stack and
+    /// the current cell. If the same fingerprint is recorded `config.repeats` times with no output
+    /// in between, `run` stops with `HaltReason::NoProgress`.
+    ///
+    /// This is a heuristic intended for discarding stuck genetic programs early. It does not look
+    /// at the rest of the data tape or the stack, so a program which is making progress elsewhere
+    /// may be stopped (a false positive). It is disabled by default.
+    pub fn set_no_progress_detection(&mut self, config: Option<NoProgressConfig>) {
+        self.no_progress = config;
+        self.fingerprints.clear();
+    }
+
+    fn fingerprint(&self) -> Fingerprint<C> {
+        (
+            self.inst_p,
+            self.data_address(),
+            self.auxi_r,
+            self.data_stack.last().cloned(),
+            self.cell(),
+        )
+    }
+
+    /// Record a fingerprint if one is due, returning true if the program seems to be stuck.
+    fn check_progress(&mut self, done_cycles: u32) -> bool {
+        let config = match self.no_progress {
+            Some(config) => config,
+            None => return false,
+        };
+        if config.interval == 0 || !done_cycles.is_multiple_of(config.interval) {
+            return false;
+        }
+        let fingerprint = self.fingerprint();
+        let seen = self.fingerprints.entry(fingerprint).or_insert(0);
+        *seen = seen.saturating_add(1);
+        *seen >= config.repeats
+    }
+
+    /// Execute a bracket instruction, jumping to the matching bracket if `jump` is true.
+    fn do_jump(&mut self, jump: bool) -> Option<HaltReason> {
+        if !jump && self.bracket_mode == BracketMode::Lenient {
+            return None;
+        }
+        // Past the end of the program there are no brackets to match
+        match matching_bracket(&self.exec_tape, self.inst_p) {
+            Some(target) => {
+                if jump {
+                    self.inst_p = target;
+                }
+                None
+            }
+            None => match self.bracket_mode {
+                BracketMode::Lenient => None,
+                BracketMode::Strict => Some(HaltReason::UnbalancedBracket { addr: self.inst_p }),
+            },
         }
+    }
 
-        // Target is a slice of the VMs executable tape of the same size as the program
-        // This is required from clone_from_slice
-        self.exec_tape[0..program.len()].clone_from_slice(program);
-        return Ok(());
+    /// Execute a `]` in `LoopMode::JumpStack`, jumping back to the innermost loop if `jump` is true
+    /// and leaving it otherwise.
+    fn do_stack_jump(&mut self, jump: bool) -> Option<HaltReason> {
+        match self.jump_stack.last() {
+            Some(&target) if self.opcode_at(target) != 4 => {
+                // Self-modifying code overwrote the `[` this loop was entered through, so the
+                // recorded address is stale; scan for the match instead
+                self.jump_stack.pop();
+                let halt = self.do_jump(jump);
+                if halt.is_none() && jump && self.opcode_at(self.inst_p) == 4 {
+                    self.jump_stack.push(self.inst_p);
+                }
+                halt
+            }
+            Some(&target) => {
+                if jump {
+                    self.inst_p = target;
+                } else {
+                    self.jump_stack.pop();
+                }
+                None
+            }
+            None => match self.bracket_mode {
+                BracketMode::Lenient => None,
+                BracketMode::Strict => Some(HaltReason::UnbalancedBracket { addr: self.inst_p }),
+            },
+        }
     }
 
-    fn get_input(&mut self) -> io::Result<MData> {
-        let mut buf = [0; 1];
-        if let Some(ref mut r) = self.input_t {
-            r.read(&mut buf)?;
-            Ok(buf[0])
+    /// The exit code, `auxi_r`, if the machine has halted, or None if it hasn't (or was stopped
+    /// for any other reason). This stays available after `run` returns, until the machine
+    /// executes another instruction or loads a program. For cells wider than `MData`, this is
+    /// the low byte of `auxi_r`.
+    pub fn exit_code(&self) -> Option<u8> {
+        if self.halted {
+            Some(self.auxi_r.to_u64() as u8)
         } else {
-            Ok(0)
+            None
+        }
+    }
+
+    /// Whether the program has tried to read past the end of its input. Such reads read 0, which
+    /// a program can't tell apart from a real 0 byte; this tells the caller. A machine with no
+    /// input is exhausted as soon as it reads. Once set, this stays set, even if more input is
+    /// queued with `feed_input`.
+    pub fn input_exhausted(&self) -> bool {
+        self.input_exhausted
+    }
+
+    /// Make reading past the end of the input halt the machine with
+    /// `HaltReason::InputExhausted`, instead of reading 0. The cell is left unchanged. This
+    /// deviates from the specification; it is off by default.
+    pub fn set_halt_on_exhausted_input(&mut self, enabled: bool) {
+        self.halt_on_exhausted_input = enabled;
+    }
+
+    /// Make execution passing the end of the loaded program halt the machine with
+    /// `HaltReason::RanOffTape`, instead of running on through the zeroed rest of the tape and
+    /// wrapping around to the start. Restarting with `~` is not running off the end. This
+    /// deviates from the specification; it is off by default.
+    pub fn set_halt_at_program_end(&mut self, enabled: bool) {
+        self.halt_at_program_end = enabled;
+    }
+
+    /// Give every run which doesn't set its own cycle limit (by passing None to `run`, or to
+    /// the other run methods which take one) this limit instead, or remove the limit with None.
+    /// This suits harnesses which run many programs with the same budget. There is no limit by
+    /// default.
+    pub fn set_cycle_limit(&mut self, cycles: Option<u32>) {
+        self.cycle_limit = cycles;
+    }
+
+    /// Stop every run which takes longer than `limit` with `HaltReason::TimedOut`, or remove the
+    /// limit with None. This applies alongside any cycle limit, and suits programs attached to
+    /// slow input or output, where cycles are a poor measure of time. The clock is only checked
+    /// every few thousand cycles, so a run may overshoot its limit slightly, and a read or write
+    /// which blocks is not interrupted. There is no limit by default.
+    #[cfg(feature = "std")]
+    pub fn set_time_limit(&mut self, limit: Option<Duration>) {
+        self.time_limit = limit;
+    }
+
+    /// Start timing a run against the time limit, if there is one.
+    #[cfg(feature = "std")]
+    fn start_clock(&mut self) {
+        self.deadline = self.time_limit.map(|limit| Instant::now() + limit);
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn start_clock(&mut self) {}
+
+    /// Whether the run has passed its deadline, checking the clock every `DEADLINE_INTERVAL`
+    /// cycles.
+    #[cfg(feature = "std")]
+    fn timed_out(&self, done_cycles: u32) -> bool {
+        match self.deadline {
+            Some(deadline) => {
+                done_cycles.is_multiple_of(DEADLINE_INTERVAL) && Instant::now() >= deadline
+            }
+            None => false,
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn timed_out(&self, _done_cycles: u32) -> bool {
+        false
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn cancelled(&self) -> bool {
+        false
+    }
+
+    /// Make pointers crossing the ends of their tapes halt the machine instead of wrapping
+    /// around: the data pointer with `HaltReason::PointerUnderflow` or
+    /// `HaltReason::PointerOverflow`, leaving it where it was, and the instruction pointer with
+    /// `HaltReason::RanOffTape`. This lets genetic programming penalize programs which run off
+    /// their tapes. Restarting with `~` is not wrapping. This deviates from the specification; it
+    /// is off by default.
+    pub fn set_strict_bounds(&mut self, enabled: bool) {
+        self.strict_bounds = enabled;
+    }
+
+    /// Load a data tape: copy data from the given slice into the data tape, starting at address
+    /// zero. Equivalent to `load_data_at(0, data)`.
+    pub fn load_data(&mut self, data: &[C]) -> Result<(), SBrainError> {
+        self.load_data_at(0, data)
+    }
+
+    /// Copy data from the given slice into the data tape, starting at address `offset`.
+    /// Cells outside the copied range, including any before `offset`, are left untouched; they
+    /// are not zeroed.
+    pub fn load_data_at(&mut self, offset: MAddr, data: &[C]) -> Result<(), SBrainError> {
+        let start = offset as usize;
+        if start + data.len() > self.data_tape.len() {
+            return Err(SBrainError::DataTooLong {
+                offset: start,
+                len: data.len(),
+            });
+        }
+
+        self.data_tape[start..start + data.len()].clone_from_slice(data);
+        Ok(())
+    }
+
+    /// Queue bytes to be read by the program. Queued bytes are read before any bytes from the
+    /// machine's `Read`; once the queue is empty, reads fall through to the `Read`, or read 0 if
+    /// there is none. This can be called between calls to `run` to feed a program as it goes.
+    pub fn feed_input(&mut self, bytes: &[MData]) {
+        self.input_queue.extend(bytes);
+    }
+
+    /// Read a byte of input, or None if there is none left.
+    fn get_input(&mut self) -> io::Result<Option<MData>> {
+        if let Some(byte) = self.input_queue.pop_front() {
+            return Ok(Some(byte));
+        }
+        if self.input_pos == self.input_buf.len() {
+            if let Some(InputTape::Reader(_)) = self.input_t {
+                // About to wait for input, so make sure any prompt has been written
+                self.flush_output()?;
+            }
+        }
+        match self.input_t {
+            Some(InputTape::Reader(ref mut r)) => {
+                if self.input_pos == self.input_buf.len() {
+                    let mut chunk = [0; INPUT_CHUNK];
+                    let n = r.read(&mut chunk)?;
+                    self.input_buf.clear();
+                    self.input_buf.extend_from_slice(&chunk[..n]);
+                    self.input_pos = 0;
+                    if n == 0 {
+                        return Ok(None);
+                    }
+                }
+                self.input_pos += 1;
+                Ok(Some(self.input_buf[self.input_pos - 1]))
+            }
+            Some(InputTape::Iter(ref mut iter)) => Ok(iter.next()),
+            None => Ok(None),
         }
     }
 
-    fn put_output(&mut self, output: MData) -> io::Result<()> {
-        match &mut self.output_t {
-            &mut Some(ref mut w) => {
-                w.write(&[output])?;
+    fn put_output(&mut self, output: C) -> io::Result<()> {
+        self.fingerprints.clear();
+        let value = output.to_u64();
+        let le = value.to_le_bytes();
+        let be = value.to_be_bytes();
+        let bytes: &[u8] = match self.output_encoding {
+            OutputEncoding::Byte => &le[..1],
+            OutputEncoding::LittleEndian(width) => &le[..width.min(8)],
+            OutputEncoding::BigEndian(width) => &be[8 - width.min(8)..],
+        };
+        match self.output_t {
+            Some(OutputTape::Buffer(ref mut buffer)) => {
+                if self.channel == 0 {
+                    buffer.extend_from_slice(bytes);
+                }
+                Ok(())
+            }
+            Some(OutputTape::Callback(ref mut f)) => {
+                if self.channel == 0 {
+                    bytes.iter().for_each(|&byte| f(byte));
+                }
                 Ok(())
             }
-            &mut None => Ok(()),
+            Some(_) => {
+                self.output_pending.extend_from_slice(bytes);
+                if self.output_pending.len() >= OUTPUT_CHUNK {
+                    self.write_pending()?;
+                }
+                Ok(())
+            }
+            None => Ok(()),
         }
     }
 
-    /// Execute an instruction on the current virtual machine
-    /// Returns true if execution is finished and false if not
-    fn do_instruction(&mut self) -> io::Result<bool> {
-        match self.exec_tape[self.inst_p as usize] {
+    /// Whether `.` currently delivers output somewhere, rather than discarding it.
+    pub(super) fn output_delivered(&self) -> bool {
+        self.output_t
+            .as_ref()
+            .is_some_and(|t| t.has_channel(self.channel))
+    }
+
+    /// Write the output held back so far to the selected channel.
+    fn write_pending(&mut self) -> io::Result<()> {
+        if self.output_pending.is_empty() {
+            return Ok(());
+        }
+        let channel = self.channel;
+        if let Some(w) = self.output_t.as_mut().and_then(|t| t.writer(channel)) {
+            w.write_all(&self.output_pending)?;
+        }
+        self.output_pending.clear();
+        Ok(())
+    }
+
+    /// Write any output the machine is holding back to its `Write`, and flush the `Write` (or
+    /// every `Write`, for a machine with several output channels).
+    /// Output is written in chunks rather than a byte at a time, and this happens automatically
+    /// whenever a call to `run` (or `run_observed` or `run_metered`) returns and before the
+    /// machine waits for more input, so it is only needed to see output sooner.
+    pub fn flush_output(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        match self.output_t {
+            Some(OutputTape::Writer(ref mut w)) => w.flush(),
+            Some(OutputTape::Channels(ref mut ws)) => ws.iter_mut().try_for_each(|w| w.flush()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Execute a decoded instruction on the current virtual machine
+    /// Returns the reason execution must stop, or None if it may continue
+    fn do_instruction(&mut self, instruction: Instruction) -> io::Result<Option<HaltReason>> {
+        match instruction {
             // wrapping_add() and wrapping_sub are used in order to never overflow the bounds
             // of unsigned int types
             //
             // Decr. and incr. for data_p
-            0 => {
-                self.data_p = self.data_p.wrapping_sub(1);
-            }
-            1 => {
-                self.data_p = self.data_p.wrapping_add(1);
-            }
+            Instruction::Left => return Ok(self.move_data_p(false)),
+            Instruction::Right => return Ok(self.move_data_p(true)),
             // Decr. and incr. for *data_p
-            2 => {
-                self.data_tape[self.data_p as usize] =
-                    self.data_tape[self.data_p as usize].wrapping_sub(1);
+            Instruction::Decrement => {
+                let arith_mode = self.arith_mode;
+                let cell = self.cell_mut();
+                *cell = match arith_mode {
+                    ArithMode::Wrapping => cell.wrapping_sub(C::from_u64(1)),
+                    ArithMode::Saturating => cell.saturating_sub(C::from_u64(1)),
+                };
             }
-            3 => {
-                self.data_tape[self.data_p as usize] =
-                    self.data_tape[self.data_p as usize].wrapping_add(1);
+            Instruction::Increment => {
+                let arith_mode = self.arith_mode;
+                let cell = self.cell_mut();
+                *cell = match arith_mode {
+                    ArithMode::Wrapping => cell.wrapping_add(C::from_u64(1)),
+                    ArithMode::Saturating => cell.saturating_add(C::from_u64(1)),
+                };
             }
             // Jump instructions
-            4 => {
+            Instruction::LoopStart => {
                 // If *data_p is 0, skip forward to the corresponding 5
-                let this_inst = self.inst_p;
-                if self.data_tape[self.data_p as usize] == 0 {
-                    let mut nest_level = 1;
-                    while nest_level > 0 {
-                        self.inst_p = self.inst_p.wrapping_add(1);
-                        if self.inst_p == 0 {
-                            self.inst_p = this_inst;
-                            break;
-                        }
-                        if self.exec_tape[self.inst_p as usize] == 4 {
-                            nest_level += 1;
-                        } else if self.exec_tape[self.inst_p as usize] == 5 {
-                            nest_level -= 1;
-                        }
-                    }
+                let jump = self.cell() == C::default();
+                let halt = self.do_jump(jump);
+                if halt.is_none() && !jump && self.loop_mode == LoopMode::JumpStack {
+                    self.jump_stack.push(self.inst_p);
                 }
+                return Ok(halt);
             }
-            5 => {
+            Instruction::LoopEnd => {
                 // If *data_p isn't 0, skip backward to the corresponding 4
-                let this_inst = self.inst_p;
-                if self.data_tape[self.data_p as usize] != 0 {
-                    let mut nest_level = 1;
-                    while nest_level > 0 {
-                        self.inst_p = self.inst_p.wrapping_sub(1);
-                        if self.inst_p == u16MAX {
-                            self.inst_p = this_inst;
-                            break;
-                        }
-                        if self.exec_tape[self.inst_p as usize] == 5 {
-                            nest_level += 1;
-                        } else if self.exec_tape[self.inst_p as usize] == 4 {
-                            nest_level -= 1;
-                        }
-                    }
+                let jump = self.cell() != C::default();
+                let from = self.inst_p;
+                let halt = match self.loop_mode {
+                    LoopMode::Rescan => self.do_jump(jump),
+                    LoopMode::JumpStack => self.do_stack_jump(jump),
+                };
+                if self.inst_p != from {
+                    self.record_loop(self.inst_p);
                 }
+                return Ok(halt);
             }
             // I/O commands
-            6 => {
-                let temp = self.data_tape[self.data_p as usize];
+            Instruction::Output => {
+                let temp = self.cell();
                 self.put_output(temp)?;
             }
-            7 => {
-                let temp = self.get_input()?;
-                self.data_tape[self.data_p as usize] = temp;
+            Instruction::Input => {
+                let temp = match self.get_input()? {
+                    Some(byte) => {
+                        // The rest of the input is part of the state, and it just changed
+                        self.forget_states();
+                        byte
+                    }
+                    None => {
+                        self.input_exhausted = true;
+                        if self.halt_on_exhausted_input {
+                            return Ok(Some(HaltReason::InputExhausted));
+                        }
+                        // EOF reads 0
+                        0
+                    }
+                };
+                *self.cell_mut() = C::from_u64(u64::from(temp));
             }
             // Stack instructions
-            8 => {
-                self.data_stack.push(self.data_tape[self.data_p as usize]);
+            Instruction::Push => {
+                let value = self.cell();
+                self.data_stack.push(value);
             }
-            9 => {
-                self.data_tape[self.data_p as usize] = match self.data_stack.pop() {
-                    Some(n) => n,
-                    None => 0,
+            Instruction::Pop => {
+                let value = match self.data_stack.pop() {
+                    Some(value) => value,
+                    None => match self.underflow_mode {
+                        UnderflowMode::Zero => C::default(),
+                        UnderflowMode::Trap => return Ok(Some(HaltReason::StackUnderflow)),
+                    },
                 };
+                *self.cell_mut() = value;
             }
             // Aux register instructions
-            10 => {
-                self.auxi_r = self.data_tape[self.data_p as usize];
+            Instruction::StoreAux => {
+                self.auxi_r = self.cell();
             }
-            11 => {
-                self.data_tape[self.data_p as usize] = self.auxi_r;
+            Instruction::LoadAux => {
+                *self.cell_mut() = self.auxi_r;
             }
-            12 => {
-                self.auxi_r = 0;
+            Instruction::ClearAux => {
+                self.auxi_r = C::default();
             }
             // Bitwise auxi_r instructions
             //  NOT
-            13 => self.auxi_r = !self.auxi_r,
+            Instruction::Not => self.auxi_r = self.auxi_r.bit_not(),
             //  AND
-            14 => {
-                self.auxi_r = self.data_tape[self.data_p as usize] & self.auxi_r;
+            Instruction::And => {
+                self.auxi_r = self.auxi_r.bit_and(self.cell());
+            }
+            Instruction::Halt => {
+                return Ok(Some(HaltReason::Halted(self.auxi_r.to_u64() as u8)));
+            }
+            // Rotate auxi_r
+            Instruction::RotateLeft => self.auxi_r = self.auxi_r.rotate_left(1),
+            Instruction::RotateRight => self.auxi_r = self.auxi_r.rotate_right(1),
+            // Random value into *data_p, if enabled
+            Instruction::Random => {
+                if let Some(ref mut rng) = self.rng {
+                    let value = C::from_u64(rng.next_u64());
+                    *self.cell_mut() = value;
+                }
+            }
+            // Restart from the first instruction, keeping the tapes, stack and auxi_r. Like any
+            // other instruction this takes a cycle, so a program that only restarts still runs
+            // into the cycle limit rather than spinning forever.
+            Instruction::Restart => {
+                // nexti() wraps this around to 0
+                self.inst_p = MAddr::MAX;
+                self.jump_stack.clear();
+            }
+            // Read and write the exec tape at data_p, if enabled
+            // Addresses past the end of a smaller exec tape read 0 and can't be written.
+            Instruction::ReadCode if self.self_modifying => {
+                let code = self.opcode_at(self.data_p);
+                *self.cell_mut() = C::from_u64(u64::from(code));
+            }
+            Instruction::WriteCode if self.self_modifying => {
+                let addr = self.data_p as usize;
+                if addr < self.exec_len {
+                    // Code past the end of the program is now part of it
+                    if addr >= self.exec_tape.len() {
+                        self.exec_tape.resize(addr + 1, 0);
+                    }
+                    self.exec_tape[addr] = self.cell().to_u64() as u8;
+                    self.fingerprints.clear();
+                    self.forget_states();
+                    self.discard_bytecode();
+                }
+            }
+            // Compare *data_p with auxi_r, replacing *data_p with 1 if true or 0 if not
+            Instruction::Equal => {
+                let auxi_r = self.auxi_r;
+                let cell = self.cell_mut();
+                *cell = C::from_u64((*cell == auxi_r) as u64);
             }
-            15 => {
-                return Ok(true);
+            Instruction::Less | Instruction::Greater => {
+                let (a, b) = if instruction == Instruction::Less {
+                    (self.cell(), self.auxi_r)
+                } else {
+                    (self.auxi_r, self.cell())
+                };
+                // a < b
+                let result = if self.signed {
+                    a.to_i64() < b.to_i64()
+                } else {
+                    a < b
+                };
+                *self.cell_mut() = C::from_u64(result as u64);
+            }
+            // Skip the next instruction if *data_p is 0. The skipped instruction is not executed
+            // and takes no cycle of its own.
+            Instruction::SkipIfZero if self.cell() == C::default() => {
+                self.wrapped |= self.nexti();
+            }
+            // Select the output channel *data_p
+            Instruction::Channel => {
+                self.write_pending()?;
+                self.channel = self.cell().to_u64() as usize;
             }
             _ => {}
         }
-        return Ok(false);
+        Ok(None)
+    }
+
+    /// The opcode at `addr` on the exec tape.
+    fn opcode_at(&self, addr: MAddr) -> u8 {
+        self.exec_tape.get(addr as usize).cloned().unwrap_or(0)
     }
 
     fn nexti(&mut self) -> bool {
-        // increment the PC
-        self.inst_p = self.inst_p.wrapping_add(1);
-        // if it went over, wrap it and inform the caller
-        if self.inst_p as usize == self.exec_tape.len() - 1 {
+        // increment the PC, wrapping past the last cell of the tape and informing the caller
+        let next = self.inst_p as usize + 1;
+        if next >= self.exec_len {
             self.inst_p = 0;
             return true;
         }
-        return false;
+        self.inst_p = next as MAddr;
+        false
+    }
+
+    /// Execute the current instruction and advance to the next one.
+    /// Returns the reason execution must stop, or None if it may continue
+    fn step(&mut self) -> io::Result<Option<HaltReason>> {
+        let opcode = self.opcode_at(self.inst_p);
+        self.step_decoded(opcode, Instruction::decode(opcode))
+    }
+
+    /// Like `step`, for the current instruction already decoded from `opcode`.
+    fn step_decoded(
+        &mut self,
+        opcode: u8,
+        instruction: Instruction,
+    ) -> io::Result<Option<HaltReason>> {
+        let ran_off = if self.halt_at_program_end {
+            self.wrapped || self.inst_p as usize >= self.exec_tape.len()
+        } else {
+            self.strict_bounds && self.wrapped
+        };
+        if ran_off {
+            self.halted = false;
+            return Ok(Some(HaltReason::RanOffTape));
+        }
+        self.cell_written = false;
+        self.record_undo();
+        self.record_instruction(opcode);
+        let halt = self.do_instruction(instruction)?;
+        self.finish_undo(halt.is_none());
+        self.halted = matches!(halt, Some(HaltReason::Halted(_)));
+        if let Some(reason) = halt {
+            return Ok(Some(reason));
+        }
+        // A restart wraps around on purpose
+        self.wrapped |= self.nexti() && instruction != Instruction::Restart;
+        Ok(None)
     }
 
     /// Run the machine, until completion (cycles = None) or for n cycles (cycles = Some(n)).
-    /// Return values are number of cycles run and the return code, or None if the code simply ran
-    /// out of cycles.
-    pub fn run(&mut self, cycles: Option<u32>) -> io::Result<(u32, Option<u8>)> {
+    /// If the machine has a cycle limit, set with `set_cycle_limit`, None runs for that many
+    /// cycles instead. Return values are number of cycles run and the reason the machine stopped.
+    ///
+    /// The machine keeps its state between calls, so a run which stops with
+    /// `HaltReason::CycleLimit` can be continued with another call, which picks up exactly where
+    /// the last one stopped. Running in several smaller budgets gives the same result as running
+    /// in one large one.
+    ///
+    /// Where it can, this runs fused operations in place of common runs of instructions; see
+    /// `set_fusion`. With the `jit` feature, it can also run them as native code; see `set_jit`.
+    pub fn run(&mut self, cycles: Option<u32>) -> io::Result<(u32, HaltReason)> {
+        if !self.can_fuse() {
+            return self.run_observed(&mut (), cycles);
+        }
+        let cycles = cycles.or(self.cycle_limit);
+        self.start_clock();
+        #[cfg(feature = "jit")]
+        let result = self.run_compiled(cycles);
+        #[cfg(not(feature = "jit"))]
+        let result = self.run_fused(cycles);
+        self.finish_run(result)
+    }
+
+    /// Run the machine like `run`, calling `obs.on_cycle` after each cycle. If the observer
+    /// returns `ControlFlow::Break`, the machine stops with `HaltReason::Aborted`; the cycle
+    /// the observer saw is counted. A halt instruction is not a cycle, so it is not observed.
+    pub fn run_observed(
+        &mut self,
+        obs: &mut impl Observer<C>,
+        cycles: Option<u32>,
+    ) -> io::Result<(u32, HaltReason)> {
+        let cycles = cycles.or(self.cycle_limit);
+        self.start_clock();
+        let result = self.run_observed_unflushed(obs, cycles);
+        self.finish_run(result)
+    }
+
+    fn run_observed_unflushed(
+        &mut self,
+        obs: &mut impl Observer<C>,
+        cycles: Option<u32>,
+    ) -> io::Result<(u32, HaltReason)> {
+        let (done_cycles, reason) = self.run_loop(obs, cycles)?;
+        obs.on_halt(self, reason);
+        Ok((done_cycles, reason))
+    }
+
+    fn run_loop(
+        &mut self,
+        obs: &mut impl Observer<C>,
+        cycles: Option<u32>,
+    ) -> io::Result<(u32, HaltReason)> {
         let mut done_cycles = 0;
 
         // The main execution loop
         loop {
+            if self.cancelled() {
+                return Ok((done_cycles, HaltReason::Cancelled));
+            }
+            if done_cycles > 0 && self.at_breakpoint() {
+                return Ok((done_cycles, HaltReason::Breakpoint { addr: self.inst_p }));
+            }
             // Execute the current instruction.
-            if self.do_instruction()? {
-                return Ok((done_cycles, Some(self.auxi_r)));
-            } else {
-                self.nexti();
+            let opcode = self.opcode_at(self.inst_p);
+            if let Some(reason) = self.step()? {
+                return Ok((done_cycles, reason));
             }
 
-            // Increment the cycle count
-            done_cycles += 1;
+            // Increment the cycle count. Without a limit this could run past u32::MAX cycles, so
+            // the count sticks there rather than overflowing.
+            done_cycles = done_cycles.saturating_add(1);
+            match opcode {
+                6 => obs.on_output(self, self.cell()),
+                7 => obs.on_input(self, self.cell()),
+                _ => {}
+            }
+            if obs.on_cycle(self, opcode) == ControlFlow::Break {
+                return Ok((done_cycles, HaltReason::Aborted));
+            }
+            if let Some(addr) = self.watchpoint_hit() {
+                return Ok((done_cycles, HaltReason::Watchpoint { addr }));
+            }
             if let Some(n) = cycles {
                 if done_cycles >= n {
-                    return Ok((done_cycles, None));
+                    return Ok((done_cycles, HaltReason::CycleLimit));
                 }
             }
+            if self.check_progress(done_cycles) {
+                return Ok((done_cycles, HaltReason::NoProgress));
+            }
+            if self.check_loop(done_cycles) {
+                return Ok((done_cycles, HaltReason::LoopDetected));
+            }
+            if self.timed_out(done_cycles) {
+                return Ok((done_cycles, HaltReason::TimedOut));
+            }
+        }
+    }
+
+    /// Run the machine with a gas budget instead of a cycle limit. Before each instruction,
+    /// `weights[opcode]` is deducted from `gas`; opcodes outside the table cost 1. If the budget
+    /// cannot cover the next instruction, the machine stops with `HaltReason::OutOfGas` without
+    /// executing it. Like a cycle in `run`, an instruction which stops the machine, such as `@`,
+    /// is not charged once it has run. With every weight set to 1, this behaves like `run` with a
+    /// cycle limit; `OpcodeClass::cost_table` builds a table with a cost for each class of
+    /// instruction.
+    /// Return values are the amount of gas used and the reason the machine stopped.
+    pub fn run_metered(&mut self, gas: u64, weights: &[u64; 32]) -> io::Result<(u64, HaltReason)> {
+        self.start_clock();
+        let result = self.run_metered_unflushed(gas, weights);
+        self.finish_run(result)
+    }
+
+    fn run_metered_unflushed(
+        &mut self,
+        gas: u64,
+        weights: &[u64; 32],
+    ) -> io::Result<(u64, HaltReason)> {
+        let mut used_gas: u64 = 0;
+        let mut done_cycles: u32 = 0;
+        let mut first = true;
+
+        loop {
+            if self.cancelled() {
+                return Ok((used_gas, HaltReason::Cancelled));
+            }
+            if !first && self.at_breakpoint() {
+                return Ok((used_gas, HaltReason::Breakpoint { addr: self.inst_p }));
+            }
+            first = false;
+            let opcode = self.opcode_at(self.inst_p);
+            let cost = weights.get(opcode as usize).cloned().unwrap_or(1);
+            if gas - used_gas < cost {
+                return Ok((used_gas, HaltReason::OutOfGas));
+            }
+
+            if let Some(reason) = self.step()? {
+                return Ok((used_gas, reason));
+            }
+            used_gas += cost;
+
+            done_cycles = done_cycles.wrapping_add(1);
+            if let Some(addr) = self.watchpoint_hit() {
+                return Ok((used_gas, HaltReason::Watchpoint { addr }));
+            }
+            if self.check_progress(done_cycles) {
+                return Ok((used_gas, HaltReason::NoProgress));
+            }
+            if self.check_loop(done_cycles) {
+                return Ok((used_gas, HaltReason::LoopDetected));
+            }
+            if self.timed_out(done_cycles) {
+                return Ok((used_gas, HaltReason::TimedOut));
+            }
+        }
+    }
+
+    /// Flush output at the end of a run, whether or not it succeeded. An error from the run
+    /// itself takes precedence over one from flushing.
+    fn finish_run<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        let flushed = self.flush_output();
+        let value = result?;
+        flushed?;
+        Ok(value)
+    }
+}
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// The cells within `WINDOW_RADIUS` of `data_p`, wrapping around the ends of the tape.
+    fn data_window(&self) -> Vec<(MAddr, C)> {
+        (0..=2 * WINDOW_RADIUS)
+            .map(|i| {
+                let addr = self.offset_address(i32::from(i) - i32::from(WINDOW_RADIUS));
+                (addr as MAddr, self.cell_at(addr))
+            })
+            .collect()
+    }
+
+    /// The top `STACK_SHOWN` values of the stack, bottom first.
+    fn stack_top(&self) -> &[C] {
+        let skip = self.data_stack.len().saturating_sub(STACK_SHOWN);
+        &self.data_stack[skip..]
+    }
+}
+
+/// Shows the registers, the top of the stack, and the neighborhood of `data_p` rather than the
+/// full tapes.
+impl<'a, C: Cell> fmt::Debug for SBrainVM<'a, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let window: Vec<String> = self
+            .data_window()
+            .iter()
+            .map(|&(addr, value)| format!("{:#06x}: {}", addr, value))
+            .collect();
+        f.debug_struct("SBrainVM")
+            .field("inst_p", &self.inst_p)
+            .field("data_p", &self.data_p)
+            .field("auxi_r", &self.auxi_r)
+            .field("stack_depth", &self.data_stack.len())
+            .field("stack_top", &self.stack_top())
+            .field("data_window", &window)
+            .finish()
+    }
+}
+
+/// A short, human-readable summary of the machine: registers, the top of the stack, and the
+/// cells around `data_p`, with the current cell in brackets.
+impl<'a, C: Cell> fmt::Display for SBrainVM<'a, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "inst_p: {}, data_p: {}, aux: {}",
+            self.inst_p, self.data_p, self.auxi_r
+        )?;
+        writeln!(
+            f,
+            "stack: {} values, top {:?}",
+            self.data_stack.len(),
+            self.stack_top()
+        )?;
+        let window = self.data_window();
+        write!(f, "tape from {}:", window[0].0)?;
+        for (addr, value) in window {
+            if addr == self.data_p {
+                write!(f, " [{}]", value)?;
+            } else {
+                write!(f, " {}", value)?;
+            }
         }
+        Ok(())
     }
 }
@@ -0,0 +1,176 @@
+//! Data pointers wider than `MAddr`.
+use super::{HaltReason, InitPattern, SBrainVM};
+use crate::cell::Cell;
+use crate::rng::SplitMix64;
+use crate::MAddr;
+
+/// How many bits the data pointer has, and so how many cells the data tape holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressWidth {
+    /// A 16 bit pointer over 65536 cells, as described by the specification.
+    #[default]
+    Bits16,
+    /// A 32 bit pointer over 4294967296 cells. Only the cells on the data tape are allocated up
+    /// front; the rest are stored sparsely as they are written.
+    Bits32,
+    /// A 32 bit pointer over a tape which grows to the right as the pointer reaches its end, a
+    /// segment of `GROWTH_SEGMENT` cells at a time, so the pointer never wraps around. Moving
+    /// left of cell 0 halts the machine with `HaltReason::PointerUnderflow`, and moving right of
+    /// the last cell it can grow to, `VmConfig::max_data_len`, with `HaltReason::PointerOverflow`.
+    /// Unlike
+    /// `AddressWidth::Bits32`, the cells are stored densely, which suits programs that sweep
+    /// right across a long tape.
+    Growable,
+}
+
+/// How many cells are added at a time when a `AddressWidth::Growable` data tape grows.
+pub const GROWTH_SEGMENT: usize = 4096;
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// Set the width of the data pointer. The pointer wraps around at the end of the tape of the
+    /// chosen width, so with `AddressWidth::Bits32`, `<` at cell 0 moves to cell 4294967295.
+    /// Anything other than `AddressWidth::Bits16` deviates from the specification.
+    ///
+    /// `data_p`, and everything else reported as an `MAddr`, is the pointer's low 16 bits; see
+    /// `data_address` for the whole pointer. Loading data and reading or writing code with `:`
+    /// and `;` only reach the cells on the data tape.
+    pub fn with_address_width(mut self, width: AddressWidth) -> SBrainVM<'a, C> {
+        self.address_width = width;
+        self
+    }
+
+    /// The address of the current cell, including any bits beyond `MAddr`.
+    pub fn data_address(&self) -> u32 {
+        (u32::from(self.data_page) << 16) | u32::from(self.data_p)
+    }
+
+    /// Point the data pointer at `addr`, wrapped to the pointer's width. With a 16 bit pointer,
+    /// this wraps at the end of the data tape, which may be shorter than 65536 cells. A growable
+    /// tape grows to include `addr` if it can; cells past the most it can grow to are stored
+    /// sparsely instead.
+    pub(super) fn set_data_address(&mut self, addr: u32) {
+        match self.address_width {
+            AddressWidth::Bits16 => {
+                self.data_p = (addr as usize % self.data_tape.len()) as MAddr;
+                self.data_page = 0;
+            }
+            AddressWidth::Bits32 | AddressWidth::Growable => {
+                self.data_p = addr as MAddr;
+                self.data_page = (addr >> 16) as MAddr;
+            }
+        }
+        if self.address_width == AddressWidth::Growable {
+            self.grow_to(addr as usize + 1);
+        }
+    }
+
+    /// Lengthen the data tape to at least `len` cells, in whole segments but no longer than
+    /// `max_data_len`. New cells take any values already stored sparsely for them, or start as
+    /// the init pattern says. Returns false, leaving the tape as it is, if `len` is more than
+    /// `max_data_len`.
+    pub(super) fn grow_to(&mut self, len: usize) -> bool {
+        let old_len = self.data_tape.len();
+        if len <= old_len {
+            return true;
+        }
+        if len > self.max_data_len {
+            return false;
+        }
+        let new_len = (len.div_ceil(GROWTH_SEGMENT) * GROWTH_SEGMENT).min(self.max_data_len);
+        self.data_tape.reserve(new_len - old_len);
+        for addr in old_len..new_len {
+            let addr = addr as u32;
+            let value = match self.far_cells.remove(&addr) {
+                Some(value) => value,
+                None => self.far_init(addr),
+            };
+            self.data_tape.push(value);
+        }
+        true
+    }
+
+    /// The address `delta` cells from the current one, wrapping around the ends of the tape.
+    pub(super) fn offset_address(&self, delta: i32) -> u32 {
+        let addr = self.data_address();
+        match self.address_width {
+            AddressWidth::Bits16 => {
+                (i64::from(addr) + i64::from(delta)).rem_euclid(self.data_tape.len() as i64) as u32
+            }
+            AddressWidth::Bits32 | AddressWidth::Growable => addr.wrapping_add(delta as u32),
+        }
+    }
+
+    /// Move the data pointer one cell left or right, wrapping around the ends of the tape.
+    /// A growable tape doesn't wrap, and with strict bounds no tape does: moving past an end
+    /// stops the machine instead.
+    pub(super) fn move_data_p(&mut self, right: bool) -> Option<HaltReason> {
+        let addr = self.data_address();
+        if !right
+            && addr == 0
+            && (self.strict_bounds || self.address_width == AddressWidth::Growable)
+        {
+            return Some(HaltReason::PointerUnderflow);
+        }
+        if right && self.strict_bounds && addr == self.last_address() {
+            return Some(HaltReason::PointerOverflow);
+        }
+        if right && self.address_width == AddressWidth::Growable && !self.grow_to(addr as usize + 2)
+        {
+            return Some(HaltReason::PointerOverflow);
+        }
+        let addr = self.offset_address(if right { 1 } else { -1 });
+        self.set_data_address(addr);
+        None
+    }
+
+    /// The highest address the data pointer can reach.
+    fn last_address(&self) -> u32 {
+        match self.address_width {
+            AddressWidth::Bits16 => self.data_tape.len() as u32 - 1,
+            AddressWidth::Bits32 | AddressWidth::Growable => u32::MAX,
+        }
+    }
+
+    /// The value of the cell at `addr`.
+    pub(super) fn cell_at(&self, addr: u32) -> C {
+        if (addr as usize) < self.data_tape.len() {
+            return self.data_tape[addr as usize];
+        }
+        match self.far_cells.get(&addr) {
+            Some(&value) => value,
+            None => self.far_init(addr),
+        }
+    }
+
+    /// The value of the current cell.
+    pub(super) fn cell(&self) -> C {
+        if self.data_page == 0 && (self.data_p as usize) < self.data_tape.len() {
+            self.data_tape[self.data_p as usize]
+        } else {
+            self.cell_at(self.data_address())
+        }
+    }
+
+    /// The current cell, to be changed.
+    pub(super) fn cell_mut(&mut self) -> &mut C {
+        self.cell_written = true;
+        let addr = self.data_address();
+        if (addr as usize) < self.data_tape.len() {
+            return &mut self.data_tape[addr as usize];
+        }
+        let init = self.far_init(addr);
+        self.far_cells.entry(addr).or_insert(init)
+    }
+
+    /// The value a cell past the end of the data tape starts with, following the init pattern. Random
+    /// cells take their value from the seed and their address.
+    fn far_init(&self, addr: u32) -> C {
+        match self.init_pattern {
+            InitPattern::Zero => C::default(),
+            InitPattern::Fill(value) => C::from_u64(u64::from(value)),
+            InitPattern::Random { seed } => {
+                C::from_u64(SplitMix64::new(seed ^ u64::from(addr)).next_u64())
+            }
+        }
+    }
+}
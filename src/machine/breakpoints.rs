@@ -0,0 +1,83 @@
+//! Stopping the machine at chosen points, for debugging.
+use super::SBrainVM;
+use crate::cell::Cell;
+use crate::{MAddr, MData};
+use alloc::collections::{BTreeMap, BTreeSet};
+
+/// When a watchpoint on a data cell stops the machine. See `SBrainVM::set_watchpoint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch<C: Cell = MData> {
+    /// Whenever the cell is written, even if its value doesn't change.
+    Write,
+    /// Whenever the given value is written to the cell.
+    Value(C),
+}
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// Stop running with `HaltReason::Breakpoint` before executing the instruction at `addr`.
+    /// A run never stops at the instruction it starts on, so calling `run` again resumes past the
+    /// breakpoint.
+    pub fn set_breakpoint(&mut self, addr: MAddr) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Remove the breakpoint at `addr`, returning whether there was one.
+    pub fn clear_breakpoint(&mut self, addr: MAddr) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Remove every breakpoint.
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// The addresses with breakpoints, in ascending order.
+    pub fn breakpoints(&self) -> &BTreeSet<MAddr> {
+        &self.breakpoints
+    }
+
+    /// Whether the instruction about to be executed has a breakpoint.
+    pub(super) fn at_breakpoint(&self) -> bool {
+        !self.breakpoints.is_empty() && self.breakpoints.contains(&self.inst_p)
+    }
+
+    /// Stop running with `HaltReason::Watchpoint` after an instruction writes the data cell at
+    /// `addr` as described by `watch`, replacing any watchpoint already on that cell. The
+    /// instruction which wrote the cell is counted as a cycle. With a wider `AddressWidth`, only
+    /// the cells on the data tape can be watched.
+    pub fn set_watchpoint(&mut self, addr: MAddr, watch: Watch<C>) {
+        self.watchpoints.insert(addr, watch);
+    }
+
+    /// Remove the watchpoint on the cell at `addr`, returning whether there was one.
+    pub fn clear_watchpoint(&mut self, addr: MAddr) -> bool {
+        self.watchpoints.remove(&addr).is_some()
+    }
+
+    /// Remove every watchpoint.
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// The watched cells and what each is watched for, in ascending order of address.
+    pub fn watchpoints(&self) -> &BTreeMap<MAddr, Watch<C>> {
+        &self.watchpoints
+    }
+
+    /// The address of the watched cell the last instruction wrote as its watchpoint describes,
+    /// if it did.
+    pub(super) fn watchpoint_hit(&self) -> Option<MAddr> {
+        if self.watchpoints.is_empty()
+            || !self.cell_written
+            || self.data_page != 0
+            || self.data_p as usize >= self.data_tape.len()
+        {
+            return None;
+        }
+        match self.watchpoints.get(&self.data_p) {
+            Some(&Watch::Write) => Some(self.data_p),
+            Some(&Watch::Value(value)) if value == self.cell() => Some(self.data_p),
+            _ => None,
+        }
+    }
+}
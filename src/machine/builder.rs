@@ -0,0 +1,128 @@
+//! Building machines one option at a time.
+use super::{AddressWidth, InitPattern, OutputTape, SBrainVM, VmConfig};
+use crate::cell::Cell;
+use crate::error::SBrainError;
+use crate::io::{Read, Write};
+use crate::MData;
+use alloc::vec::Vec;
+
+/// Builds an `SBrainVM`, as returned by `SBrainVM::builder`. Every option has a default, so only
+/// the ones that matter need to be set:
+///
+/// ```
+/// # use sbrain::*;
+/// let mut machine = SBrainVM::builder()
+///     .program(&source_to_tape("[.>]@"))
+///     .data(b"Hi")
+///     .buffered()
+///     .cycle_limit(1000)
+///     .build()
+///     .expect("Could not build machine");
+/// machine.run(None).expect("I/O failed");
+///
+/// assert_eq!(machine.output_string(), "Hi");
+/// ```
+///
+/// For cells wider than `MData`, start from `VmBuilder::<u32>::new()` (or another `Cell` type)
+/// instead.
+pub struct VmBuilder<'a, C: Cell = MData> {
+    input: Option<&'a mut dyn Read>,
+    output: Option<OutputTape<'a>>,
+    program: Vec<u8>,
+    data: Vec<C>,
+    config: VmConfig,
+    init_pattern: InitPattern,
+    address_width: AddressWidth,
+    cycle_limit: Option<u32>,
+}
+
+impl<'a, C: Cell> VmBuilder<'a, C> {
+    /// A builder for a machine with no input, no output, an empty program and a zeroed data tape.
+    pub fn new() -> VmBuilder<'a, C> {
+        VmBuilder {
+            input: None,
+            output: None,
+            program: Vec::new(),
+            data: Vec::new(),
+            config: VmConfig::default(),
+            init_pattern: InitPattern::Zero,
+            address_width: AddressWidth::Bits16,
+            cycle_limit: None,
+        }
+    }
+
+    /// Read input from `input`. Without input, all reads read 0.
+    pub fn input(mut self, input: &'a mut dyn Read) -> VmBuilder<'a, C> {
+        self.input = Some(input);
+        self
+    }
+
+    /// Write output to `output`. Without output, all writes are discarded.
+    pub fn output(mut self, output: &'a mut dyn Write) -> VmBuilder<'a, C> {
+        self.output = Some(OutputTape::Writer(output));
+        self
+    }
+
+    /// Collect output in the machine, as `SBrainVM::new_buffered` does, instead of writing it.
+    pub fn buffered(mut self) -> VmBuilder<'a, C> {
+        self.output = Some(OutputTape::Buffer(Vec::new()));
+        self
+    }
+
+    /// Load `program` onto the exec tape.
+    pub fn program(mut self, program: &[u8]) -> VmBuilder<'a, C> {
+        self.program = program.to_vec();
+        self
+    }
+
+    /// Load `data` onto the start of the data tape, after it is filled with the init pattern.
+    pub fn data(mut self, data: &[C]) -> VmBuilder<'a, C> {
+        self.data = data.to_vec();
+        self
+    }
+
+    /// Choose the tape and stack sizes; see `VmConfig`.
+    pub fn config(mut self, config: VmConfig) -> VmBuilder<'a, C> {
+        self.config = config;
+        self
+    }
+
+    /// Fill the data tape with `pattern`, as `SBrainVM::init_data` does.
+    pub fn init_pattern(mut self, pattern: InitPattern) -> VmBuilder<'a, C> {
+        self.init_pattern = pattern;
+        self
+    }
+
+    /// Set the width of the data pointer, as `SBrainVM::with_address_width` does.
+    pub fn address_width(mut self, width: AddressWidth) -> VmBuilder<'a, C> {
+        self.address_width = width;
+        self
+    }
+
+    /// Give the machine a cycle limit for runs that don't set their own; see
+    /// `SBrainVM::set_cycle_limit`.
+    pub fn cycle_limit(mut self, cycles: u32) -> VmBuilder<'a, C> {
+        self.cycle_limit = Some(cycles);
+        self
+    }
+
+    /// Build the machine. Fails if the configuration is invalid, or if the program or data do
+    /// not fit on their tapes.
+    pub fn build(self) -> Result<SBrainVM<'a, C>, SBrainError> {
+        let mut vm = SBrainVM::new_wide(self.input, None, &self.program, self.config)?
+            .with_address_width(self.address_width);
+        vm.output_t = self.output;
+        if self.init_pattern != InitPattern::Zero {
+            vm.init_data(self.init_pattern);
+        }
+        vm.load_data(&self.data)?;
+        vm.set_cycle_limit(self.cycle_limit);
+        Ok(vm)
+    }
+}
+
+impl<'a, C: Cell> Default for VmBuilder<'a, C> {
+    fn default() -> VmBuilder<'a, C> {
+        VmBuilder::new()
+    }
+}
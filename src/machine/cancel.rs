@@ -0,0 +1,58 @@
+//! Stopping a running machine from another thread.
+use super::SBrainVM;
+use crate::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A flag which stops a machine from outside, for example to abort a runaway program running on
+/// a worker thread. Clones share the flag, so keep one and give another to the machine with
+/// `SBrainVM::set_cancel_token`.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// A token which has not been cancelled.
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Stop every machine holding this token at its next instruction boundary.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether `cancel` has been called since the token was made or last reset.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clear the flag, so machines holding the token can run again.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Use an existing flag as a token; setting the flag cancels.
+impl From<Arc<AtomicBool>> for CancelToken {
+    fn from(flag: Arc<AtomicBool>) -> CancelToken {
+        CancelToken(flag)
+    }
+}
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// Stop running with `HaltReason::Cancelled` once `token` is cancelled, or stop checking a
+    /// token with None. The token is checked before each instruction, so the machine stops
+    /// between instructions; a read or write which blocks is not interrupted. While the token is
+    /// cancelled, every run stops before running anything.
+    pub fn set_cancel_token(&mut self, token: Option<CancelToken>) {
+        self.cancel = token;
+    }
+
+    /// Whether the machine's cancel token has been cancelled.
+    pub(super) fn cancelled(&self) -> bool {
+        match self.cancel {
+            Some(ref token) => token.is_cancelled(),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Decoding opcodes into the instructions the machine dispatches on.
+
+/// An instruction, decoded from its opcode. Opcodes which do nothing all decode to `Nop`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Instruction {
+    /// `<`
+    Left,
+    /// `>`
+    Right,
+    /// `-`
+    Decrement,
+    /// `+`
+    Increment,
+    /// `[`
+    LoopStart,
+    /// `]`
+    LoopEnd,
+    /// `.`
+    Output,
+    /// `,`
+    Input,
+    /// `{`
+    Push,
+    /// `}`
+    Pop,
+    /// `(`
+    StoreAux,
+    /// `)`
+    LoadAux,
+    /// `^`
+    ClearAux,
+    /// `!`
+    Not,
+    /// `&`
+    And,
+    /// `@`
+    Halt,
+    /// `/`
+    RotateLeft,
+    /// `\`
+    RotateRight,
+    /// `%`
+    Random,
+    /// `~`
+    Restart,
+    /// `:`
+    ReadCode,
+    /// `;`
+    WriteCode,
+    /// `_`
+    Equal,
+    /// `'`
+    Less,
+    /// `"`
+    Greater,
+    /// `?`
+    SkipIfZero,
+    /// `$`
+    Channel,
+    Nop,
+}
+
+impl Instruction {
+    /// The instruction the given opcode stands for.
+    pub(super) fn decode(opcode: u8) -> Instruction {
+        match opcode {
+            0 => Instruction::Left,
+            1 => Instruction::Right,
+            2 => Instruction::Decrement,
+            3 => Instruction::Increment,
+            4 => Instruction::LoopStart,
+            5 => Instruction::LoopEnd,
+            6 => Instruction::Output,
+            7 => Instruction::Input,
+            8 => Instruction::Push,
+            9 => Instruction::Pop,
+            10 => Instruction::StoreAux,
+            11 => Instruction::LoadAux,
+            12 => Instruction::ClearAux,
+            13 => Instruction::Not,
+            14 => Instruction::And,
+            15 => Instruction::Halt,
+            16 => Instruction::RotateLeft,
+            17 => Instruction::RotateRight,
+            18 => Instruction::Random,
+            19 => Instruction::Restart,
+            20 => Instruction::ReadCode,
+            21 => Instruction::WriteCode,
+            22 => Instruction::Equal,
+            23 => Instruction::Less,
+            24 => Instruction::Greater,
+            25 => Instruction::SkipIfZero,
+            26 => Instruction::Channel,
+            _ => Instruction::Nop,
+        }
+    }
+}
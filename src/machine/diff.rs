@@ -0,0 +1,87 @@
+//! Comparing the states of two machines.
+use super::SBrainVM;
+use crate::cell::Cell;
+use crate::MData;
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+/// A data cell which holds different values in two machine states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellChange<C: Cell = MData> {
+    /// The cell's full address, including any bits beyond `MAddr`.
+    pub addr: u32,
+    pub old: C,
+    pub new: C,
+}
+
+/// The differences between two machine states, as produced by `SBrainVM::diff`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StateDiff<C: Cell = MData> {
+    /// Every data cell that differs, in address order. Unchanged cells are not listed.
+    pub cells: Vec<CellChange<C>>,
+    /// How far the data pointer moved (new minus old), by its full address.
+    pub data_p_delta: i64,
+    /// How far `inst_p` moved (new minus old).
+    pub inst_p_delta: i32,
+    /// The old and new values of `auxi_r`, if it changed.
+    pub auxi_r: Option<(C, C)>,
+    /// Values on the old stack above the part both stacks share, bottom first.
+    pub stack_popped: Vec<C>,
+    /// Values on the new stack above the part both stacks share, bottom first.
+    pub stack_pushed: Vec<C>,
+}
+
+impl<C: Cell> StateDiff<C> {
+    /// Returns true if the two states were identical.
+    pub fn is_empty(&self) -> bool {
+        *self == StateDiff::default()
+    }
+}
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// Compare this machine (the old state) with `other` (the new state). Every cell either
+    /// machine holds is compared, whether on its data tape or stored sparsely past the end of it,
+    /// so tapes of different lengths can be compared; a cell one machine does not hold counts as
+    /// its initial value. I/O and configuration are not compared.
+    pub fn diff(&self, other: &SBrainVM<C>) -> StateDiff<C> {
+        let dense = self.data_tape.len().max(other.data_tape.len());
+        let far: BTreeSet<u32> = self
+            .far_cells
+            .keys()
+            .chain(other.far_cells.keys())
+            .cloned()
+            .filter(|&addr| addr as usize >= dense)
+            .collect();
+        let cells = (0..dense as u32)
+            .chain(far)
+            .filter_map(|addr| {
+                let (old, new) = (self.cell_at(addr), other.cell_at(addr));
+                if old != new {
+                    Some(CellChange { addr, old, new })
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let shared = self
+            .data_stack
+            .iter()
+            .zip(other.data_stack.iter())
+            .take_while(|&(old, new)| old == new)
+            .count();
+
+        StateDiff {
+            cells,
+            data_p_delta: i64::from(other.data_address()) - i64::from(self.data_address()),
+            inst_p_delta: i32::from(other.inst_p) - i32::from(self.inst_p),
+            auxi_r: if self.auxi_r != other.auxi_r {
+                Some((self.auxi_r, other.auxi_r))
+            } else {
+                None
+            },
+            stack_popped: self.data_stack[shared..].to_vec(),
+            stack_pushed: other.data_stack[shared..].to_vec(),
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! Running programs faster by fusing common runs of instructions into single operations.
+use super::decode::Instruction;
+use super::{AddressWidth, ArithMode, BracketMode, HaltReason, LoopMode, SBrainVM};
+use crate::cell::Cell;
+use crate::io;
+use crate::MAddr;
+use alloc::vec::Vec;
+
+/// What the machine can do in one go starting at an address of the exec tape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum FusedOp {
+    /// Nothing to fuse; the instruction, decoded from `opcode`, is run on its own.
+    Single {
+        opcode: u8,
+        instruction: Instruction,
+    },
+    /// `[` (if `forward`) or `]`, with the address of the matching bracket if it has one.
+    Jump {
+        forward: bool,
+        target: Option<MAddr>,
+    },
+    /// A run of `+` and `-`, adding `plus - minus` to the current cell.
+    Add { plus: u32, minus: u32 },
+    /// A run of `<` and `>`, moving the data pointer `right - left` cells.
+    Move { right: u32, left: u32 },
+    /// `[-]` (or `[+]` if `up`), which loops until the current cell is zero.
+    Clear { up: bool },
+}
+
+/// Compile a program into one operation per address, each covering as much of the program
+/// from that address on as it can. Keeping an operation for every address, not just where a run
+/// begins, lets execution jump into the middle of a run.
+pub(super) fn compile(program: &[u8]) -> Vec<FusedOp> {
+    let targets = bracket_targets(program);
+    let mut ops = Vec::with_capacity(program.len());
+    let mut next = None;
+    for addr in (0..program.len()).rev() {
+        let op = match program[addr] {
+            0 | 1 => {
+                let (right, left) = match next {
+                    Some(FusedOp::Move { right, left }) => (right, left),
+                    _ => (0, 0),
+                };
+                let moved = (program[addr] == 1) as u32;
+                FusedOp::Move {
+                    right: right + moved,
+                    left: left + 1 - moved,
+                }
+            }
+            2 | 3 => {
+                let (plus, minus) = match next {
+                    Some(FusedOp::Add { plus, minus }) => (plus, minus),
+                    _ => (0, 0),
+                };
+                let added = (program[addr] == 3) as u32;
+                FusedOp::Add {
+                    plus: plus + added,
+                    minus: minus + 1 - added,
+                }
+            }
+            4 => match program.get(addr + 1..addr + 3) {
+                Some(&[code, 5]) if code == 2 || code == 3 => FusedOp::Clear { up: code == 3 },
+                _ => FusedOp::Jump {
+                    forward: true,
+                    target: targets[addr],
+                },
+            },
+            5 => FusedOp::Jump {
+                forward: false,
+                target: targets[addr],
+            },
+            opcode => FusedOp::Single {
+                opcode,
+                instruction: Instruction::decode(opcode),
+            },
+        };
+        ops.push(op);
+        next = Some(op);
+    }
+    ops.reverse();
+    ops
+}
+
+/// The address of the bracket matching each bracket of `program`, as `matching_bracket` would
+/// find it, or None for other opcodes and unmatched brackets.
+fn bracket_targets(program: &[u8]) -> Vec<Option<MAddr>> {
+    let mut targets = vec![None; program.len()];
+    let mut open = Vec::new();
+    for (addr, &opcode) in program.iter().enumerate() {
+        match opcode {
+            4 => open.push(addr),
+            5 => {
+                if let Some(start) = open.pop() {
+                    targets[start] = Some(addr as MAddr);
+                    targets[addr] = Some(start as MAddr);
+                }
+            }
+            _ => {}
+        }
+    }
+    targets
+}
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// Enable or disable instruction fusion. When enabled, `run` compiles the program into
+    /// operations which each do the work of a run of `+` and `-`, a run of `<` and `>`, or a
+    /// `[-]` loop at once, and executes those instead of one instruction at a time. Programs which
+    /// spend most of their time in such runs go several times faster.
+    ///
+    /// The results, including cycle counts and `stats`, are exactly those of the plain
+    /// interpreter. Fusion is only used while nothing needs to see each instruction: it is skipped
+    /// by `run_observed` and `run_metered`, and whenever breakpoints, watchpoints, coverage, loop
+    /// counting, undo, no-progress or loop detection, self-modifying code, saturating arithmetic,
+    /// strict bounds or a growable tape are in use. It is enabled by default.
+    pub fn set_fusion(&mut self, enabled: bool) {
+        self.fusion = enabled;
+    }
+
+    /// Forget the compiled program, because the exec tape has changed.
+    pub(super) fn discard_bytecode(&mut self) {
+        self.bytecode = None;
+        #[cfg(feature = "jit")]
+        {
+            self.jit = super::jit::Jit::Cold;
+            self.jit_warmup = 0;
+        }
+    }
+
+    /// Whether `run` can use the compiled program.
+    pub(super) fn can_fuse(&self) -> bool {
+        self.fusion
+            && self.arith_mode == ArithMode::Wrapping
+            && self.address_width != AddressWidth::Growable
+            && !self.strict_bounds
+            && !self.self_modifying
+            && self.undo.is_none()
+            && self.coverage.is_none()
+            && self.loop_counts.is_none()
+            && self.breakpoints.is_empty()
+            && self.watchpoints.is_empty()
+            && self.no_progress.is_none()
+            && self.loop_detection.is_none()
+    }
+
+    /// Run the compiled program, compiling it first if the exec tape has changed, as
+    /// `run_observed` runs with no observer.
+    pub(super) fn run_fused(&mut self, cycles: Option<u32>) -> io::Result<(u32, HaltReason)> {
+        let bytecode = match self.bytecode.take() {
+            Some(bytecode) => bytecode,
+            None => compile(&self.exec_tape),
+        };
+        let result = self.run_bytecode(&bytecode, cycles);
+        self.bytecode = Some(bytecode);
+        result
+    }
+
+    fn run_bytecode(
+        &mut self,
+        bytecode: &[FusedOp],
+        cycles: Option<u32>,
+    ) -> io::Result<(u32, HaltReason)> {
+        let mut done_cycles: u32 = 0;
+        // The clock is checked by operations run rather than cycles, since one operation can
+        // take any number of cycles
+        let mut done_ops: u32 = 0;
+
+        loop {
+            if self.cancelled() {
+                return Ok((done_cycles, HaltReason::Cancelled));
+            }
+            let budget = match cycles {
+                Some(n) => u64::from(n.saturating_sub(done_cycles)),
+                None => u64::MAX,
+            };
+            // Once execution has wrapped around, `step` stops a machine which halts at the end
+            // of its program
+            let op = if self.halt_at_program_end && self.wrapped {
+                None
+            } else {
+                bytecode.get(self.inst_p as usize).cloned()
+            };
+            // An operation which doesn't fit in the budget is run one instruction at a time, so
+            // that the run stops at exactly the same cycle as it would without fusion
+            let ran = match op.and_then(|op| self.apply_fused(op, budget)) {
+                Some(ran) => ran,
+                None => {
+                    let halt = match op {
+                        Some(FusedOp::Single {
+                            opcode,
+                            instruction,
+                        }) => self.step_decoded(opcode, instruction)?,
+                        _ => self.step()?,
+                    };
+                    match halt {
+                        Some(reason) => return Ok((done_cycles, reason)),
+                        None => 1,
+                    }
+                }
+            };
+
+            done_cycles = done_cycles.saturating_add(ran.min(u64::from(u32::MAX)) as u32);
+            done_ops = done_ops.wrapping_add(1);
+            if let Some(n) = cycles {
+                if done_cycles >= n {
+                    return Ok((done_cycles, HaltReason::CycleLimit));
+                }
+            }
+            if self.timed_out(done_ops) {
+                return Ok((done_cycles, HaltReason::TimedOut));
+            }
+        }
+    }
+
+    /// Carry out `op` if it takes no more than `budget` cycles, returning the number of cycles it
+    /// took, or None if it must be run one instruction at a time instead.
+    fn apply_fused(&mut self, op: FusedOp, budget: u64) -> Option<u64> {
+        let (len, cycles) = match op {
+            FusedOp::Single { .. } => return None,
+            FusedOp::Jump { forward, target } => {
+                // The jump stack and unmatched brackets in strict mode are left to `step`
+                if self.loop_mode != LoopMode::Rescan {
+                    return None;
+                }
+                let jump = (self.cell() == C::default()) == forward;
+                match target {
+                    Some(target) if jump => self.inst_p = target,
+                    None if self.bracket_mode == BracketMode::Strict => return None,
+                    _ => {}
+                }
+                self.record_instructions(if forward { 4 } else { 5 }, 1);
+                (1, 1)
+            }
+            FusedOp::Add { plus, minus } => {
+                let len = u64::from(plus) + u64::from(minus);
+                if len > budget {
+                    return None;
+                }
+                let delta =
+                    C::from_u64(u64::from(plus)).wrapping_sub(C::from_u64(u64::from(minus)));
+                let cell = self.cell_mut();
+                *cell = cell.wrapping_add(delta);
+                self.record_instructions(3, u64::from(plus));
+                self.record_instructions(2, u64::from(minus));
+                (len, len)
+            }
+            FusedOp::Move { right, left } => {
+                let len = u64::from(right) + u64::from(left);
+                if len > budget {
+                    return None;
+                }
+                let addr = self.offset_address(right as i32 - left as i32);
+                self.set_data_address(addr);
+                self.record_instructions(1, u64::from(right));
+                self.record_instructions(0, u64::from(left));
+                (len, len)
+            }
+            FusedOp::Clear { up } => {
+                let cell = self.cell();
+                if cell == C::default() {
+                    // The loop is skipped, taking just the cycle for the `[`
+                    self.record_instructions(4, 1);
+                    return Some(self.advance(3, 1));
+                }
+                // Each time around the loop runs the `-` (or `+`) and the `]`
+                let iterations = if up {
+                    C::default().wrapping_sub(cell).to_u64()
+                } else {
+                    cell.to_u64()
+                };
+                let cycles = iterations.saturating_mul(2).saturating_add(1);
+                if cycles > budget {
+                    return None;
+                }
+                *self.cell_mut() = C::default();
+                self.record_instructions(4, 1);
+                self.record_instructions(if up { 3 } else { 2 }, iterations);
+                self.record_instructions(5, iterations);
+                (3, cycles)
+            }
+        };
+        Some(self.advance(len, cycles))
+    }
+
+    /// Move past an operation `len` instructions long which took `cycles` cycles, returning
+    /// `cycles`.
+    fn advance(&mut self, len: u64, cycles: u64) -> u64 {
+        self.halted = false;
+        let next = self.inst_p as usize + len as usize;
+        if next >= self.exec_len {
+            self.inst_p = 0;
+            self.wrapped = true;
+        } else {
+            self.inst_p = next as MAddr;
+        }
+        cycles
+    }
+}
@@ -0,0 +1,512 @@
+//! Compiling hot programs to native code with Cranelift.
+use super::fusion::{compile as fuse, FusedOp};
+use super::{AddressWidth, HaltReason, LoopMode, SBrainVM, DEADLINE_INTERVAL};
+use crate::cell::Cell;
+use crate::io;
+use crate::MAddr;
+use cranelift_codegen::entity::EntityRef;
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Block, InstBuilder, MemFlags, Type, Value};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Switch, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+use std::mem;
+
+/// The registers native code works on, passed to it by pointer. Native code runs until its cycle
+/// count reaches its budget or it reaches an instruction it can't run, then returns with the
+/// registers describing where it stopped.
+#[repr(C)]
+struct Registers {
+    tape: *mut u8,
+    /// Two counters per address: how often the operation there ran, and for a clear loop, how
+    /// many times around the loop it went in total
+    hits: *mut u64,
+    data_p: u64,
+    inst_p: u64,
+    cycles: u64,
+    budget: u64,
+}
+
+const TAPE: i32 = 0;
+const HITS: i32 = 8;
+const DATA_P: i32 = 16;
+const INST_P: i32 = 24;
+const CYCLES: i32 = 32;
+const BUDGET: i32 = 40;
+
+/// A program compiled to native code.
+pub(super) struct JitCode {
+    /// Owns the code; it is freed when this is dropped
+    module: Option<JITModule>,
+    entry: unsafe extern "C" fn(*mut Registers),
+    ops: Vec<FusedOp>,
+    hits: Vec<u64>,
+    tape_len: usize,
+}
+
+impl Drop for JitCode {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // Safe because the only pointer to the code is `entry`, which goes with it
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+/// Whether a machine's program has been compiled to native code yet.
+pub(super) enum Jit {
+    /// Not compiled yet; the program runs in the interpreter until it is hot.
+    Cold,
+    Compiled(Box<JitCode>),
+    /// Compiling failed, for example because Cranelift doesn't support this host.
+    Unavailable,
+}
+
+/// Compile `program` for a data tape of `tape_len` cells of `cell_bytes` bytes each, or return
+/// None if the host can't run the result.
+fn compile(program: &[u8], tape_len: usize, cell_bytes: usize) -> Option<JitCode> {
+    let cell_type = Type::int_with_byte_size(cell_bytes as u16)?;
+    let mut module = JITModule::new(JITBuilder::new(default_libcall_names()).ok()?);
+    if module.target_config().pointer_type() != types::I64 {
+        return None;
+    }
+    let ops = fuse(program);
+
+    let mut ctx = module.make_context();
+    ctx.func.signature.params.push(AbiParam::new(types::I64));
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        Emitter {
+            builder: &mut builder,
+            program,
+            ops: &ops,
+            tape_len: tape_len as i64,
+            cell_type,
+            cell_shift: cell_bytes.trailing_zeros() as i64,
+        }
+        .emit();
+        builder.seal_all_blocks();
+        builder.finalize();
+    }
+
+    let id = module
+        .declare_anonymous_function(&ctx.func.signature)
+        .ok()?;
+    module.define_function(id, &mut ctx).ok()?;
+    module.clear_context(&mut ctx);
+    module.finalize_definitions().ok()?;
+    let code = module.get_finalized_function(id);
+    Some(JitCode {
+        module: Some(module),
+        entry: unsafe { mem::transmute::<*const u8, unsafe extern "C" fn(*mut Registers)>(code) },
+        ops,
+        hits: vec![0; 2 * program.len()],
+        tape_len,
+    })
+}
+
+const DATA_P_VAR: usize = 0;
+const CYCLES_VAR: usize = 1;
+
+/// Builds the native function, with one block for each address of the program.
+struct Emitter<'b, 'f: 'b, 'p> {
+    builder: &'b mut FunctionBuilder<'f>,
+    program: &'p [u8],
+    ops: &'p [FusedOp],
+    tape_len: i64,
+    cell_type: Type,
+    cell_shift: i64,
+}
+
+/// Values loaded once on entry, which every block can use.
+struct Entry {
+    tape: Value,
+    hits: Value,
+    budget: Value,
+    blocks: Vec<Block>,
+    exit: Block,
+}
+
+impl<'b, 'f, 'p> Emitter<'b, 'f, 'p> {
+    fn emit(mut self) {
+        let b = &mut self.builder;
+        let start = b.create_block();
+        b.append_block_params_for_function_params(start);
+        let exit = b.create_block();
+        b.append_block_param(exit, types::I64);
+        let stray = b.create_block();
+        let blocks: Vec<Block> = self.program.iter().map(|_| b.create_block()).collect();
+        let data_p = Variable::new(DATA_P_VAR);
+        let cycles = Variable::new(CYCLES_VAR);
+        b.declare_var(data_p, types::I64);
+        b.declare_var(cycles, types::I64);
+
+        // Load the registers and go to the block for inst_p
+        b.switch_to_block(start);
+        let regs = b.block_params(start)[0];
+        let flags = MemFlags::trusted();
+        let tape = b.ins().load(types::I64, flags, regs, TAPE);
+        let hits = b.ins().load(types::I64, flags, regs, HITS);
+        let value = b.ins().load(types::I64, flags, regs, DATA_P);
+        b.def_var(data_p, value);
+        let value = b.ins().load(types::I64, flags, regs, CYCLES);
+        b.def_var(cycles, value);
+        let budget = b.ins().load(types::I64, flags, regs, BUDGET);
+        let inst_p = b.ins().load(types::I64, flags, regs, INST_P);
+        let mut switch = Switch::new();
+        for (addr, &block) in blocks.iter().enumerate() {
+            switch.set_entry(addr as u128, block);
+        }
+        switch.emit(b, inst_p, stray);
+
+        // Past the end of the program, there is nothing compiled
+        b.switch_to_block(stray);
+        b.ins().jump(exit, &[inst_p]);
+
+        // Store the registers and return
+        b.switch_to_block(exit);
+        let stopped_at = b.block_params(exit)[0];
+        let value = b.use_var(data_p);
+        b.ins().store(flags, value, regs, DATA_P);
+        let value = b.use_var(cycles);
+        b.ins().store(flags, value, regs, CYCLES);
+        b.ins().store(flags, stopped_at, regs, INST_P);
+        b.ins().return_(&[]);
+
+        let entry = Entry {
+            tape,
+            hits,
+            budget,
+            blocks,
+            exit,
+        };
+        for addr in 0..self.program.len() {
+            self.builder.switch_to_block(entry.blocks[addr]);
+            self.emit_op(&entry, addr);
+        }
+    }
+
+    fn emit_op(&mut self, entry: &Entry, addr: usize) {
+        match self.ops[addr] {
+            FusedOp::Add { plus, minus } => {
+                let len = u64::from(plus) + u64::from(minus);
+                self.check_budget(entry, addr, len as i64);
+                let cell = self.cell_address(entry);
+                let b = &mut self.builder;
+                let value = b.ins().load(self.cell_type, MemFlags::trusted(), cell, 0);
+                let delta = i64::from(plus).wrapping_sub(i64::from(minus));
+                let value = b.ins().iadd_imm(value, delta);
+                b.ins().store(MemFlags::trusted(), value, cell, 0);
+                self.count(entry, addr, None, len as i64);
+                self.goto(entry, addr + len as usize);
+            }
+            FusedOp::Move { right, left } => {
+                let len = u64::from(right) + u64::from(left);
+                self.check_budget(entry, addr, len as i64);
+                let delta = (i64::from(right) - i64::from(left)).rem_euclid(self.tape_len);
+                let b = &mut self.builder;
+                let data_p = b.use_var(Variable::new(DATA_P_VAR));
+                let moved = b.ins().iadd_imm(data_p, delta);
+                let past_end =
+                    b.ins()
+                        .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, moved, self.tape_len);
+                let wrapped = b.ins().iadd_imm(moved, -self.tape_len);
+                let data_p = b.ins().select(past_end, wrapped, moved);
+                b.def_var(Variable::new(DATA_P_VAR), data_p);
+                self.count(entry, addr, None, len as i64);
+                self.goto(entry, addr + len as usize);
+            }
+            FusedOp::Clear { up } => {
+                let cell = self.cell_address(entry);
+                let b = &mut self.builder;
+                let value = b.ins().load(self.cell_type, MemFlags::trusted(), cell, 0);
+                let iterations = if up { b.ins().ineg(value) } else { value };
+                let iterations = if self.cell_type == types::I64 {
+                    iterations
+                } else {
+                    b.ins().uextend(types::I64, iterations)
+                };
+                // The loop takes 2 * iterations + 1 cycles, which may not fit in 64 bits
+                let cycles = b.use_var(Variable::new(CYCLES_VAR));
+                let left = b.ins().isub(entry.budget, cycles);
+                let any_left = b.ins().icmp_imm(IntCC::NotEqual, left, 0);
+                let spare = b.ins().iadd_imm(left, -1);
+                let half = b.ins().ushr_imm(spare, 1);
+                let in_half = b
+                    .ins()
+                    .icmp(IntCC::UnsignedLessThanOrEqual, iterations, half);
+                let fits = b.ins().band(any_left, in_half);
+                self.branch_or_exit(entry, addr, fits);
+
+                let b = &mut self.builder;
+                let zero = b.ins().iconst(self.cell_type, 0);
+                b.ins().store(MemFlags::trusted(), zero, cell, 0);
+                let doubled = b.ins().ishl_imm(iterations, 1);
+                let cost = b.ins().iadd_imm(doubled, 1);
+                self.count_value(entry, addr, Some(iterations), cost);
+                self.goto(entry, addr + 3);
+            }
+            FusedOp::Jump { forward, target } => self.emit_bracket(entry, addr, forward, target),
+            FusedOp::Single { .. } => self.exit_at(entry, addr),
+        }
+    }
+
+    /// `[` jumps past its `]` if the cell is zero, and `]` back past its `[` if it isn't. An
+    /// unmatched bracket is left to the interpreter, which knows the machine's bracket mode.
+    fn emit_bracket(&mut self, entry: &Entry, addr: usize, forward: bool, target: Option<MAddr>) {
+        let target = match target {
+            Some(target) => target as usize,
+            None => return self.exit_at(entry, addr),
+        };
+        self.check_budget(entry, addr, 1);
+        let cell = self.cell_address(entry);
+        let b = &mut self.builder;
+        let value = b.ins().load(self.cell_type, MemFlags::trusted(), cell, 0);
+        let cond = if forward {
+            IntCC::Equal
+        } else {
+            IntCC::NotEqual
+        };
+        let jump = b.ins().icmp_imm(cond, value, 0);
+        self.count(entry, addr, None, 1);
+        let (taken, taken_args) = self.target(entry, target + 1);
+        let (next, next_args) = self.target(entry, addr + 1);
+        self.builder
+            .ins()
+            .brif(jump, taken, &taken_args, next, &next_args);
+    }
+
+    /// The address of the current cell.
+    fn cell_address(&mut self, entry: &Entry) -> Value {
+        let b = &mut self.builder;
+        let data_p = b.use_var(Variable::new(DATA_P_VAR));
+        let offset = b.ins().ishl_imm(data_p, self.cell_shift);
+        b.ins().iadd(entry.tape, offset)
+    }
+
+    /// Leave for the interpreter, unless the budget has room for `cost` more cycles.
+    fn check_budget(&mut self, entry: &Entry, addr: usize, cost: i64) {
+        let b = &mut self.builder;
+        let cycles = b.use_var(Variable::new(CYCLES_VAR));
+        let left = b.ins().isub(entry.budget, cycles);
+        let fits = b
+            .ins()
+            .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, left, cost);
+        self.branch_or_exit(entry, addr, fits);
+    }
+
+    /// Carry on in a new block if `fits`, and otherwise leave for the interpreter at `addr`.
+    fn branch_or_exit(&mut self, entry: &Entry, addr: usize, fits: Value) {
+        let b = &mut self.builder;
+        let next = b.create_block();
+        let at = b.ins().iconst(types::I64, addr as i64);
+        b.ins().brif(fits, next, &[], entry.exit, &[at]);
+        b.switch_to_block(next);
+    }
+
+    /// Count one run of the operation at `addr`, taking `cost` cycles.
+    fn count(&mut self, entry: &Entry, addr: usize, iterations: Option<Value>, cost: i64) {
+        let cost = self.builder.ins().iconst(types::I64, cost);
+        self.count_value(entry, addr, iterations, cost);
+    }
+
+    fn count_value(&mut self, entry: &Entry, addr: usize, iterations: Option<Value>, cost: Value) {
+        let b = &mut self.builder;
+        let flags = MemFlags::trusted();
+        let offset = (16 * addr) as i32;
+        let hits = b.ins().load(types::I64, flags, entry.hits, offset);
+        let hits = b.ins().iadd_imm(hits, 1);
+        b.ins().store(flags, hits, entry.hits, offset);
+        if let Some(iterations) = iterations {
+            let total = b.ins().load(types::I64, flags, entry.hits, offset + 8);
+            let total = b.ins().iadd(total, iterations);
+            b.ins().store(flags, total, entry.hits, offset + 8);
+        }
+        let cycles = b.use_var(Variable::new(CYCLES_VAR));
+        let cycles = b.ins().iadd(cycles, cost);
+        b.def_var(Variable::new(CYCLES_VAR), cycles);
+    }
+
+    /// The block which runs the instruction at `addr`, or the exit if it isn't compiled.
+    fn target(&mut self, entry: &Entry, addr: usize) -> (Block, Vec<Value>) {
+        match entry.blocks.get(addr) {
+            Some(&block) => (block, Vec::new()),
+            None => {
+                let at = self.builder.ins().iconst(types::I64, addr as i64);
+                (entry.exit, vec![at])
+            }
+        }
+    }
+
+    fn goto(&mut self, entry: &Entry, addr: usize) {
+        let (block, args) = self.target(entry, addr);
+        self.builder.ins().jump(block, &args);
+    }
+
+    fn exit_at(&mut self, entry: &Entry, addr: usize) {
+        let at = self.builder.ins().iconst(types::I64, addr as i64);
+        self.builder.ins().jump(entry.exit, &[at]);
+    }
+}
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// Compile the program to native code once it has run for `threshold` cycles, or never with
+    /// None. Programs which only run briefly aren't worth compiling, so they stay in the
+    /// interpreter. Compiled code is only used by `run`, and only when instruction fusion could
+    /// be (see `set_fusion`), the data pointer is 16 bits wide and loops use `LoopMode::Rescan`.
+    ///
+    /// Native code runs `+`, `-`, `<`, `>`, `[` and `]` itself, in fused operations, and passes
+    /// everything else, such as input and output, to the interpreter. Results, cycle counts and
+    /// `stats` are exactly those of the interpreter. Native code checks the cancel token every few
+    /// thousand cycles rather than before every instruction, and the time limit less often
+    /// still. Loading a program, or
+    /// rewriting the exec tape in any other way, discards the compiled code. Compiling falls back
+    /// to the interpreter if Cranelift doesn't support the host. It is disabled by default.
+    pub fn set_jit(&mut self, threshold: Option<u64>) {
+        self.jit_threshold = threshold;
+    }
+
+    /// Whether `run` may use compiled code.
+    fn can_jit(&self) -> bool {
+        self.jit_threshold.is_some()
+            && self.address_width == AddressWidth::Bits16
+            && self.loop_mode == LoopMode::Rescan
+            && matches!(mem::size_of::<C>(), 1 | 2 | 4 | 8)
+            && !matches!(self.jit, Jit::Unavailable)
+    }
+
+    /// Run the program as `run_fused` does, compiling it to native code once it is hot.
+    pub(super) fn run_compiled(&mut self, cycles: Option<u32>) -> io::Result<(u32, HaltReason)> {
+        if !self.can_jit() {
+            return self.run_fused(cycles);
+        }
+        let mut done_cycles = 0;
+        if let Jit::Cold = self.jit {
+            let threshold = self.jit_threshold.unwrap_or(0);
+            let left = threshold.saturating_sub(self.jit_warmup);
+            if left > 0 {
+                let warmup = left.min(u64::from(u32::MAX)) as u32;
+                let budget = cycles.map_or(warmup, |n| n.min(warmup));
+                let (ran, reason) = self.run_fused(Some(budget))?;
+                self.jit_warmup += u64::from(ran);
+                done_cycles = ran;
+                if reason != HaltReason::CycleLimit || cycles == Some(ran) {
+                    return Ok((ran, reason));
+                }
+            }
+            self.jit = match compile(
+                &self.exec_tape,
+                self.data_tape.len(),
+                mem::size_of::<C>(),
+            ) {
+                Some(code) => Jit::Compiled(Box::new(code)),
+                None => Jit::Unavailable,
+            };
+        }
+
+        let mut code = match mem::replace(&mut self.jit, Jit::Cold) {
+            Jit::Compiled(ref code) if code.tape_len != self.data_tape.len() => {
+                self.jit = Jit::Unavailable;
+                return self.run_fused(cycles.map(|n| n - done_cycles));
+            }
+            Jit::Compiled(code) => code,
+            other => {
+                self.jit = other;
+                return self.run_fused(cycles.map(|n| n - done_cycles));
+            }
+        };
+        let result = self.run_native(&mut code, cycles.map(|n| n - done_cycles));
+        self.record_hits(&mut code);
+        self.jit = Jit::Compiled(code);
+        let (ran, reason) = result?;
+        Ok((done_cycles.saturating_add(ran), reason))
+    }
+
+    fn run_native(
+        &mut self,
+        code: &mut JitCode,
+        cycles: Option<u32>,
+    ) -> io::Result<(u32, HaltReason)> {
+        let mut done_cycles: u32 = 0;
+        let mut slices: u32 = 0;
+
+        loop {
+            if self.cancelled() {
+                return Ok((done_cycles, HaltReason::Cancelled));
+            }
+            let left = cycles.map_or(u64::MAX, |n| u64::from(n - done_cycles));
+            let mut regs = Registers {
+                tape: self.data_tape.as_mut_ptr() as *mut u8,
+                hits: code.hits.as_mut_ptr(),
+                data_p: u64::from(self.data_p),
+                inst_p: u64::from(self.inst_p),
+                cycles: 0,
+                budget: left.min(u64::from(DEADLINE_INTERVAL)),
+            };
+            // Safe because the code was compiled for this program, cell type and tape length,
+            // and keeps data_p within the tape
+            unsafe { (code.entry)(&mut regs) };
+            if regs.cycles > 0 {
+                self.halted = false;
+            }
+            self.data_p = regs.data_p as MAddr;
+            if regs.inst_p as usize >= self.exec_len {
+                self.inst_p = 0;
+                self.wrapped = true;
+            } else {
+                self.inst_p = regs.inst_p as MAddr;
+            }
+            done_cycles = done_cycles.saturating_add(regs.cycles as u32);
+
+            // Native code stopped short of its budget, so the next instruction is one it can't
+            // run, or a fused operation with too few cycles left to run whole
+            if regs.cycles < regs.budget {
+                if let Some(reason) = self.step()? {
+                    return Ok((done_cycles, reason));
+                }
+                done_cycles = done_cycles.saturating_add(1);
+            }
+            if let Some(n) = cycles {
+                if done_cycles >= n {
+                    return Ok((done_cycles, HaltReason::CycleLimit));
+                }
+            }
+            slices = slices.wrapping_add(1);
+            if self.timed_out(slices) {
+                return Ok((done_cycles, HaltReason::TimedOut));
+            }
+        }
+    }
+
+    /// Add the counts kept by native code to `stats`, and clear them.
+    fn record_hits(&mut self, code: &mut JitCode) {
+        for (addr, counts) in code.hits.chunks_mut(2).enumerate() {
+            let (hits, iterations) = (counts[0], counts[1]);
+            if hits == 0 {
+                continue;
+            }
+            match code.ops[addr] {
+                FusedOp::Add { plus, minus } => {
+                    self.record_instructions(3, u64::from(plus) * hits);
+                    self.record_instructions(2, u64::from(minus) * hits);
+                }
+                FusedOp::Move { right, left } => {
+                    self.record_instructions(1, u64::from(right) * hits);
+                    self.record_instructions(0, u64::from(left) * hits);
+                }
+                FusedOp::Clear { up } => {
+                    self.record_instructions(4, hits);
+                    self.record_instructions(if up { 3 } else { 2 }, iterations);
+                    self.record_instructions(5, iterations);
+                }
+                FusedOp::Jump { forward, .. } => {
+                    self.record_instructions(if forward { 4 } else { 5 }, hits)
+                }
+                FusedOp::Single { opcode, .. } => self.record_instructions(opcode, hits),
+            }
+            counts[0] = 0;
+            counts[1] = 0;
+        }
+    }
+}
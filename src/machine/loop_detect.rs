@@ -0,0 +1,71 @@
+//! Detecting programs which have entered an endless loop.
+use super::SBrainVM;
+use crate::cell::Cell;
+
+/// Mix one value into an FNV-1a style hash.
+fn mix(hash: u64, value: u64) -> u64 {
+    (hash ^ value).wrapping_mul(0x0000_0100_0000_01B3)
+}
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// Enable or disable loop detection. When enabled, every `interval` cycles the machine hashes
+    /// its whole state: both pointers, the data tape, the stack, the registers, and the state of
+    /// the random number generator. If a hash repeats, the program has come back to a state it
+    /// was already in, so it will never halt, and `run` stops with `HaltReason::LoopDetected`.
+    ///
+    /// Unlike the no-progress heuristic, this looks at everything the program's future depends
+    /// on, so it only stops programs which really are stuck, barring a hash collision. Reading
+    /// input and rewriting the program with `;` change the machine in ways the hash doesn't see,
+    /// so they forget the states seen so far. A program stuck in a loop which outputs is still
+    /// stopped. Hashing the whole tape takes time, so a larger `interval` costs less but catches
+    /// loops later. It is disabled by default.
+    pub fn set_loop_detection(&mut self, interval: Option<u32>) {
+        self.loop_detection = interval;
+        self.forget_states();
+    }
+
+    /// Forget the states seen so far by loop detection.
+    pub(super) fn forget_states(&mut self) {
+        self.seen_states.clear();
+    }
+
+    /// Hash the state if one is due, returning true if the same state has been seen before.
+    pub(super) fn check_loop(&mut self, done_cycles: u32) -> bool {
+        match self.loop_detection {
+            Some(interval) if interval != 0 && done_cycles.is_multiple_of(interval) => {
+                let hash = self.state_hash();
+                !self.seen_states.insert(hash)
+            }
+            _ => false,
+        }
+    }
+
+    /// A hash of everything which decides what the machine does next, other than its input and
+    /// program.
+    fn state_hash(&self) -> u64 {
+        let mut hash = 0xCBF2_9CE4_8422_2325;
+        hash = mix(hash, u64::from(self.inst_p));
+        hash = mix(hash, u64::from(self.data_address()));
+        hash = mix(hash, self.auxi_r.to_u64());
+        hash = mix(hash, self.channel as u64);
+        hash = mix(hash, self.wrapped as u64);
+        if let Some(ref rng) = self.rng {
+            hash = mix(hash, rng.clone().next_u64());
+        }
+        for &cell in self.data_tape.iter() {
+            hash = mix(hash, cell.to_u64());
+        }
+        for (&addr, &cell) in &self.far_cells {
+            hash = mix(mix(hash, u64::from(addr)), cell.to_u64());
+        }
+        hash = mix(hash, self.data_stack.len() as u64);
+        for &value in &self.data_stack {
+            hash = mix(hash, value.to_u64());
+        }
+        hash = mix(hash, self.jump_stack.len() as u64);
+        for &addr in &self.jump_stack {
+            hash = mix(hash, u64::from(addr));
+        }
+        hash
+    }
+}
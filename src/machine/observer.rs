@@ -0,0 +1,143 @@
+//! Hooks into a running machine.
+use super::{HaltReason, SBrainVM};
+use crate::cell::Cell;
+use crate::io;
+use crate::MData;
+use alloc::vec::Vec;
+
+/// Whether an `Observer` lets the machine keep running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlFlow {
+    /// Keep running.
+    Continue,
+    /// Stop the machine with `HaltReason::Aborted`.
+    Break,
+}
+
+/// Something which watches a machine run, one cycle at a time. See `SBrainVM::run_observed`.
+///
+/// Only `on_cycle` must be implemented; the other callbacks do nothing unless overridden. For a
+/// cycle which reads or writes, `on_input` or `on_output` is called before `on_cycle`.
+pub trait Observer<C: Cell = MData> {
+    /// Called after each cycle with the state of the machine and the opcode just executed.
+    fn on_cycle(&mut self, vm: &SBrainVM<C>, op: u8) -> ControlFlow;
+
+    /// Called after `,` with the value it read into the current cell; 0 past the end of input.
+    fn on_input(&mut self, _vm: &SBrainVM<C>, _value: C) {}
+
+    /// Called after `.` with the value it output, whether or not the machine has somewhere to
+    /// write it.
+    fn on_output(&mut self, _vm: &SBrainVM<C>, _value: C) {}
+
+    /// Called once when the run stops, for any reason, with the reason `run_observed` returns.
+    /// Not called if the run fails with an I/O error.
+    fn on_halt(&mut self, _vm: &SBrainVM<C>, _reason: HaltReason) {}
+}
+
+/// The unit observer never stops the machine; running with it is the same as `SBrainVM::run`.
+impl<C: Cell> Observer<C> for () {
+    fn on_cycle(&mut self, _vm: &SBrainVM<C>, _op: u8) -> ControlFlow {
+        ControlFlow::Continue
+    }
+}
+
+/// Calls a function every `every` cycles.
+struct Checkpointer<F> {
+    every: u32,
+    done_cycles: u32,
+    f: F,
+}
+
+impl<C: Cell, F: FnMut(&SBrainVM<C>)> Observer<C> for Checkpointer<F> {
+    fn on_cycle(&mut self, vm: &SBrainVM<C>, _op: u8) -> ControlFlow {
+        self.done_cycles = self.done_cycles.saturating_add(1);
+        if self.every != 0 && self.done_cycles.is_multiple_of(self.every) {
+            (self.f)(vm);
+        }
+        ControlFlow::Continue
+    }
+}
+
+/// Stops the machine once a predicate holds.
+struct Until<F>(F);
+
+impl<C: Cell, F: FnMut(&SBrainVM<C>) -> bool> Observer<C> for Until<F> {
+    fn on_cycle(&mut self, vm: &SBrainVM<C>, _op: u8) -> ControlFlow {
+        if (self.0)(vm) {
+            ControlFlow::Break
+        } else {
+            ControlFlow::Continue
+        }
+    }
+}
+
+/// A value output by a program, and the cycle it was output on. See
+/// `SBrainVM::run_capturing_events`.
+pub type OutputEvent<C = MData> = (u32, C);
+
+/// Records every value output, along with the cycle it was output on.
+struct OutputRecorder<C> {
+    done_cycles: u32,
+    events: Vec<OutputEvent<C>>,
+}
+
+impl<C: Cell> Observer<C> for OutputRecorder<C> {
+    fn on_cycle(&mut self, _vm: &SBrainVM<C>, _op: u8) -> ControlFlow {
+        self.done_cycles = self.done_cycles.saturating_add(1);
+        ControlFlow::Continue
+    }
+
+    fn on_output(&mut self, _vm: &SBrainVM<C>, value: C) {
+        self.events.push((self.done_cycles, value));
+    }
+}
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// Run the machine like `run`, calling `f` with the machine every `every` cycles, for example
+    /// to save a snapshot or update a display. `f` only sees the machine, so it can't change how
+    /// the run goes. If `every` is 0, `f` is never called.
+    pub fn run_checkpointed(
+        &mut self,
+        every: u32,
+        cycles: Option<u32>,
+        f: impl FnMut(&SBrainVM<C>),
+    ) -> io::Result<(u32, HaltReason)> {
+        let mut checkpointer = Checkpointer {
+            every,
+            done_cycles: 0,
+            f,
+        };
+        self.run_observed(&mut checkpointer, cycles)
+    }
+
+    /// Run the machine like `run`, and also return every value the program output, each with the
+    /// cycle it was output on, counting from 0 at the start of this run. This catches changes in
+    /// when a program outputs, which comparing only the final output would miss. Values are
+    /// recorded whether or not the machine has somewhere to write them.
+    pub fn run_capturing_events(
+        &mut self,
+        cycles: Option<u32>,
+    ) -> io::Result<(Vec<OutputEvent<C>>, u32, HaltReason)> {
+        let mut recorder = OutputRecorder {
+            done_cycles: 0,
+            events: Vec::new(),
+        };
+        let (cycles, reason) = self.run_observed(&mut recorder, cycles)?;
+        Ok((recorder.events, cycles, reason))
+    }
+
+    /// Run the machine like `run`, checking `pred` after each cycle and stopping with
+    /// `HaltReason::PredicateMet` as soon as it returns true. The predicate is not checked before
+    /// the first cycle, and the cycle it fired on is counted.
+    pub fn run_until(
+        &mut self,
+        pred: impl FnMut(&SBrainVM<C>) -> bool,
+        max_cycles: Option<u32>,
+    ) -> io::Result<(u32, HaltReason)> {
+        let (cycles, reason) = self.run_observed(&mut Until(pred), max_cycles)?;
+        Ok(match reason {
+            HaltReason::Aborted => (cycles, HaltReason::PredicateMet),
+            reason => (cycles, reason),
+        })
+    }
+}
@@ -0,0 +1,149 @@
+//! Reusing the tapes of finished machines to build new ones cheaply.
+use super::{check_config, OutputTape, SBrainVM, VmConfig};
+use crate::cell::Cell;
+use crate::error::SBrainError;
+use crate::eval::EvalResult;
+use crate::io::Read;
+use crate::MData;
+use alloc::vec::Vec;
+
+/// The allocations of a machine which is no longer in use, cleared ready for the next one.
+struct Spare<C> {
+    data_tape: Vec<C>,
+    exec_tape: Vec<u8>,
+    data_stack: Vec<C>,
+    output: Vec<MData>,
+}
+
+/// A supply of machines for running many short programs, one after another. Building a machine
+/// allocates and zeroes its data tape, which for short programs takes far longer than running
+/// them; a pool instead hands out machines built around the tapes of machines it has reclaimed,
+/// which only need clearing.
+///
+/// Every machine from `get` is exactly like one built with `SBrainVM::new_buffered` and the
+/// pool's `VmConfig`, whatever was done with the machine its tapes came from.
+///
+/// ```
+/// # use sbrain::*;
+/// let mut pool: VmPool = VmPool::new(VmConfig::default()).unwrap();
+/// for source in &["+++.@", "++.@"] {
+///     let mut machine = pool.get(None, &source_to_tape(source)).unwrap();
+///     machine.run(Some(100)).unwrap();
+///     println!("{:?}", machine.output());
+///     pool.reclaim(machine);
+/// }
+/// assert_eq!(pool.spare(), 1);
+/// ```
+pub struct VmPool<C: Cell = MData> {
+    config: VmConfig,
+    spares: Vec<Spare<C>>,
+}
+
+impl<C: Cell> VmPool<C> {
+    /// Return a new, empty pool of machines with the tape and stack sizes given by `config`.
+    /// Fails if either tape length is out of range.
+    pub fn new(config: VmConfig) -> Result<VmPool<C>, SBrainError> {
+        check_config(&config)?;
+        Ok(VmPool {
+            config,
+            spares: Vec::new(),
+        })
+    }
+
+    /// The configuration the pool's machines are built with.
+    pub fn config(&self) -> &VmConfig {
+        &self.config
+    }
+
+    /// The number of reclaimed machines waiting to be reused.
+    pub fn spare(&self) -> usize {
+        self.spares.len()
+    }
+
+    /// Return a machine which collects its output in an internal buffer, as
+    /// `SBrainVM::new_buffered` does, with `program` loaded. A reclaimed machine's tapes are
+    /// reused if there are any; otherwise new ones are allocated. Fails if the program does not
+    /// fit on the exec tape.
+    pub fn get<'a>(
+        &mut self,
+        input: Option<&'a mut dyn Read>,
+        program: &[u8],
+    ) -> Result<SBrainVM<'a, C>, SBrainError> {
+        let spare = match self.spares.pop() {
+            Some(spare) => spare,
+            None => Spare {
+                data_tape: vec![C::default(); self.config.data_len],
+                exec_tape: Vec::new(),
+                data_stack: Vec::with_capacity(self.config.stack_capacity),
+                output: Vec::new(),
+            },
+        };
+        let mut machine = SBrainVM::from_tapes(
+            input,
+            None,
+            spare.data_tape,
+            spare.exec_tape,
+            self.config.exec_len,
+            spare.data_stack,
+        );
+        machine.output_t = Some(OutputTape::Buffer(spare.output));
+        machine.max_data_len = self.config.max_data_len;
+        match machine.load_program(program) {
+            Ok(()) => Ok(machine),
+            Err(e) => {
+                self.reclaim(machine);
+                Err(e)
+            }
+        }
+    }
+
+    /// Take back a machine which is no longer needed, so that its tapes can be reused by a later
+    /// call to `get`. Any machine built with the pool's `VmConfig` can be reclaimed, whether or not
+    /// it came from the pool; others are dropped.
+    pub fn reclaim(&mut self, machine: SBrainVM<C>) {
+        let SBrainVM {
+            mut data_tape,
+            mut exec_tape,
+            mut data_stack,
+            output_t,
+            exec_len,
+            ..
+        } = machine;
+        // A growable data tape may have been lengthened
+        data_tape.truncate(self.config.data_len);
+        if data_tape.len() != self.config.data_len || exec_len != self.config.exec_len {
+            return;
+        }
+        data_tape.iter_mut().for_each(|cell| *cell = C::default());
+        exec_tape.clear();
+        data_stack.clear();
+        let mut output = match output_t {
+            Some(OutputTape::Buffer(buffer)) => buffer,
+            _ => Vec::new(),
+        };
+        output.clear();
+        self.spares.push(Spare {
+            data_tape,
+            exec_tape,
+            data_stack,
+            output,
+        });
+    }
+
+    /// Run `program` on a machine from the pool, as `evaluate` does on a new one, and reclaim the
+    /// machine afterwards.
+    pub fn evaluate(
+        &mut self,
+        program: &[u8],
+        input: &[MData],
+        limit: Option<u32>,
+    ) -> Result<EvalResult, SBrainError> {
+        let mut input = input;
+        let mut machine = self.get(Some(&mut input), program)?;
+        // Reading from a slice and writing to a buffer cannot fail.
+        let result = machine.run(limit);
+        let result = result.map(|(cycles, reason)| EvalResult::collect(&machine, cycles, reason));
+        self.reclaim(machine);
+        Ok(result?)
+    }
+}
@@ -0,0 +1,127 @@
+//! Running a machine and summarizing the run.
+use super::{ControlFlow, HaltReason, Observer, SBrainVM};
+use crate::cell::Cell;
+use crate::io;
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+/// A summary of a call to `SBrainVM::run_report`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunReport {
+    /// The number of cycles run, as returned by `run`.
+    pub cycles: u32,
+    /// The reason the machine stopped, as returned by `run`.
+    pub halt_reason: HaltReason,
+    /// The most values on the data stack at any point in the run.
+    pub peak_stack: usize,
+    /// The number of distinct data cells the data pointer pointed at during the run, by their
+    /// full addresses.
+    pub cells_touched: usize,
+    /// The number of bytes of output delivered during the run. Output to a channel with nowhere
+    /// to write it, or by a machine with no output at all, is discarded and not counted.
+    pub output_len: usize,
+    /// The number of times the program read input during the run, including reads past the end
+    /// of the input.
+    pub input_reads: usize,
+    /// The number of distinct addresses of the loaded program executed during the run.
+    pub instructions_covered: usize,
+    /// The length of the loaded program at the end of the run.
+    pub program_len: usize,
+}
+
+impl RunReport {
+    /// The fraction of the loaded program's instructions executed during the run, from 0 to 1;
+    /// 0 for an empty program. This suits coverage-guided fitness shaping.
+    pub fn coverage(&self) -> f64 {
+        if self.program_len == 0 {
+            return 0.0;
+        }
+        self.instructions_covered as f64 / self.program_len as f64
+    }
+}
+
+/// Collects what a `RunReport` needs as the machine runs.
+struct Reporter {
+    peak_stack: usize,
+    /// The cells touched on the data tape as it was when the run started
+    touched: Vec<bool>,
+    /// The cells touched past the end of that tape
+    far_touched: BTreeSet<u32>,
+    cells_touched: usize,
+    output_len: usize,
+    input_reads: usize,
+}
+
+impl Reporter {
+    fn touch(&mut self, addr: u32) {
+        let new = match self.touched.get_mut(addr as usize) {
+            Some(touched) => {
+                let new = !*touched;
+                *touched = true;
+                new
+            }
+            None => self.far_touched.insert(addr),
+        };
+        if new {
+            self.cells_touched += 1;
+        }
+    }
+}
+
+impl<C: Cell> Observer<C> for Reporter {
+    fn on_cycle(&mut self, vm: &SBrainVM<C>, _op: u8) -> ControlFlow {
+        self.peak_stack = self.peak_stack.max(vm.data_stack.len());
+        self.touch(vm.data_address());
+        ControlFlow::Continue
+    }
+
+    fn on_output(&mut self, vm: &SBrainVM<C>, _value: C) {
+        if vm.output_delivered() {
+            self.output_len += vm.output_encoding.width();
+        }
+    }
+
+    fn on_input(&mut self, _vm: &SBrainVM<C>, _value: C) {
+        self.input_reads += 1;
+    }
+}
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// Run the machine like `run`, and report on the run, including how much input and output it
+    /// did. This does more work per cycle than `run`, which remains the fast path when only the
+    /// cycle count and halt reason are needed.
+    pub fn run_report(&mut self, cycles: Option<u32>) -> io::Result<RunReport> {
+        let mut reporter = Reporter {
+            peak_stack: self.data_stack.len(),
+            touched: vec![false; self.data_tape.len()],
+            far_touched: BTreeSet::new(),
+            cells_touched: 0,
+            output_len: 0,
+            input_reads: 0,
+        };
+        reporter.touch(self.data_address());
+
+        // Record this run's coverage separately, then add it to any coverage being kept
+        let kept_coverage = self.coverage.replace(vec![false; self.exec_len]);
+        let result = self.run_observed(&mut reporter, cycles);
+        let run_coverage = self.coverage.take().unwrap_or_default();
+        self.coverage = kept_coverage;
+        if let Some(ref mut coverage) = self.coverage {
+            for (kept, &run) in coverage.iter_mut().zip(run_coverage.iter()) {
+                *kept |= run;
+            }
+        }
+        let (cycles, halt_reason) = result?;
+        let program_len = self.exec_tape.len();
+        Ok(RunReport {
+            cycles,
+            halt_reason,
+            peak_stack: reporter.peak_stack,
+            cells_touched: reporter.cells_touched,
+            output_len: reporter.output_len,
+            input_reads: reporter.input_reads,
+            instructions_covered: run_coverage[..program_len].iter().filter(|&&c| c).count(),
+            program_len,
+        })
+    }
+}
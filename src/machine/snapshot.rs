@@ -0,0 +1,106 @@
+//! Saving a machine's state and restoring it later.
+use super::{AddressWidth, SBrainVM};
+use crate::cell::Cell;
+use crate::error::SBrainError;
+use crate::{MAddr, MData};
+use alloc::vec::Vec;
+#[cfg(feature = "serde")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use std::io::{self, Read, Write};
+
+/// The state of a running program, as taken by `SBrainVM::snapshot`: the program, the data tape,
+/// the stack, the registers and the pointers. With the `serde` feature, snapshots can be
+/// serialized, for example to checkpoint a long run and resume it in another process.
+///
+/// A snapshot does not include the machine's configuration, its input and output, or its
+/// statistics; restore it into a machine configured and connected like the original.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VmSnapshot<C: Cell = MData> {
+    /// The program on the exec tape.
+    pub program: Vec<u8>,
+    /// The data tape, without any trailing zero cells.
+    pub data: Vec<C>,
+    /// Cells past the data tape which have been written, with a wider `AddressWidth`.
+    pub far_cells: Vec<(u32, C)>,
+    /// The data stack, bottom first.
+    pub stack: Vec<C>,
+    pub auxi_r: C,
+    /// The full data pointer; see `SBrainVM::data_address`.
+    pub data_address: u32,
+    pub inst_p: MAddr,
+    /// The loops being executed, in `LoopMode::JumpStack`.
+    pub jump_stack: Vec<MAddr>,
+}
+
+#[cfg(feature = "serde")]
+impl<C: Cell + Serialize + DeserializeOwned> VmSnapshot<C> {
+    /// Write the snapshot as JSON.
+    pub fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        serde_json::to_writer(writer, self).map_err(io::Error::from)
+    }
+
+    /// Read a snapshot written by `save`.
+    pub fn load(reader: &mut dyn Read) -> io::Result<VmSnapshot<C>> {
+        serde_json::from_reader(reader).map_err(io::Error::from)
+    }
+}
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// Take a snapshot of the running program's state, to restore later with `restore`.
+    pub fn snapshot(&self) -> VmSnapshot<C> {
+        let data_len = self
+            .data_tape
+            .iter()
+            .rposition(|&cell| cell != C::default())
+            .map_or(0, |last| last + 1);
+        VmSnapshot {
+            program: self.exec_tape.clone(),
+            data: self.data_tape[..data_len].to_vec(),
+            far_cells: self.far_cells.iter().map(|(&a, &v)| (a, v)).collect(),
+            stack: self.data_stack.clone(),
+            auxi_r: self.auxi_r,
+            data_address: self.data_address(),
+            inst_p: self.inst_p,
+            jump_stack: self.jump_stack.clone(),
+        }
+    }
+
+    /// Replace the running program's state with a snapshot taken by `snapshot`, so that running
+    /// continues where the snapshot was taken. Cells not in the snapshot are zeroed.
+    /// On error, the machine is unchanged.
+    pub fn restore(&mut self, snapshot: &VmSnapshot<C>) -> Result<(), SBrainError> {
+        if self.address_width == AddressWidth::Bits16
+            && (snapshot.data_address as usize >= self.data_tape.len()
+                || !snapshot.far_cells.is_empty())
+        {
+            return Err(SBrainError::AddressTooWide);
+        }
+        if snapshot.data.len() > self.data_tape.len()
+            && (self.address_width != AddressWidth::Growable
+                || snapshot.data.len() > self.max_data_len)
+        {
+            return Err(SBrainError::DataTooLong {
+                offset: 0,
+                len: snapshot.data.len(),
+            });
+        }
+        self.load_program(&snapshot.program)?;
+
+        self.data_tape
+            .iter_mut()
+            .for_each(|cell| *cell = C::default());
+        self.far_cells = snapshot.far_cells.iter().cloned().collect();
+        self.grow_to(snapshot.data.len());
+        self.data_tape[..snapshot.data.len()].clone_from_slice(&snapshot.data);
+        self.data_stack = snapshot.stack.clone();
+        self.auxi_r = snapshot.auxi_r;
+        self.set_data_address(snapshot.data_address);
+        self.inst_p = (snapshot.inst_p as usize % self.exec_len) as MAddr;
+        self.jump_stack = snapshot.jump_stack.clone();
+        Ok(())
+    }
+}
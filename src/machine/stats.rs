@@ -0,0 +1,116 @@
+//! Counters describing what a machine has executed.
+use super::SBrainVM;
+use crate::analysis::{OpcodeClass, OPCODE_CLASSES};
+use crate::cell::Cell;
+use crate::MAddr;
+use alloc::collections::BTreeMap;
+
+/// Counts of the instructions a machine has executed, accumulated across calls to `run` until
+/// `SBrainVM::reset_stats` is called.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RunStats {
+    /// The total number of instructions executed, including any halt instruction.
+    pub instructions: u64,
+    /// The number of instructions executed in each `OpcodeClass`, indexed by
+    /// `OpcodeClass::index`. These always sum to `instructions`.
+    pub class_cycles: [u64; OPCODE_CLASSES],
+    /// The number of times each opcode was executed, indexed by opcode. Opcodes of 32 and above,
+    /// which are all NOPs, are counted only in `instructions`.
+    pub opcodes: [u64; 32],
+}
+
+impl RunStats {
+    /// The number of instructions executed in the given class.
+    pub fn cycles_in(&self, class: OpcodeClass) -> u64 {
+        self.class_cycles[class.index()]
+    }
+
+    /// The number of times the given opcode was executed.
+    pub fn executions_of(&self, opcode: u8) -> u64 {
+        self.opcodes.get(opcode as usize).cloned().unwrap_or(0)
+    }
+}
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// Counts of the instructions this machine has executed so far.
+    pub fn stats(&self) -> &RunStats {
+        &self.stats
+    }
+
+    /// Zero the counters returned by `stats`.
+    pub fn reset_stats(&mut self) {
+        self.stats = RunStats::default();
+    }
+
+    /// Start (or stop) recording which instruction addresses are executed. Enabling coverage
+    /// clears any previously recorded coverage.
+    pub fn set_coverage(&mut self, enabled: bool) {
+        self.coverage = if enabled {
+            Some(vec![false; self.exec_len])
+        } else {
+            None
+        };
+    }
+
+    /// For each address of the loaded program, whether the instruction there has been executed
+    /// since coverage was enabled. Empty if coverage is not enabled.
+    pub fn coverage(&self) -> &[bool] {
+        match self.coverage {
+            Some(ref coverage) => &coverage[..self.exec_tape.len()],
+            None => &[],
+        }
+    }
+
+    /// The percentage of the loaded program's instructions which have been executed since
+    /// coverage was enabled. 0 if coverage is not enabled or no program is loaded.
+    pub fn coverage_percent(&self) -> f64 {
+        let coverage = self.coverage();
+        if coverage.is_empty() {
+            return 0.0;
+        }
+        let covered = coverage.iter().filter(|&&c| c).count();
+        100.0 * covered as f64 / coverage.len() as f64
+    }
+
+    /// Start (or stop) counting loop iterations for `loop_counts`. Enabling counting clears any
+    /// previous counts.
+    pub fn set_loop_counting(&mut self, enabled: bool) {
+        self.loop_counts = if enabled {
+            Some(BTreeMap::new())
+        } else {
+            None
+        };
+    }
+
+    /// For each loop, keyed by the address of its `[`, how many times its `]` has jumped back
+    /// to it since loop counting was enabled; that is, one less than the number of times its
+    /// body ran to the end. Loops which never jumped back are not listed. None if loop counting
+    /// is not enabled.
+    pub fn loop_counts(&self) -> Option<&BTreeMap<MAddr, u64>> {
+        self.loop_counts.as_ref()
+    }
+
+    /// Count a `]` jumping back to the `[` at `addr`.
+    pub(super) fn record_loop(&mut self, addr: MAddr) {
+        if let Some(ref mut counts) = self.loop_counts {
+            *counts.entry(addr).or_insert(0) += 1;
+        }
+    }
+
+    /// Count `count` executions of `opcode`, without marking them in the coverage.
+    pub(super) fn record_instructions(&mut self, opcode: u8, count: u64) {
+        self.stats.instructions += count;
+        self.stats.class_cycles[OpcodeClass::of(opcode).index()] += count;
+        if let Some(total) = self.stats.opcodes.get_mut(opcode as usize) {
+            *total += count;
+        }
+    }
+
+    /// Count the execution of one instruction.
+    pub(super) fn record_instruction(&mut self, opcode: u8) {
+        self.record_instructions(opcode, 1);
+        if let Some(ref mut coverage) = self.coverage {
+            coverage[self.inst_p as usize] = true;
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Rolling back the most recent instructions.
+use super::SBrainVM;
+use crate::cell::Cell;
+use crate::MAddr;
+use alloc::collections::VecDeque;
+use alloc::fmt;
+use alloc::vec::Vec;
+
+/// Why `SBrainVM::undo_last` could not roll back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoError {
+    /// Only `available` instructions are recorded, fewer than the `requested` number.
+    NotEnoughHistory { requested: usize, available: usize },
+}
+
+impl fmt::Display for UndoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UndoError::NotEnoughHistory {
+                requested,
+                available,
+            } => write!(
+                f,
+                "Cannot undo {} instructions; only {} are recorded.",
+                requested, available
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UndoError {}
+
+/// Everything one instruction can change, as it was before the instruction ran.
+#[derive(Debug, Clone)]
+pub(super) struct UndoEntry<C> {
+    inst_p: MAddr,
+    data_p: MAddr,
+    data_page: MAddr,
+    auxi_r: C,
+    /// The data cell and exec tape opcode at `data_p`
+    cell: C,
+    code: u8,
+    program_len: usize,
+    stack_len: usize,
+    stack_top: Option<C>,
+    /// Only kept for instructions which can change the jump stack
+    jump_stack: Option<Vec<MAddr>>,
+    channel: usize,
+}
+
+/// The last few instructions' entries, oldest first.
+#[derive(Debug, Clone)]
+pub(super) struct UndoLog<C> {
+    depth: usize,
+    entries: VecDeque<UndoEntry<C>>,
+}
+
+impl<'a, C: Cell> SBrainVM<'a, C> {
+    /// Start recording the effects of the last `depth` instructions so that they can be rolled
+    /// back with `undo_last`, or stop recording if `depth` is 0. Any previous record is dropped.
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.undo = if depth > 0 {
+            Some(UndoLog {
+                depth,
+                entries: VecDeque::with_capacity(depth + 1),
+            })
+        } else {
+            None
+        };
+    }
+
+    /// Roll back the last `n` instructions' effects on the tapes, stack, registers and pointers.
+    /// Input already read and output already written stay that way, as does the state of the
+    /// random number generator. If fewer than `n` instructions are recorded, nothing is rolled
+    /// back. Halts are not instructions for this purpose, so the first instruction undone after
+    /// a halt is the one before it.
+    pub fn undo_last(&mut self, n: usize) -> Result<(), UndoError> {
+        let available = self.undo.as_ref().map_or(0, |log| log.entries.len());
+        if n > available {
+            return Err(UndoError::NotEnoughHistory {
+                requested: n,
+                available,
+            });
+        }
+        if n > 0 {
+            self.halted = false;
+        }
+        for _ in 0..n {
+            let entry = match self.undo.as_mut().and_then(|log| log.entries.pop_back()) {
+                Some(entry) => entry,
+                None => break,
+            };
+            self.inst_p = entry.inst_p;
+            self.data_p = entry.data_p;
+            self.data_page = entry.data_page;
+            self.auxi_r = entry.auxi_r;
+            *self.cell_mut() = entry.cell;
+            if let Some(code) = self.exec_tape.get_mut(entry.data_p as usize) {
+                if *code != entry.code {
+                    *code = entry.code;
+                    self.discard_bytecode();
+                }
+            }
+            if self.exec_tape.len() != entry.program_len {
+                self.exec_tape.resize(entry.program_len, 0);
+                self.discard_bytecode();
+            }
+            // An instruction pushes or pops at most one value
+            self.data_stack.truncate(entry.stack_len);
+            if let Some(top) = entry.stack_top {
+                if self.data_stack.len() < entry.stack_len {
+                    self.data_stack.push(top);
+                }
+            }
+            if let Some(jump_stack) = entry.jump_stack {
+                self.jump_stack = jump_stack;
+            }
+            self.channel = entry.channel;
+        }
+        Ok(())
+    }
+
+    /// Forget every recorded instruction, keeping the depth.
+    pub(super) fn clear_undo(&mut self) {
+        if let Some(ref mut log) = self.undo {
+            log.entries.clear();
+        }
+    }
+
+    /// Record the state the instruction about to run may change, if undo is enabled.
+    pub(super) fn record_undo(&mut self) {
+        if self.undo.is_none() {
+            return;
+        }
+        let opcode = self.opcode_at(self.inst_p);
+        let entry = UndoEntry {
+            inst_p: self.inst_p,
+            data_p: self.data_p,
+            data_page: self.data_page,
+            auxi_r: self.auxi_r,
+            cell: self.cell(),
+            code: self.opcode_at(self.data_p),
+            program_len: self.exec_tape.len(),
+            stack_len: self.data_stack.len(),
+            stack_top: self.data_stack.last().cloned(),
+            jump_stack: match opcode {
+                4 | 5 | 19 => Some(self.jump_stack.clone()),
+                _ => None,
+            },
+            channel: self.channel,
+        };
+        if let Some(ref mut log) = self.undo {
+            log.entries.push_back(entry);
+        }
+    }
+
+    /// Finish recording an instruction: keep its record if it `completed`, dropping the oldest
+    /// record if there are now too many, or forget it if it halted instead.
+    pub(super) fn finish_undo(&mut self, completed: bool) {
+        if let Some(ref mut log) = self.undo {
+            if !completed {
+                log.entries.pop_back();
+            } else if log.entries.len() > log.depth {
+                log.entries.pop_front();
+            }
+        }
+    }
+}
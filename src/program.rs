@@ -0,0 +1,104 @@
+//! Program tapes checked once, ahead of running them.
+use crate::analysis::VerifyError;
+use crate::{MAddr, MAX_PROGRAM_LEN};
+use alloc::vec::Vec;
+use core::ops::Deref;
+
+/// A program tape which is known to fit on a machine's exec tape and to use only the 32 opcodes
+/// of the instruction set, with the match of every bracket worked out in advance.
+///
+/// The bracket table is for tooling, such as editors and debuggers which need
+/// `matching_bracket` for many addresses; a machine works out its own jumps as it runs, so it
+/// does not use the table. A `Program` dereferences to its tape, so it can be passed wherever a
+/// tape is expected, such as to `SBrainVM::new`:
+///
+/// ```
+/// # use sbrain::*;
+/// let program = Program::new(&source_to_tape("+++[>++<-]>.@")).expect("Invalid program");
+/// assert_eq!(program.matching_bracket(3), Some(9));
+/// let mut machine = SBrainVM::new_buffered(None, &program).expect("Could not build machine");
+/// machine.run(Some(1000)).expect("I/O failed");
+/// assert_eq!(machine.output(), &[6]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    tape: Vec<u8>,
+    /// The address of the matching bracket for each address holding a matched bracket
+    brackets: Vec<Option<MAddr>>,
+    balanced: bool,
+}
+
+impl Program {
+    /// Check `tape` and record where its brackets match. Fails with every problem found, in the
+    /// order `verify` gives them, if the tape is longer than `MAX_PROGRAM_LEN` or holds an opcode
+    /// of 32 or above.
+    ///
+    /// Unlike `verify`, this accepts unbalanced brackets, which a machine can still run (see
+    /// `BracketMode`), and the unimplemented opcodes below 32, which do nothing.
+    pub fn new(tape: &[u8]) -> Result<Program, Vec<VerifyError>> {
+        let mut errors = Vec::new();
+        if tape.len() > MAX_PROGRAM_LEN {
+            errors.push(VerifyError::TooLong { len: tape.len() });
+        }
+        errors.extend(
+            tape.iter()
+                .enumerate()
+                .filter(|&(_, &opcode)| opcode >= 32)
+                .map(|(addr, &opcode)| VerifyError::UnknownOpcode { addr, opcode }),
+        );
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut brackets = vec![None; tape.len()];
+        let mut open = Vec::new();
+        let mut balanced = true;
+        for (addr, &opcode) in tape.iter().enumerate() {
+            match opcode {
+                4 => open.push(addr),
+                5 => match open.pop() {
+                    Some(start) => {
+                        brackets[start] = Some(addr as MAddr);
+                        brackets[addr] = Some(start as MAddr);
+                    }
+                    None => balanced = false,
+                },
+                _ => {}
+            }
+        }
+        Ok(Program {
+            tape: tape.to_vec(),
+            brackets,
+            balanced: balanced && open.is_empty(),
+        })
+    }
+
+    /// The program tape.
+    pub fn tape(&self) -> &[u8] {
+        &self.tape
+    }
+
+    /// Give up the checks and return the program tape.
+    pub fn into_tape(self) -> Vec<u8> {
+        self.tape
+    }
+
+    /// The bracket matching the one at `addr`, as `matching_bracket` would find it, or None if
+    /// `addr` does not hold a matched bracket.
+    pub fn matching_bracket(&self, addr: MAddr) -> Option<MAddr> {
+        self.brackets.get(addr as usize).cloned().unwrap_or(None)
+    }
+
+    /// Whether every bracket in the program has a match.
+    pub fn is_balanced(&self) -> bool {
+        self.balanced
+    }
+}
+
+impl Deref for Program {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.tape
+    }
+}
@@ -0,0 +1,40 @@
+//! Bundling a program with its input so a run can be reproduced exactly.
+use crate::error::SBrainError;
+use crate::eval::{evaluate, EvalResult};
+use crate::MData;
+use alloc::vec::Vec;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use std::io::{self, Read, Write};
+
+/// Everything needed to reproduce a run: the program tape, its input, and the cycle limit.
+/// With the `serde` feature, cases can be saved and loaded as JSON, making them suitable for bug
+/// reports and regression corpora.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ReplayCase {
+    pub program: Vec<u8>,
+    pub input: Vec<MData>,
+    pub limit: Option<u32>,
+}
+
+impl ReplayCase {
+    /// Run the case on a fresh machine. Replaying the same case always gives the same result.
+    /// Fails if the program is too long to load.
+    pub fn replay(&self) -> Result<EvalResult, SBrainError> {
+        evaluate(&self.program, &self.input, self.limit)
+    }
+
+    /// Write the case as JSON.
+    #[cfg(feature = "serde")]
+    pub fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        serde_json::to_writer(writer, self).map_err(io::Error::from)
+    }
+
+    /// Read a case written by `save`.
+    #[cfg(feature = "serde")]
+    pub fn load(reader: &mut dyn Read) -> io::Result<ReplayCase> {
+        serde_json::from_reader(reader).map_err(io::Error::from)
+    }
+}
@@ -1,58 +1,629 @@
+use crate::io;
+use crate::io::Read;
+use crate::program::Program;
+use crate::MAX_PROGRAM_LEN;
+use alloc::collections::BTreeMap;
+use alloc::fmt;
+use alloc::vec::Vec;
+use alloc::str;
+
 enum ParserState {
     Code,
     Comment,
 }
 
+/// The specification's mapping between source characters and instructions. Opcodes are numbered
+/// consecutively from 0, so every opcode below its length is implemented.
+pub(crate) const SPEC_MAPPING: [(char, u8); 27] = [
+    ('<', 0),
+    ('>', 1),
+    ('-', 2),
+    ('+', 3),
+    ('[', 4),
+    (']', 5),
+    ('.', 6),
+    (',', 7),
+    ('{', 8),
+    ('}', 9),
+    ('(', 10),
+    (')', 11),
+    ('^', 12),
+    ('!', 13),
+    ('&', 14),
+    ('@', 15),
+    ('/', 16),
+    ('\\', 17),
+    ('%', 18),
+    ('~', 19),
+    (':', 20),
+    (';', 21),
+    ('_', 22),
+    ('\'', 23),
+    ('"', 24),
+    ('?', 25),
+    ('$', 26),
+];
+
 /// Given a character, turn it into a SBrainVM instruction
 fn char_to_instruction(character: char) -> Option<u8> {
-    match character {
-        '<' => Some(0),
-        '>' => Some(1),
-        '-' => Some(2),
-        '+' => Some(3),
-        '[' => Some(4),
-        ']' => Some(5),
-        '.' => Some(6),
-        ',' => Some(7),
-        '{' => Some(8),
-        '}' => Some(9),
-        '(' => Some(10),
-        ')' => Some(11),
-        '^' => Some(12),
-        '!' => Some(13),
-        '&' => Some(14),
-        '@' => Some(15),
-        _ => None,
+    SPEC_MAPPING
+        .iter()
+        .find(|&&(c, _)| c == character)
+        .map(|&(_, opcode)| opcode)
+}
+
+/// Converts source code to instructions using a mapping from characters to opcodes.
+/// `Transliterator::new()` uses the mapping from the specification, which is what
+/// `source_to_tape` and `compile_reader` use.
+///
+/// Whatever the mapping, `#` always delimits comments and digits always form repeat counts, so
+/// mapping them has no effect.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transliterator {
+    /// None for the specification's mapping
+    mapping: Option<BTreeMap<char, u8>>,
+}
+
+impl Transliterator {
+    /// A transliterator using the specification's mapping.
+    pub fn new() -> Transliterator {
+        Transliterator { mapping: None }
+    }
+
+    /// A transliterator using the given mapping. Characters not in the mapping are ignored.
+    pub fn with_mapping(mapping: BTreeMap<char, u8>) -> Transliterator {
+        Transliterator {
+            mapping: Some(mapping),
+        }
+    }
+
+    /// The specification's mapping, as a starting point for a custom one.
+    pub fn spec_mapping() -> BTreeMap<char, u8> {
+        SPEC_MAPPING.iter().cloned().collect()
+    }
+
+    /// The instruction the given character stands for, if any.
+    pub fn instruction(&self, character: char) -> Option<u8> {
+        match self.mapping {
+            Some(ref mapping) => mapping.get(&character).cloned(),
+            None => char_to_instruction(character),
+        }
+    }
+
+    /// The character which stands for the given instruction, if any; the inverse of
+    /// `instruction`. If several characters map to the same opcode, the smallest is returned.
+    pub fn character(&self, opcode: u8) -> Option<char> {
+        match self.mapping {
+            Some(ref mapping) => mapping
+                .iter()
+                .find(|&(_, &o)| o == opcode)
+                .map(|(&c, _)| c),
+            None => SPEC_MAPPING
+                .iter()
+                .find(|&&(_, o)| o == opcode)
+                .map(|&(c, _)| c),
+        }
+    }
+
+    /// Transliterate a source code into the corresponding instructions.
+    /// See `source_to_tape` for the rules.
+    pub fn compile(&self, source: &str) -> Vec<u8> {
+        self.parse(source, false).finish()
+    }
+
+    /// Run the parser over all of `source`, building a source map if `map` is set.
+    fn parse(&self, source: &str, map: bool) -> Parser<'_> {
+        // Strip out comments. Anything between # goes.
+        // Code gets turned into u8s
+        let mut parser = Parser::new(self);
+        if map {
+            parser.map = Some(Vec::new());
+        }
+        for character in source.chars() {
+            parser.feed(character);
+        }
+        parser
+    }
+
+    /// Like `compile`, but also return a source map giving the position in `source` of the
+    /// character each instruction came from.
+    pub fn compile_with_map(&self, source: &str) -> (Vec<u8>, SourceMap) {
+        let mut parser = self.parse(source, true);
+        let map = SourceMap {
+            positions: parser.map.take().unwrap_or_default(),
+        };
+        (parser.finish(), map)
+    }
+
+    /// Like `compile`, but checks that the program can be loaded into a machine.
+    /// On success, the length of the returned tape is the number of instructions compiled.
+    pub fn compile_checked(&self, source: &str) -> Result<Vec<u8>, CompileError> {
+        self.compile_limited(source, None)
+    }
+
+    /// Like `compile_checked`, but also rejects programs with loops nested more than
+    /// `max_nesting` deep, if given. The first loop to go too deep is reported.
+    pub fn compile_limited(
+        &self,
+        source: &str,
+        max_nesting: Option<usize>,
+    ) -> Result<Vec<u8>, CompileError> {
+        let parser = self.parse(source, false);
+        if parser.len > MAX_PROGRAM_LEN {
+            return Err(CompileError::ProgramTooLong { len: parser.len });
+        }
+        let code = parser.finish();
+        if let Some(max_nesting) = max_nesting {
+            let mut depth: usize = 0;
+            for (addr, &opcode) in code.iter().enumerate() {
+                match opcode {
+                    4 => {
+                        depth += 1;
+                        if depth > max_nesting {
+                            return Err(CompileError::TooDeeplyNested { depth, addr });
+                        }
+                    }
+                    5 => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+        }
+        Ok(code)
+    }
+
+    /// Transliterate source code read from `reader` into the corresponding instructions, reading
+    /// it in chunks rather than all at once. The result is the same as `compile` on the same text.
+    /// Invalid UTF-8 is not an error; each invalid sequence is treated as a single non-command
+    /// character.
+    pub fn compile_reader(&self, reader: &mut dyn Read) -> io::Result<Vec<u8>> {
+        self.parse_reader(reader).map(Parser::finish)
+    }
+
+    /// Run the parser over all of the source code read from `reader`.
+    fn parse_reader(&self, reader: &mut dyn Read) -> io::Result<Parser<'_>> {
+        let mut parser = Parser::new(self);
+        let mut buf = [0; 4096];
+        // The number of bytes at the start of buf left over from the previous read; these are
+        // the start of a character split across reads
+        let mut pending = 0;
+
+        loop {
+            let n = match reader.read(&mut buf[pending..]) {
+                Ok(0) => {
+                    if pending > 0 {
+                        // The input ended partway through a character
+                        parser.feed(char::REPLACEMENT_CHARACTER);
+                    }
+                    return Ok(parser);
+                }
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            let end = pending + n;
+            let mut start = 0;
+            while start < end {
+                match str::from_utf8(&buf[start..end]) {
+                    Ok(text) => {
+                        text.chars().for_each(|c| parser.feed(c));
+                        start = end;
+                    }
+                    Err(e) => {
+                        let valid = start + e.valid_up_to();
+                        str::from_utf8(&buf[start..valid])
+                            .expect("Prefix was checked to be valid")
+                            .chars()
+                            .for_each(|c| parser.feed(c));
+                        match e.error_len() {
+                            Some(len) => {
+                                parser.feed(char::REPLACEMENT_CHARACTER);
+                                start = valid + len;
+                            }
+                            None => {
+                                // An incomplete character at the end; wait for the rest
+                                start = valid;
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            buf.copy_within(start..end, 0);
+            pending = end - start;
+        }
     }
 }
 
-/// Transliterate a source code into the corresponding instructions.
-pub fn source_to_tape(source: &str) -> Vec<u8> {
-    // Strip out comments. Anything between # goes.
-    // Code gets turned into u8s
+/// A position in a source code: the line, counting from 1, and the column within it, counting
+/// characters (not bytes) from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourcePosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
 
-    let mut code: Vec<u8> = Vec::new();
+/// For each instruction of a transliterated program, the position of the character in the source
+/// code it came from. Every instruction produced by a repeat count maps to the character the
+/// count applies to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    positions: Vec<SourcePosition>,
+}
 
-    let mut state: ParserState = ParserState::Code;
+impl SourceMap {
+    /// The position of the character the instruction at `addr` came from, or None if the program
+    /// has no instruction there.
+    pub fn position(&self, addr: usize) -> Option<SourcePosition> {
+        self.positions.get(addr).cloned()
+    }
 
-    for character in source.chars() {
-        match state {
+    /// The position of every instruction, in order.
+    pub fn positions(&self) -> &[SourcePosition] {
+        &self.positions
+    }
+}
+
+/// A reason a source code could not be compiled into a loadable program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileError {
+    /// The program has `len` instructions, more than `MAX_PROGRAM_LEN`. A length too large for
+    /// a `usize` is given as `usize::MAX`.
+    ProgramTooLong { len: usize },
+    /// The `[ at `addr` opens a loop nested `depth` deep, more than the limit allows.
+    TooDeeplyNested { depth: usize, addr: usize },
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompileError::ProgramTooLong { len } => write!(
+                f,
+                "Program has {} instructions, but at most {} can be loaded.",
+                len, MAX_PROGRAM_LEN
+            ),
+            CompileError::TooDeeplyNested { depth, addr } => write!(
+                f,
+                "Loop at {} is nested {} deep, more than allowed.",
+                addr, depth
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CompileError {}
+
+/// The transliterator's state as it works through a source code one character at a time.
+struct Parser<'t> {
+    transliterator: &'t Transliterator,
+    state: ParserState,
+    /// A repeat count read in code, waiting for the instruction it applies to
+    repeat: Option<usize>,
+    code: Vec<u8>,
+    /// The length of the program, which is longer than `code` if repeat counts took it past
+    /// `MAX_PROGRAM_LEN`
+    len: usize,
+    /// The position of the character being fed
+    position: SourcePosition,
+    /// The source position of each instruction, if a source map is being built
+    map: Option<Vec<SourcePosition>>,
+}
+
+impl<'t> Parser<'t> {
+    fn new(transliterator: &'t Transliterator) -> Parser<'t> {
+        Parser {
+            transliterator,
+            state: ParserState::Code,
+            repeat: None,
+            code: Vec::new(),
+            len: 0,
+            position: SourcePosition { line: 1, column: 1 },
+            map: None,
+        }
+    }
+
+    /// Advance the transliterator by one character.
+    fn feed(&mut self, character: char) {
+        self.feed_at_position(character);
+        if character == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+    }
+
+    fn feed_at_position(&mut self, character: char) {
+        match self.state {
             ParserState::Code => {
+                if let Some(digit) = character.to_digit(10) {
+                    let repeat = self.repeat.unwrap_or(0);
+                    self.repeat = Some(repeat.saturating_mul(10).saturating_add(digit as usize));
+                    return;
+                }
+                let repeat = self.repeat.take();
                 if character == '#' {
-                    state = ParserState::Comment;
+                    self.state = ParserState::Comment;
                 } else {
-                    match char_to_instruction(character) {
+                    match self.transliterator.instruction(character) {
                         None => {}
-                        Some(n) => code.push(n),
+                        Some(n) => {
+                            let count = match repeat {
+                                // A program longer than this can never be loaded, so there's no
+                                // need to spend memory on any more of a count
+                                Some(count) => {
+                                    self.len = self.len.saturating_add(count);
+                                    count.min((MAX_PROGRAM_LEN + 1).saturating_sub(self.code.len()))
+                                }
+                                None => {
+                                    self.len = self.len.saturating_add(1);
+                                    1
+                                }
+                            };
+                            self.code.extend((0..count).map(|_| n));
+                            if let Some(ref mut map) = self.map {
+                                let position = self.position;
+                                map.extend((0..count).map(|_| position));
+                            }
+                        }
                     };
                 }
             }
             ParserState::Comment => {
                 if character == '#' {
-                    state = ParserState::Code;
+                    self.state = ParserState::Code;
                 }
             }
         };
     }
-    return code;
+
+    fn finish(self) -> Vec<u8> {
+        self.code
+    }
+}
+
+/// Transliterate a source code into the corresponding instructions.
+///
+/// As an extension to the specification, an instruction may be preceded by a decimal repeat
+/// count: `16+` is the same as sixteen `+` and `0+` produces nothing. The count must come
+/// directly before the instruction; if any other character follows it (including whitespace or a
+/// comment), the count is ignored. Digits inside comments are ignored as usual.
+///
+/// A count which would take the program past `MAX_PROGRAM_LEN` instructions, so that it could
+/// never be loaded, only adds instructions up to one past that length, so that a huge count
+/// cannot exhaust memory. `compile` reports the program's full length.
+pub fn source_to_tape(source: &str) -> Vec<u8> {
+    Transliterator::new().compile(source)
+}
+
+/// Transliterate a source code into the corresponding instructions, like `source_to_tape`, and
+/// build a source map pointing each instruction back at the character it came from, so that
+/// debuggers and error messages can refer to what was written rather than to addresses.
+///
+/// ```
+/// # use sbrain::*;
+/// let (program, map) = source_to_tape_with_map("+ # add #\n2>.");
+/// assert_eq!(program, vec![3, 1, 1, 6]);
+/// assert_eq!(map.position(2), Some(SourcePosition { line: 2, column: 2 }));
+/// assert_eq!(map.position(3).unwrap().to_string(), "2:3");
+/// ```
+pub fn source_to_tape_with_map(source: &str) -> (Vec<u8>, SourceMap) {
+    Transliterator::new().compile_with_map(source)
+}
+
+/// Transliterate a source code into the corresponding instructions, like `source_to_tape`, but
+/// check that the result can be loaded into a machine, so that an over-long program is caught
+/// before trying to load it.
+pub fn compile(source: &str) -> Result<Vec<u8>, CompileError> {
+    Transliterator::new().compile_checked(source)
+}
+
+/// Like `compile`, but also rejects programs with loops nested more than `max_nesting` deep, to
+/// bound the structural complexity of a program before running it.
+pub fn compile_limited(source: &str, max_nesting: usize) -> Result<Vec<u8>, CompileError> {
+    Transliterator::new().compile_limited(source, Some(max_nesting))
+}
+
+/// Characters which are not instructions, but which may have been meant as one: ASCII punctuation
+/// with no instruction, and characters easily typed or pasted in place of an instruction's. Each
+/// has the instruction's character it resembles, if there is one.
+const LOOKALIKES: [(char, Option<char>); 14] = [
+    ('*', None),
+    ('=', None),
+    ('|', None),
+    ('`', Some('\'')),
+    ('\u{2018}', Some('\'')),
+    ('\u{2019}', Some('\'')),
+    ('\u{201c}', Some('"')),
+    ('\u{201d}', Some('"')),
+    ('\u{2013}', Some('-')),
+    ('\u{2014}', Some('-')),
+    ('\u{2212}', Some('-')),
+    ('\u{2039}', Some('<')),
+    ('\u{203a}', Some('>')),
+    ('\u{ff20}', Some('@')),
+];
+
+/// A likely mistake in a source code, found by `parse`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// The program has `len` instructions, more than `MAX_PROGRAM_LEN`; the instruction at
+    /// `position` is the first which does not fit.
+    ProgramTooLong {
+        len: usize,
+        position: SourcePosition,
+    },
+    /// The comment opened by the `#` at `position` is never closed, so it runs to the end of the
+    /// source.
+    UnterminatedComment { position: SourcePosition },
+    /// The `@` at `position` directly follows another, so it can never run.
+    RepeatedHalt { position: SourcePosition },
+    /// The `character` at `position` is not an instruction, but looks like it was meant to be;
+    /// `suggestion` is the instruction's character it resembles, if there is one.
+    NotAnInstruction {
+        character: char,
+        suggestion: Option<char>,
+        position: SourcePosition,
+    },
+}
+
+impl Diagnostic {
+    /// The position in the source the diagnostic refers to.
+    pub fn position(&self) -> SourcePosition {
+        match *self {
+            Diagnostic::ProgramTooLong { position, .. }
+            | Diagnostic::UnterminatedComment { position }
+            | Diagnostic::RepeatedHalt { position }
+            | Diagnostic::NotAnInstruction { position, .. } => position,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.position())?;
+        match *self {
+            Diagnostic::ProgramTooLong { len, .. } => write!(
+                f,
+                "Program has {} instructions, but at most {} can be loaded.",
+                len, MAX_PROGRAM_LEN
+            ),
+            Diagnostic::UnterminatedComment { .. } => {
+                write!(f, "Comment is never closed with a '#'.")
+            }
+            Diagnostic::RepeatedHalt { .. } => {
+                write!(f, "Halt follows another halt, so it can never run.")
+            }
+            Diagnostic::NotAnInstruction {
+                character,
+                suggestion: Some(suggestion),
+                ..
+            } => write!(
+                f,
+                "{:?} is not an instruction; did you mean {:?}?",
+                character, suggestion
+            ),
+            Diagnostic::NotAnInstruction { character, .. } => {
+                write!(f, "{:?} is not an instruction.", character)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Diagnostic {}
+
+/// Transliterate a source code like `source_to_tape`, but look for the mistakes it passes over
+/// silently: comments which are never closed, halts which directly follow another halt, and
+/// characters outside comments which look like instructions but are not (such as `*`, or a
+/// typographic quote in place of `"`). A program too long to load is also reported.
+///
+/// Every problem is reported, in source order, with the position it was found at; the program is
+/// only returned if there are none. Other characters outside comments, such as letters and
+/// whitespace, are ignored as usual.
+///
+/// ```
+/// # use sbrain::*;
+/// let program = parse("+++[>++<-]>.@").expect("Invalid program");
+/// assert_eq!(program.tape(), &source_to_tape("+++[>++<-]>.@")[..]);
+///
+/// let errors = parse("+\n\u{2018}@@ # halt").unwrap_err();
+/// assert_eq!(errors.len(), 3);
+/// assert_eq!(errors[0].to_string(), "2:1: '\u{2018}' is not an instruction; did you mean '\\''?");
+/// ```
+pub fn parse(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    let transliterator = Transliterator::new();
+    let mut parser = transliterator.parse(source, true);
+    let len = parser.len;
+    let map = SourceMap {
+        positions: parser.map.take().unwrap_or_default(),
+    };
+    let code = parser.finish();
+    let mut diagnostics = Vec::new();
+
+    let mut position = SourcePosition { line: 1, column: 1 };
+    let mut comment = None;
+    for character in source.chars() {
+        match comment {
+            Some(_) if character == '#' => comment = None,
+            Some(_) => {}
+            None if character == '#' => comment = Some(position),
+            None => {
+                if let Some(&(_, suggestion)) = LOOKALIKES.iter().find(|&&(c, _)| c == character) {
+                    diagnostics.push(Diagnostic::NotAnInstruction {
+                        character,
+                        suggestion,
+                        position,
+                    });
+                }
+            }
+        }
+        if character == '\n' {
+            position.line += 1;
+            position.column = 1;
+        } else {
+            position.column += 1;
+        }
+    }
+    if let Some(position) = comment {
+        diagnostics.push(Diagnostic::UnterminatedComment { position });
+    }
+
+    diagnostics.extend(
+        code.windows(2)
+            .zip(map.positions().iter().skip(1))
+            .filter(|&(pair, _)| pair == [15, 15])
+            .map(|(_, &position)| Diagnostic::RepeatedHalt { position }),
+    );
+    if let Some(position) = map.position(MAX_PROGRAM_LEN) {
+        diagnostics.push(Diagnostic::ProgramTooLong { len, position });
+    }
+    diagnostics.sort_by_key(Diagnostic::position);
+
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    Ok(Program::new(&code).expect("Program was checked to fit, and uses only the spec's opcodes"))
+}
+
+/// The separator `compile_many` is usually given: a line of four `=`, which is not an instruction.
+pub const DEFAULT_SEPARATOR: &str = "====";
+
+/// Split `source` on `separator` and transliterate each segment as a separate program,
+/// preserving their order. Splitting happens before transliteration, so each segment starts
+/// outside of a comment, an unterminated comment only swallows the rest of its own segment, and
+/// a separator inside a comment still splits. Empty segments (for example, after a trailing
+/// separator) produce empty programs.
+pub fn compile_many(source: &str, separator: &str) -> Vec<Vec<u8>> {
+    source.split(separator).map(source_to_tape).collect()
+}
+
+/// Transliterate source code read from `reader` into the corresponding instructions, reading it
+/// in chunks rather than all at once. The result is the same as `source_to_tape` on the same text.
+/// Invalid UTF-8 is not an error; each invalid sequence is treated as a single non-command
+/// character.
+pub fn compile_reader(reader: &mut dyn Read) -> io::Result<Vec<u8>> {
+    Transliterator::new().compile_reader(reader)
+}
+
+/// Read the source file at `path` and compile it, like `compile`. A program too long to load is
+/// reported as an `io::Error` of kind `InvalidData` wrapping the `CompileError`, so that one
+/// error type covers both reading and compiling.
+#[cfg(feature = "std")]
+pub fn compile_file<P: AsRef<std::path::Path>>(path: P) -> io::Result<Vec<u8>> {
+    let mut file = std::fs::File::open(path)?;
+    let transliterator = Transliterator::new();
+    let parser = transliterator.parse_reader(&mut file)?;
+    if parser.len > MAX_PROGRAM_LEN {
+        let error = CompileError::ProgramTooLong { len: parser.len };
+        return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+    }
+    Ok(parser.finish())
 }
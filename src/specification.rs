@@ -43,6 +43,17 @@
 //!       13|      !| Perform a bitwise NOT on the value in `auxi_r`.
 //!       14|      &| Perform a bitwise AND on the value in `auxi_r` and the cell pointed at `data_p`, placing the value in `auxi_r`.
 //!       15|      @| End the program. The exit code is the value in `auxi_r`.
+//!       16|      /| Rotate the bits of `auxi_r` left by one; the high bit becomes the low bit.
+//!       17|      \\| Rotate the bits of `auxi_r` right by one; the low bit becomes the high bit.
+//!       18|      %| Set the cell pointed at by `data_p` to a pseudo-random value. Optional: implementations may treat this as a NOP unless randomness is explicitly enabled.
+//!       19|      ~| Restart the program: continue from the first instruction without clearing the tapes, the stack or `auxi_r`. Optional.
+//!       20|      :| Set the cell pointed at by `data_p` to the opcode at the same address on the execution tape. Optional: a NOP unless self-modifying code is enabled.
+//!       21|      ;| Write the cell pointed at by `data_p` to the execution tape as the opcode at the same address. Optional: a NOP unless self-modifying code is enabled.
+//!       22|      _| Set the cell pointed at by `data_p` to 1 if it is equal to `auxi_r`, or 0 otherwise.
+//!       23|      '| Set the cell pointed at by `data_p` to 1 if it is less than `auxi_r`, or 0 otherwise.
+//!       24|      "| Set the cell pointed at by `data_p` to 1 if it is greater than `auxi_r`, or 0 otherwise.
+//!       25|      ?| If the cell pointed at by `data_p` is 0, skip the next instruction. Skipping takes no extra cycle.
+//!       26|      $| Select the output channel numbered by the cell pointed at by `data_p` for subsequent output. Optional: implementations with a single output have only channel 0, and may discard output to any other channel.
 //!
 //! ### Further Rules
 //! No read operation shall ever disrupt a cell on the data tape.
@@ -0,0 +1,68 @@
+//! Reading standard input without blocking forever.
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+/// Chunks of standard input, read by a background thread. The thread is started the first time
+/// this is called and reads until standard input is closed, which drops the sender. There is
+/// only ever one such thread, so no input is lost to a reader nobody is waiting on.
+fn chunks() -> &'static Mutex<Receiver<Vec<u8>>> {
+    static CHUNKS: OnceLock<Mutex<Receiver<Vec<u8>>>> = OnceLock::new();
+    CHUNKS.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            loop {
+                match io::stdin().read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if sender.send(buf[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        Mutex::new(receiver)
+    })
+}
+
+/// Standard input, except that a read which waits longer than `wait` for input reads nothing,
+/// as at EOF.
+pub struct TimeoutStdin {
+    wait: Duration,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl TimeoutStdin {
+    pub fn new(wait: Duration) -> TimeoutStdin {
+        TimeoutStdin {
+            wait,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for TimeoutStdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            let receiver = chunks().lock().unwrap_or_else(|e| e.into_inner());
+            match receiver.recv_timeout(self.wait) {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                // Timed out, or standard input is closed
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -1,19 +1,70 @@
 use crate::MData;
-use std::borrow::Cow;
+use alloc::borrow::Cow;
+use alloc::fmt;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
 use std::io::Cursor;
 
 /// Convert a tape of MData cells into Unicode chars. Invalid chars are excluded, which could have
 /// some unintended side effects for genesis based on string comparisons.
 pub fn tape_to_string<'a>(tape: &'a [MData]) -> Cow<'a, str> {
-    String::from_utf8_lossy(&tape)
+    String::from_utf8_lossy(tape)
 }
 
+#[cfg(feature = "std")]
 /// Create a new Cursor-wrapped input vector which can be used by a machine to read from.
 pub fn make_input_vec(data: &[u8]) -> Box<Cursor<Vec<u8>>> {
     Box::new(Cursor::new(data.to_vec()))
 }
 
+#[cfg(feature = "std")]
 /// Create a new Cursor-wrapped output vector which can be used by a machine to write onto.
 pub fn make_output_vec() -> Box<Cursor<Vec<u8>>> {
     Box::new(Cursor::new(Vec::new()))
 }
+
+/// A value too large for an `MData` cell, found by `narrow_data`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NarrowError {
+    /// The position of the value in the tape.
+    pub addr: usize,
+    /// The value itself.
+    pub value: u32,
+}
+
+impl fmt::Display for NarrowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Value {} at {} does not fit in a cell.",
+            self.value, self.addr
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for NarrowError {}
+
+/// Convert a tape of cells to the legacy format with 32-bit cells. This never loses information.
+pub fn widen_data(tape: &[MData]) -> Vec<u32> {
+    tape.iter().map(|&cell| cell as u32).collect()
+}
+
+/// Convert a tape in the legacy format with 32-bit cells to a tape of cells. Values are never
+/// truncated: if any value is too large for a cell, the first such value is the error.
+///
+/// Only data tapes are converted. The legacy opcodes are numbered differently (its halt is 31,
+/// not 15), so a legacy program tape narrowed this way does not give the same program.
+pub fn narrow_data(tape: &[u32]) -> Result<Vec<MData>, NarrowError> {
+    tape.iter()
+        .enumerate()
+        .map(|(addr, &value)| {
+            if value > MData::MAX as u32 {
+                Err(NarrowError { addr, value })
+            } else {
+                Ok(value as MData)
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,457 @@
+//! Compiling programs to WebAssembly modules.
+use crate::analysis::matching_bracket;
+use crate::error::SBrainError;
+use crate::{MAddr, MAX_PROGRAM_LEN};
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+// Instructions
+const UNREACHABLE: u8 = 0x00;
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0B;
+const BR: u8 = 0x0C;
+const BR_TABLE: u8 = 0x0E;
+const RETURN: u8 = 0x0F;
+const CALL: u8 = 0x10;
+const DROP: u8 = 0x1A;
+const SELECT: u8 = 0x1B;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const GLOBAL_GET: u8 = 0x23;
+const GLOBAL_SET: u8 = 0x24;
+const I32_LOAD8_U: u8 = 0x2D;
+const I32_STORE8: u8 = 0x3A;
+const MEMORY_SIZE: u8 = 0x3F;
+const MEMORY_GROW: u8 = 0x40;
+const I32_CONST: u8 = 0x41;
+const I64_CONST: u8 = 0x42;
+const I32_EQZ: u8 = 0x45;
+const I32_EQ: u8 = 0x46;
+const I32_NE: u8 = 0x47;
+const I32_LT_U: u8 = 0x49;
+const I32_GE_S: u8 = 0x4E;
+const I32_GE_U: u8 = 0x4F;
+const I64_LT_U: u8 = 0x54;
+const I32_ADD: u8 = 0x6A;
+const I32_SUB: u8 = 0x6B;
+const I32_AND: u8 = 0x71;
+const I32_OR: u8 = 0x72;
+const I32_XOR: u8 = 0x73;
+const I32_SHL: u8 = 0x74;
+const I32_SHR_U: u8 = 0x76;
+const I64_SUB: u8 = 0x7D;
+
+// Types
+const EMPTY: u8 = 0x40;
+const I32: u8 = 0x7F;
+const I64: u8 = 0x7E;
+
+// Functions: the imports, then `run`
+const INPUT: u32 = 0;
+const OUTPUT: u32 = 1;
+const RUN: u32 = 2;
+
+// Locals of `run`: its cycle limit, then the registers, which are kept in globals between calls
+const LIMIT: u32 = 0;
+const REGISTERS: [u32; 5] = [PC, DP, AUX, SP, CHANNEL];
+const PC: u32 = 1;
+const DP: u32 = 2;
+const AUX: u32 = 3;
+const SP: u32 = 4;
+const CHANNEL: u32 = 5;
+const VALUE: u32 = 6;
+
+/// The stack lives in memory after the data tape, growing upward.
+const STACK_BASE: u32 = 65536;
+
+/// Compile a program tape to a WebAssembly module which runs it as a machine built with
+/// `SBrainVM::new` would, with 8 bit cells and the default settings, without an interpreter.
+/// The module is self-contained apart from two imported functions for input and output:
+///
+/// * `env.input: () -> i32` returns the next byte of input, or a negative number at the end of
+///   the input, which reads as 0.
+/// * `env.output: (byte: i32, channel: i32) -> ()` receives each byte of output along with the
+///   channel selected by `$`.
+///
+/// It exports its `memory`, which holds the data tape in its first 65536 bytes and the stack after
+/// it, and `run: (limit: i64) -> i32`. `run` returns the exit code when the program halts, or -1
+/// once running on would take more than `limit` cycles. Like `SBrainVM::run`, `run` can be
+/// called again to carry on where the last call stopped, and after a halt it stays halted.
+///
+/// The random instruction (`%`) and self-modifying code (`:` and `;`) are NOPs, as they are in a
+/// machine which hasn't enabled them. Fails only if the program is too long to load.
+pub fn to_wasm(program: &[u8]) -> Result<Vec<u8>, SBrainError> {
+    if program.len() > MAX_PROGRAM_LEN {
+        return Err(SBrainError::ProgramTooLong {
+            len: program.len(),
+            max: MAX_PROGRAM_LEN,
+        });
+    }
+
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+    // Types: input, output and run
+    section(&mut module, 1, |s| {
+        uleb(s, 3);
+        s.extend_from_slice(&[0x60, 0, 1, I32]);
+        s.extend_from_slice(&[0x60, 2, I32, I32, 0]);
+        s.extend_from_slice(&[0x60, 1, I64, 1, I32]);
+    });
+    section(&mut module, 2, |s| {
+        uleb(s, 2);
+        for &(name, ty) in &[("input", 0), ("output", 1)] {
+            name_bytes(s, "env");
+            name_bytes(s, name);
+            s.extend_from_slice(&[0x00, ty]);
+        }
+    });
+    section(&mut module, 3, |s| s.extend_from_slice(&[1, 2]));
+    // Memory for the data tape and a little stack, which grows as needed
+    section(&mut module, 5, |s| s.extend_from_slice(&[1, 0x00, 2]));
+    section(&mut module, 6, |s| {
+        uleb(s, REGISTERS.len() as u32);
+        for _ in &REGISTERS {
+            s.extend_from_slice(&[I32, 0x01, I32_CONST, 0, END]);
+        }
+    });
+    section(&mut module, 7, |s| {
+        uleb(s, 2);
+        name_bytes(s, "memory");
+        s.extend_from_slice(&[0x02, 0]);
+        name_bytes(s, "run");
+        s.push(0x00);
+        uleb(s, RUN);
+    });
+    section(&mut module, 10, |s| {
+        let body = run_body(program);
+        uleb(s, 1);
+        uleb(s, body.len() as u32 + 3);
+        // One group of locals: VALUE and the registers, all i32
+        s.extend_from_slice(&[1, VALUE as u8, I32]);
+        s.extend_from_slice(&body);
+    });
+    Ok(module)
+}
+
+/// The code of `run`. The program is split into basic blocks, and `run` is a loop around a
+/// `br_table` which branches to the block whose number is in PC. Each block counts its cycles
+/// against the limit, runs, and sets PC to the block to run next.
+fn run_body(program: &[u8]) -> Vec<u8> {
+    let opcode = |addr: usize| program.get(addr).cloned().unwrap_or(0);
+    let wrap = |addr: usize| addr % MAX_PROGRAM_LEN;
+    let matching = |addr: usize| matching_bracket(program, addr as MAddr).map(|m| m as usize);
+
+    // Blocks begin at the start of the tape, where jumps land, after anything which jumps, and
+    // where the program ends and the zeroed rest of the exec tape begins. A halt is a block by
+    // itself, so that running again after a halt halts again.
+    let mut leaders = BTreeSet::new();
+    leaders.insert(0);
+    if program.len() < MAX_PROGRAM_LEN {
+        leaders.insert(program.len());
+    }
+    for addr in 0..program.len() {
+        match opcode(addr) {
+            4 | 5 => {
+                if let Some(target) = matching(addr) {
+                    leaders.insert(wrap(addr + 1));
+                    leaders.insert(wrap(target + 1));
+                }
+            }
+            15 => {
+                leaders.insert(addr);
+                leaders.insert(wrap(addr + 1));
+            }
+            19 => {
+                leaders.insert(wrap(addr + 1));
+            }
+            25 => {
+                leaders.insert(wrap(addr + 1));
+                leaders.insert(wrap(addr + 2));
+            }
+            _ => {}
+        }
+    }
+    let leaders: Vec<usize> = leaders.into_iter().collect();
+    let block_of = |addr: usize| leaders.binary_search(&wrap(addr)).unwrap() as u32;
+    let blocks = leaders.len() as u32;
+
+    let mut code = Vec::new();
+    for &register in &REGISTERS {
+        code.push(GLOBAL_GET);
+        uleb(&mut code, register - 1);
+        code.push(LOCAL_SET);
+        uleb(&mut code, register);
+    }
+    code.extend_from_slice(&[LOOP, EMPTY]);
+    for _ in 0..blocks {
+        code.extend_from_slice(&[BLOCK, EMPTY]);
+    }
+    code.extend_from_slice(&[LOCAL_GET, PC as u8, BR_TABLE]);
+    uleb(&mut code, blocks);
+    for block in 0..blocks {
+        uleb(&mut code, block);
+    }
+    uleb(&mut code, blocks - 1);
+
+    for (block, &start) in leaders.iter().enumerate() {
+        let block = block as u32;
+        code.push(END);
+        if opcode(start) == 15 && start < program.len() {
+            save(&mut code, block);
+            code.extend_from_slice(&[LOCAL_GET, AUX as u8, RETURN]);
+            continue;
+        }
+        let end = leaders
+            .get(block as usize + 1)
+            .cloned()
+            .unwrap_or(MAX_PROGRAM_LEN);
+
+        // Stop if the limit can't cover the whole block
+        code.extend_from_slice(&[LOCAL_GET, LIMIT as u8, I64_CONST]);
+        sleb(&mut code, (end - start) as i64);
+        code.extend_from_slice(&[I64_LT_U, IF, EMPTY]);
+        save(&mut code, block);
+        code.extend_from_slice(&[I32_CONST, 0x7F, RETURN, END]);
+        code.extend_from_slice(&[LOCAL_GET, LIMIT as u8, I64_CONST]);
+        sleb(&mut code, (end - start) as i64);
+        code.extend_from_slice(&[I64_SUB, LOCAL_SET, LIMIT as u8]);
+
+        let last = end - 1;
+        let mut addr = start;
+        while addr < last || (addr == last && !is_jump(opcode(last), matching(last))) {
+            // Runs of pointer moves and of arithmetic each become a single addition
+            let class = match opcode(addr) {
+                0 | 1 => Some(0),
+                2 | 3 => Some(2),
+                _ => None,
+            };
+            match class {
+                Some(class) => {
+                    let mut delta: i64 = 0;
+                    while addr < end && opcode(addr) & !1 == class {
+                        delta += if opcode(addr) & 1 == 1 { 1 } else { -1 };
+                        addr += 1;
+                    }
+                    if class == 0 {
+                        code.extend_from_slice(&[LOCAL_GET, DP as u8, I32_CONST]);
+                        sleb(&mut code, delta);
+                        code.extend_from_slice(&[I32_ADD, I32_CONST]);
+                        sleb(&mut code, 0xFFFF);
+                        code.extend_from_slice(&[I32_AND, LOCAL_SET, DP as u8]);
+                    } else {
+                        code.extend_from_slice(&[LOCAL_GET, DP as u8]);
+                        cell(&mut code);
+                        code.push(I32_CONST);
+                        sleb(&mut code, delta);
+                        code.push(I32_ADD);
+                        store_cell(&mut code);
+                    }
+                }
+                None => {
+                    instruction(&mut code, opcode(addr));
+                    addr += 1;
+                }
+            }
+        }
+
+        // Choose the next block
+        let next = block_of(end);
+        match opcode(last) {
+            4 | 5 if matching(last).is_some() => {
+                let target = matching(last).unwrap_or(0);
+                branch(&mut code, block_of(target + 1), next, opcode(last) == 4);
+            }
+            25 => branch(&mut code, block_of(last + 2), next, true),
+            19 => set_pc(&mut code, 0),
+            _ => set_pc(&mut code, next),
+        }
+        code.push(BR);
+        uleb(&mut code, blocks - 1 - block);
+    }
+    code.extend_from_slice(&[END, UNREACHABLE, END]);
+    code
+}
+
+/// Whether the instruction ends a block by choosing where to go next.
+fn is_jump(opcode: u8, matching: Option<usize>) -> bool {
+    match opcode {
+        4 | 5 => matching.is_some(),
+        19 | 25 => true,
+        _ => false,
+    }
+}
+
+/// Emit one instruction other than a jump or halt.
+fn instruction(code: &mut Vec<u8>, opcode: u8) {
+    match opcode {
+        6 => {
+            cell(code);
+            code.extend_from_slice(&[LOCAL_GET, CHANNEL as u8, CALL, OUTPUT as u8]);
+        }
+        // The end of input reads 0
+        7 => {
+            code.extend_from_slice(&[LOCAL_GET, DP as u8, CALL, INPUT as u8]);
+            code.extend_from_slice(&[LOCAL_TEE, VALUE as u8, I32_CONST, 0]);
+            code.extend_from_slice(&[LOCAL_GET, VALUE as u8, I32_CONST, 0, I32_GE_S, SELECT]);
+            store_cell(code);
+        }
+        // Push, growing memory when the stack reaches its end
+        8 => {
+            code.extend_from_slice(&[LOCAL_GET, SP as u8, I32_CONST]);
+            sleb(code, i64::from(STACK_BASE));
+            code.extend_from_slice(&[I32_ADD, MEMORY_SIZE, 0, I32_CONST, 16, I32_SHL, I32_GE_U]);
+            code.extend_from_slice(&[IF, EMPTY, I32_CONST, 1, MEMORY_GROW, 0, DROP, END]);
+            code.extend_from_slice(&[LOCAL_GET, SP as u8]);
+            cell(code);
+            code.extend_from_slice(&[I32_STORE8, 0]);
+            uleb(code, STACK_BASE);
+            code.extend_from_slice(&[LOCAL_GET, SP as u8, I32_CONST, 1, I32_ADD]);
+            code.extend_from_slice(&[LOCAL_SET, SP as u8]);
+        }
+        // Pop, reading 0 from an empty stack
+        9 => {
+            code.extend_from_slice(&[LOCAL_GET, DP as u8, LOCAL_GET, SP as u8, IF, I32]);
+            code.extend_from_slice(&[LOCAL_GET, SP as u8, I32_CONST, 1, I32_SUB]);
+            code.extend_from_slice(&[LOCAL_TEE, SP as u8, I32_LOAD8_U, 0]);
+            uleb(code, STACK_BASE);
+            code.extend_from_slice(&[ELSE, I32_CONST, 0, END]);
+            store_cell(code);
+        }
+        10 => {
+            cell(code);
+            code.extend_from_slice(&[LOCAL_SET, AUX as u8]);
+        }
+        11 => {
+            code.extend_from_slice(&[LOCAL_GET, DP as u8, LOCAL_GET, AUX as u8]);
+            store_cell(code);
+        }
+        12 => code.extend_from_slice(&[I32_CONST, 0, LOCAL_SET, AUX as u8]),
+        13 => aux_op(code, &[I32_CONST, 0xFF, 0x01, I32_XOR]),
+        14 => {
+            code.extend_from_slice(&[LOCAL_GET, AUX as u8]);
+            cell(code);
+            code.extend_from_slice(&[I32_AND, LOCAL_SET, AUX as u8]);
+        }
+        // Rotations within 8 bits
+        16 | 17 => {
+            let (left, right) = if opcode == 16 { (1, 7) } else { (7, 1) };
+            aux_op(
+                code,
+                &[
+                    I32_CONST, left, I32_SHL, LOCAL_GET, AUX as u8, I32_CONST, right, I32_SHR_U,
+                    I32_OR, I32_CONST, 0xFF, 0x01, I32_AND,
+                ],
+            );
+        }
+        22..=24 => {
+            code.extend_from_slice(&[LOCAL_GET, DP as u8]);
+            if opcode == 24 {
+                code.extend_from_slice(&[LOCAL_GET, AUX as u8]);
+                cell(code);
+            } else {
+                cell(code);
+                code.extend_from_slice(&[LOCAL_GET, AUX as u8]);
+            }
+            code.push(if opcode == 22 { I32_EQ } else { I32_LT_U });
+            store_cell(code);
+        }
+        26 => {
+            cell(code);
+            code.extend_from_slice(&[LOCAL_SET, CHANNEL as u8]);
+        }
+        _ => {}
+    }
+}
+
+/// Replace AUX with the result of `ops` applied to it.
+fn aux_op(code: &mut Vec<u8>, ops: &[u8]) {
+    code.extend_from_slice(&[LOCAL_GET, AUX as u8]);
+    code.extend_from_slice(ops);
+    code.extend_from_slice(&[LOCAL_SET, AUX as u8]);
+}
+
+/// Push the current cell.
+fn cell(code: &mut Vec<u8>) {
+    code.extend_from_slice(&[LOCAL_GET, DP as u8, I32_LOAD8_U, 0, 0]);
+}
+
+/// Store the value on top of the stack in the cell whose address is below it.
+fn store_cell(code: &mut Vec<u8>) {
+    code.extend_from_slice(&[I32_STORE8, 0, 0]);
+}
+
+/// Go to block `taken` if the current cell is zero (or nonzero, if `if_zero` is false), and to
+/// block `next` otherwise.
+fn branch(code: &mut Vec<u8>, taken: u32, next: u32, if_zero: bool) {
+    code.push(I32_CONST);
+    sleb(code, i64::from(taken));
+    code.push(I32_CONST);
+    sleb(code, i64::from(next));
+    cell(code);
+    if if_zero {
+        code.push(I32_EQZ);
+    } else {
+        code.extend_from_slice(&[I32_CONST, 0, I32_NE]);
+    }
+    code.extend_from_slice(&[SELECT, LOCAL_SET, PC as u8]);
+}
+
+fn set_pc(code: &mut Vec<u8>, block: u32) {
+    code.push(I32_CONST);
+    sleb(code, i64::from(block));
+    code.extend_from_slice(&[LOCAL_SET, PC as u8]);
+}
+
+/// Store the registers in their globals, to resume from the start of `block`.
+fn save(code: &mut Vec<u8>, block: u32) {
+    set_pc(code, block);
+    for &register in &REGISTERS {
+        code.push(LOCAL_GET);
+        uleb(code, register);
+        code.push(GLOBAL_SET);
+        uleb(code, register - 1);
+    }
+}
+
+/// Append a section with the given id, whose contents are written by `contents`.
+fn section(module: &mut Vec<u8>, id: u8, contents: impl FnOnce(&mut Vec<u8>)) {
+    let mut bytes = Vec::new();
+    contents(&mut bytes);
+    module.push(id);
+    uleb(module, bytes.len() as u32);
+    module.extend_from_slice(&bytes);
+}
+
+fn name_bytes(out: &mut Vec<u8>, name: &str) {
+    uleb(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+/// Append `value` as an unsigned LEB128 number.
+fn uleb(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Append `value` as a signed LEB128 number.
+fn sleb(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,202 @@
+extern crate sbrain;
+use sbrain::*;
+
+#[test]
+fn test_program_metrics() {
+    let metrics = program_metrics(&source_to_tape("[[+]]>"));
+    assert_eq!(metrics.instructions, 6);
+    assert_eq!(metrics.max_nesting, 2);
+    assert!(metrics.balanced);
+    assert_eq!(metrics.count(OpcodeClass::Loop), 4);
+    assert_eq!(metrics.count(OpcodeClass::Cell), 1);
+    assert_eq!(metrics.count(OpcodeClass::Pointer), 1);
+    assert_eq!(metrics.count(OpcodeClass::Io), 0);
+}
+
+#[test]
+fn test_program_metrics_unbalanced() {
+    assert!(!program_metrics(&source_to_tape("[[+]")).balanced);
+    assert!(!program_metrics(&source_to_tape("]+[")).balanced);
+    assert!(program_metrics(&source_to_tape("+.")).balanced);
+}
+
+#[test]
+fn test_program() {
+    let tape = source_to_tape("+[>[-]<-]]@");
+    let program = Program::new(&tape).unwrap();
+    assert_eq!(program.tape(), &tape[..]);
+    assert_eq!(&program[..2], &tape[..2]);
+    for addr in 0..tape.len() as MAddr {
+        assert_eq!(
+            program.matching_bracket(addr),
+            matching_bracket(&tape, addr),
+            "{}",
+            addr
+        );
+    }
+    assert_eq!(program.matching_bracket(1000), None);
+    assert!(!program.is_balanced());
+    assert!(Program::new(&source_to_tape("[[]]")).unwrap().is_balanced());
+    assert!(!Program::new(&source_to_tape("[[]")).unwrap().is_balanced());
+
+    // A program runs just like its tape
+    let mut machine = SBrainVM::new_buffered(None, &program).unwrap();
+    let mut plain = SBrainVM::new_buffered(None, &tape).unwrap();
+    assert_eq!(
+        machine.run(Some(100)).unwrap(),
+        plain.run(Some(100)).unwrap()
+    );
+    assert_eq!(program.into_tape(), tape);
+
+    // Unimplemented opcodes below 32 are allowed, unlike with `verify`
+    assert!(Program::new(&[3, 31, 15]).is_ok());
+    let mut tape = vec![3; 65537];
+    tape[20] = 32;
+    assert_eq!(
+        Program::new(&tape),
+        Err(vec![
+            VerifyError::TooLong { len: 65537 },
+            VerifyError::UnknownOpcode {
+                addr: 20,
+                opcode: 32
+            },
+        ])
+    );
+}
+
+#[test]
+fn test_verify() {
+    assert_eq!(verify(&source_to_tape("+[>,.<-]@")), Ok(()));
+
+    let mut tape = source_to_tape("+.");
+    tape.push(31);
+    assert_eq!(
+        verify(&tape),
+        Err(vec![VerifyError::UnknownOpcode {
+            addr: 2,
+            opcode: 31
+        }])
+    );
+
+    assert_eq!(
+        verify(&source_to_tape("][+[]")),
+        Err(vec![
+            VerifyError::UnbalancedBracket { addr: 0 },
+            VerifyError::UnbalancedBracket { addr: 1 },
+        ])
+    );
+
+    let mut tape = vec![3; 65537];
+    tape[10] = 5;
+    tape[20] = 255;
+    assert_eq!(
+        verify(&tape),
+        Err(vec![
+            VerifyError::TooLong { len: 65537 },
+            VerifyError::UnbalancedBracket { addr: 10 },
+            VerifyError::UnknownOpcode {
+                addr: 20,
+                opcode: 255
+            },
+        ])
+    );
+}
+
+#[test]
+fn test_matching_bracket() {
+    let tape = source_to_tape("[+[-]>[<]]");
+    assert_eq!(matching_bracket(&tape, 0), Some(9));
+    assert_eq!(matching_bracket(&tape, 9), Some(0));
+    assert_eq!(matching_bracket(&tape, 2), Some(4));
+    assert_eq!(matching_bracket(&tape, 4), Some(2));
+    assert_eq!(matching_bracket(&tape, 6), Some(8));
+    // Not a bracket, or past the end of the tape
+    assert_eq!(matching_bracket(&tape, 1), None);
+    assert_eq!(matching_bracket(&tape, 100), None);
+
+    let tape = source_to_tape("][[]");
+    assert_eq!(matching_bracket(&tape, 0), None);
+    assert_eq!(matching_bracket(&tape, 1), None);
+    assert_eq!(matching_bracket(&tape, 2), Some(3));
+}
+
+#[test]
+fn test_disassemble() {
+    assert_eq!(
+        disassemble(&source_to_tape("[+>]")),
+        "0000  [  loop-begin -> 0003\n\
+         0001    +  increment\n\
+         0002    >  pointer-right\n\
+         0003  ]  loop-end -> 0000\n"
+    );
+    // Unmatched brackets don't change the indentation
+    assert_eq!(
+        disassemble(&[5, 4, 3, 31]),
+        "0000  ]  loop-end (unmatched)\n\
+         0001  [  loop-begin (unmatched)\n\
+         0002  +  increment\n\
+         0003     unknown opcode 31\n"
+    );
+}
+
+#[test]
+fn test_tape_to_source() {
+    let source = "+++[>++<-]>.{}()^!&@/\\%~:;_'\"?$,";
+    assert_eq!(tape_to_source(&source_to_tape(source)), source);
+    assert_eq!(tape_to_source(&[]), "");
+
+    // Unknown opcodes are shown in comments, and dropped when transliterated again
+    let tape = [3, 31, 27, 255, 6];
+    assert_eq!(tape_to_source(&tape), "+#31##27##255#.");
+    assert_eq!(source_to_tape(&tape_to_source(&tape)), vec![3, 6]);
+}
+
+#[test]
+fn test_custom_mapping_round_trip() {
+    let mut mapping = Transliterator::spec_mapping();
+    mapping.insert('+', 2);
+    mapping.insert('-', 3);
+    let transliterator = Transliterator::with_mapping(mapping);
+
+    let tape = transliterator.compile("++[>-<+]");
+    assert_eq!(tape, vec![2, 2, 4, 1, 3, 0, 2, 5]);
+    assert_eq!(transliterator.tape_to_source(&tape), "++[>-<+]");
+    assert_eq!(tape_to_source(&tape), "--[>+<-]");
+    assert_eq!(
+        transliterator.disassemble(&tape[2..]),
+        "0000  [  loop-begin -> 0005\n\
+         0001    >  pointer-right\n\
+         0002    -  increment\n\
+         0003    <  pointer-left\n\
+         0004    +  decrement\n\
+         0005  ]  loop-end -> 0000\n"
+    );
+}
+
+#[test]
+fn test_scan_forward() {
+    let tape = source_to_tape(".+.>");
+    // The address scanning starts from counts
+    assert_eq!(scan_forward(&tape, 0, 6), Some(0));
+    assert_eq!(scan_forward(&tape, 1, 6), Some(2));
+    assert_eq!(scan_forward(&tape, 1, 3), Some(1));
+    assert_eq!(scan_forward(&tape, 3, 6), None);
+    assert_eq!(scan_forward(&tape, 0, 15), None);
+    assert_eq!(scan_forward(&tape, 100, 6), None);
+}
+
+#[test]
+fn test_can_halt() {
+    let reach = |source| can_halt(&source_to_tape(source));
+    assert_eq!(reach(",+.(!)@"), Reachability::DefinitelyReachesHalt);
+    assert_eq!(reach(",+."), Reachability::NoHaltPresent);
+    assert_eq!(reach("+[-]"), Reachability::NoHaltPresent);
+    // The only halt is inside a loop, which may never be entered
+    assert_eq!(reach(",[@]"), Reachability::MayNotHalt);
+    // A loop before the halt may never end
+    assert_eq!(reach("+[]@"), Reachability::MayNotHalt);
+    // A halt before the first loop is always reached
+    assert_eq!(reach("@[]"), Reachability::DefinitelyReachesHalt);
+    // The halt may be skipped
+    assert_eq!(reach(",?@"), Reachability::MayNotHalt);
+}
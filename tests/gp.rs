@@ -0,0 +1,93 @@
+#![cfg(feature = "std")]
+extern crate sbrain;
+use sbrain::gp::*;
+use sbrain::*;
+
+fn output_of(program: &[u8], input: &[u8]) -> Vec<u8> {
+    let mut input = make_input_vec(input);
+    let mut machine =
+        SBrainVM::new_buffered(Some(&mut input), program).expect("Could not build machine");
+    machine.run(Some(10_000)).expect("I/O failed");
+    machine.output().to_vec()
+}
+
+#[test]
+fn test_minimize_cat() {
+    let padded = source_to_tape("^,(^[.{>(,^]^(");
+    let inputs: &[&[u8]] = &[b"Hello, world!", b"", b"ab\x00cd"];
+    let minimized = minimize(&padded, inputs, Some(10_000)).unwrap();
+
+    assert!(minimized.len() <= source_to_tape(",[.>,]").len());
+    for input in inputs {
+        assert_eq!(output_of(&minimized, input), output_of(&padded, input));
+    }
+
+    assert!(matches!(
+        minimize(&vec![3; MAX_PROGRAM_LEN + 1], inputs, Some(10)),
+        Err(SBrainError::ProgramTooLong { len: 65537, .. })
+    ));
+}
+
+#[test]
+fn test_random_input() {
+    let input = random_input(&mut SplitMix64::new(42), 1000, InputDist::Printable);
+    assert_eq!(input.len(), 1000);
+    assert!(input.iter().all(|&b| (0x20..=0x7e).contains(&b)));
+    // Reproducible for the same seed
+    assert_eq!(
+        input,
+        random_input(&mut SplitMix64::new(42), 1000, InputDist::Printable)
+    );
+
+    let input = random_input(
+        &mut SplitMix64::new(1),
+        100,
+        InputDist::Range { low: 3, high: 5 },
+    );
+    assert!(input.iter().all(|&b| (3..=5).contains(&b)));
+    assert!(input.contains(&3) && input.contains(&5));
+}
+
+#[test]
+fn test_evaluate_lockstep() {
+    let mut sources = vec![
+        "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.\
+         +++.------.--------.>>+.>++.@"
+            .to_string(),
+        ",[.,]@".to_string(),
+        // Reaching as far left as the limit allows, then back to where it started
+        "+[<+]".to_string(),
+        "-[>-]".to_string(),
+        "<<<+>>>.+$.@".to_string(),
+    ];
+    let mut rng = SplitMix64::new(9);
+    for _ in 0..200 {
+        let source: String = (0..30)
+            .map(|_| {
+                let alphabet = b"<>-+[].,{}()^!&@/\\%~:;_'\"?$";
+                alphabet[(rng.next_u64() % alphabet.len() as u64) as usize] as char
+            })
+            .collect();
+        sources.push(source);
+    }
+    let programs: Vec<Vec<u8>> = sources.iter().map(|s| source_to_tape(s)).collect();
+    let programs: Vec<&[u8]> = programs.iter().map(|p| &p[..]).collect();
+
+    for &limit in &[0, 1, 40, 3000, 70_000] {
+        let results = evaluate_lockstep(&programs, b"Sbrain", limit).unwrap();
+        assert_eq!(results.len(), programs.len());
+        for ((source, program), result) in sources.iter().zip(&programs).zip(results) {
+            assert_eq!(
+                result,
+                evaluate(program, b"Sbrain", Some(limit)).unwrap(),
+                "{} with a limit of {}",
+                source,
+                limit
+            );
+        }
+    }
+
+    assert!(evaluate_lockstep(&[], b"", 10).unwrap().is_empty());
+    let too_long = vec![0; MAX_PROGRAM_LEN + 1];
+    assert!(evaluate_lockstep(&[&[], &too_long], b"", 10).is_err());
+}
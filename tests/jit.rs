@@ -0,0 +1,110 @@
+#![cfg(feature = "jit")]
+extern crate sbrain;
+use sbrain::gp::SplitMix64;
+use sbrain::*;
+
+/// Run `program` on `input` natively from the first cycle, or in the plain interpreter, in runs
+/// of `budget` cycles until it stops for any other reason or `total` cycles have run.
+fn outcome(
+    program: &[u8],
+    input: &[u8],
+    jit: bool,
+    budget: u32,
+    total: u32,
+) -> (u32, HaltReason, Vec<u8>, Vec<u8>, u16, u16, RunStats) {
+    let mut input = make_input_vec(input);
+    let mut machine = SBrainVM::new_buffered(Some(&mut input), program).unwrap();
+    if jit {
+        machine.set_jit(Some(0));
+    } else {
+        machine.set_fusion(false);
+    }
+    let mut cycles = 0;
+    let mut reason = HaltReason::CycleLimit;
+    while reason == HaltReason::CycleLimit && cycles < total {
+        let (ran, stopped) = machine.run(Some(budget.min(total - cycles))).unwrap();
+        cycles += ran;
+        reason = stopped;
+    }
+    let data = machine.data_tape()[..64].to_vec();
+    (
+        cycles,
+        reason,
+        machine.output().to_vec(),
+        data,
+        machine.data_p(),
+        machine.inst_p(),
+        machine.stats().clone(),
+    )
+}
+
+#[test]
+fn test_jit_matches_interpreter() {
+    let mut sources = vec![
+        "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.\
+         +++.------.--------.>>+.>++.@"
+            .to_string(),
+        ",[.,]@".to_string(),
+        "+++++[-]>---[+]<<<<[-]>>>>+++@".to_string(),
+        "-[>+>++<<-]>[-]>[+]<<<<<@".to_string(),
+        "+[>,.<]".to_string(),
+    ];
+    let mut rng = SplitMix64::new(11);
+    for _ in 0..50 {
+        let source: String = (0..40)
+            .map(|_| b"+-<>[].,@"[(rng.next_u64() % 9) as usize] as char)
+            .collect();
+        sources.push(source);
+    }
+
+    for source in &sources {
+        let program = source_to_tape(source);
+        for &budget in &[u32::MAX, 1, 7, 5000] {
+            assert_eq!(
+                outcome(&program, b"Hello!", true, budget, 50_000),
+                outcome(&program, b"Hello!", false, budget, 50_000),
+                "{} in runs of {}",
+                source,
+                budget
+            );
+        }
+    }
+}
+
+#[test]
+fn test_jit_threshold() {
+    // The program is compiled partway through the run, which carries on seamlessly
+    let program = source_to_tape("-[>-[>+<-]<-]>>.@");
+    let mut machine = SBrainVM::new_buffered(None, &program).unwrap();
+    machine.set_jit(Some(1000));
+    let jitted = machine.run(None).unwrap();
+    let output = machine.output().to_vec();
+
+    let mut machine = SBrainVM::new_buffered(None, &program).unwrap();
+    machine.set_fusion(false);
+    assert_eq!(jitted, machine.run(None).unwrap());
+    assert_eq!(output, machine.output());
+}
+
+#[test]
+fn test_jit_wide_cells() {
+    let program = source_to_tape("---[+]>+++[-]>-[>+<-]>.@");
+    let run = |jit: bool| {
+        let mut machine: SBrainVM<u32> = VmBuilder::new().program(&program).build().unwrap();
+        if jit {
+            machine.set_jit(Some(0));
+        } else {
+            machine.set_fusion(false);
+        }
+        let result = machine.run(Some(1_000_000)).unwrap();
+        (
+            result,
+            machine.data_tape()[..4].to_vec(),
+            machine.stats().clone(),
+        )
+    };
+    let (result, data, stats) = run(true);
+    assert_eq!(result, (1_000_000, HaltReason::CycleLimit));
+    assert_eq!(data[..2], [0, 0]);
+    assert_eq!((result, data, stats), run(false));
+}
@@ -0,0 +1,37 @@
+#![cfg(feature = "serde")]
+extern crate sbrain;
+use sbrain::*;
+
+#[test]
+fn test_json_literal() {
+    let code = source_to_tape("[+]@");
+    assert_eq!(
+        to_json(&code, &[1, 2]),
+        r#"{"code":[4,3,5,15],"data":[1,2]}"#
+    );
+}
+
+#[test]
+fn test_json_round_trip() {
+    let code = source_to_tape(",[.>,]");
+    let data = vec![0, 255, 17];
+    let json = to_json(&code, &data);
+    assert_eq!(from_json(&json).unwrap(), (code, data));
+    assert!(matches!(
+        from_json(r#"{"code":[256],"data":[]}"#),
+        Err(SBrainError::Json(_))
+    ));
+    assert!(matches!(from_json("not json"), Err(SBrainError::Json(_))));
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    let mut machine = SBrainVM::new(None, None, &source_to_tape("+++{>++(@")).unwrap();
+    machine.run(None).unwrap();
+    let snapshot = machine.snapshot();
+    let mut saved = Vec::new();
+    snapshot.save(&mut saved).expect("Could not save snapshot");
+    let loaded = VmSnapshot::load(&mut &saved[..]).expect("Could not load snapshot");
+    assert_eq!(loaded, snapshot);
+    assert_eq!(loaded.data, vec![3, 2]);
+}
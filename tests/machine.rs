@@ -0,0 +1,1859 @@
+#![cfg(feature = "std")]
+extern crate sbrain;
+use sbrain::*;
+
+fn run_source(source: &str, cycles: u32) -> (HaltReason, Vec<u8>) {
+    let program = source_to_tape(source);
+    let mut output = make_output_vec();
+    let reason = {
+        let mut machine =
+            SBrainVM::new(None, Some(&mut output), &program).expect("Could not build machine");
+        machine.run(Some(cycles)).expect("I/O failed").1
+    };
+    (reason, output.into_inner())
+}
+
+#[test]
+fn test_bracket_mode_lenient() {
+    let (reason, output) = run_source("+[.", 1000);
+    assert_eq!(reason, HaltReason::CycleLimit);
+    assert_eq!(output[0], 1);
+}
+
+#[test]
+fn test_bracket_mode_strict() {
+    let program = source_to_tape("+[.");
+    let mut output = make_output_vec();
+    {
+        let mut machine =
+            SBrainVM::new(None, Some(&mut output), &program).expect("Could not build machine");
+        machine.set_bracket_mode(BracketMode::Strict);
+        let (cycles, reason) = machine.run(Some(1000)).expect("I/O failed");
+        assert_eq!(reason, HaltReason::UnbalancedBracket { addr: 1 });
+        assert_eq!(cycles, 1);
+    }
+    assert!(output.into_inner().is_empty());
+}
+
+#[test]
+fn test_no_progress_spinner() {
+    let program = source_to_tape("+[]");
+    let mut machine = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    machine.set_no_progress_detection(Some(NoProgressConfig {
+        interval: 4,
+        repeats: 3,
+    }));
+    let (cycles, reason) = machine.run(Some(100_000)).expect("I/O failed");
+    assert_eq!(reason, HaltReason::NoProgress);
+    assert!(cycles < 100, "Took {} cycles to detect", cycles);
+}
+
+#[test]
+fn test_no_progress_output_resets() {
+    // Spins in place, but emits output every iteration
+    let program = source_to_tape("+[.]");
+    let mut machine = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    machine.set_no_progress_detection(Some(NoProgressConfig::default()));
+    let (_, reason) = machine.run(Some(10_000)).expect("I/O failed");
+    assert_eq!(reason, HaltReason::CycleLimit);
+}
+
+#[test]
+fn test_display() {
+    // Leave data_p at 3 and 7 in the aux register
+    let program = source_to_tape(">>>+++++++(");
+    let mut machine = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    machine.run(Some(11)).expect("I/O failed");
+    let display = format!("{}", machine);
+    assert!(display.contains("data_p: 3"), "{}", display);
+    assert!(display.contains("aux: 7"), "{}", display);
+    assert!(display.contains("[7]"), "{}", display);
+
+    let debug = format!("{:?}", machine);
+    assert!(debug.contains("data_p: 3"), "{}", debug);
+    assert!(debug.len() < 1000, "{}", debug);
+}
+
+#[test]
+fn test_run_metered() {
+    let mut weights = [1; 32];
+    // Make AND expensive
+    weights[14] = 10;
+
+    let expensive = source_to_tape("&&&&&&&&&&@");
+    let mut machine = SBrainVM::new(None, None, &expensive).expect("Could not build machine");
+    let (used, reason) = machine.run_metered(50, &weights).expect("I/O failed");
+    assert_eq!(reason, HaltReason::OutOfGas);
+    assert_eq!(used, 50);
+
+    let cheap = source_to_tape("++++++++++@");
+    let mut machine = SBrainVM::new(None, None, &cheap).expect("Could not build machine");
+    let (used, reason) = machine.run_metered(50, &weights).expect("I/O failed");
+    assert_eq!(reason, HaltReason::Halted(0));
+    assert_eq!(used, 10);
+}
+
+#[test]
+fn test_run_metered_class_costs() {
+    let mut costs = [1; OPCODE_CLASSES];
+    costs[OpcodeClass::Io.index()] = 10;
+    costs[OpcodeClass::Cell.index()] = 2;
+    let weights = OpcodeClass::cost_table(&costs);
+
+    let program = source_to_tape(",+.>@");
+    let mut machine = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    let (used, reason) = machine.run_metered(100, &weights).expect("I/O failed");
+    assert_eq!(reason, HaltReason::Halted(0));
+    // The halt itself is free
+    assert_eq!(used, 10 + 2 + 10 + 1);
+}
+
+#[test]
+fn test_run_metered_unit_weights() {
+    let program = source_to_tape("+[]");
+    let mut machine = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    let (used, reason) = machine.run_metered(100, &[1; 32]).expect("I/O failed");
+    assert_eq!(reason, HaltReason::OutOfGas);
+    assert_eq!(used, 100);
+
+    // Halting programs use as much gas as `run` counts cycles, with or without enough to finish
+    let program = source_to_tape("+++[>++<-]>(@");
+    for &gas in &[100, 24, 23, 1] {
+        let mut metered = SBrainVM::new(None, None, &program).expect("Could not build machine");
+        let mut counted = SBrainVM::new(None, None, &program).expect("Could not build machine");
+        let (used, reason) = metered.run_metered(gas, &[1; 32]).expect("I/O failed");
+        let (cycles, expected) = counted.run(Some(gas as u32)).expect("I/O failed");
+        assert_eq!(u64::from(cycles), used, "{} gas", gas);
+        match expected {
+            HaltReason::CycleLimit => assert_eq!(reason, HaltReason::OutOfGas),
+            _ => assert_eq!(reason, expected),
+        }
+    }
+}
+
+fn run_seeded(source: &str, seed: Option<u64>) -> Vec<u8> {
+    let program = source_to_tape(source);
+    let mut output = make_output_vec();
+    {
+        let mut machine =
+            SBrainVM::new(None, Some(&mut output), &program).expect("Could not build machine");
+        if let Some(seed) = seed {
+            machine.seed_rng(seed);
+        }
+        machine.run(Some(1000)).expect("I/O failed");
+    }
+    output.into_inner()
+}
+
+#[test]
+fn test_rng_deterministic() {
+    let source = "%.%.%.%.%.%.%.%.@";
+    let first = run_seeded(source, Some(42));
+    let second = run_seeded(source, Some(42));
+    assert_eq!(first, second);
+    assert_eq!(first.len(), 8);
+    assert_ne!(first, vec![0; 8]);
+    assert_ne!(first, run_seeded(source, Some(43)));
+}
+
+#[test]
+fn test_rng_disabled_by_default() {
+    assert_eq!(run_seeded("%.%.@", None), vec![0, 0]);
+}
+
+#[test]
+fn test_diff() {
+    let program = source_to_tape("+++>++>+{(!)<<@");
+    let mut before = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    let mut after = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    before.run(Some(3)).expect("I/O failed");
+    after.run(Some(14)).expect("I/O failed");
+
+    let diff = before.diff(&after);
+    assert_eq!(
+        diff.cells,
+        vec![
+            CellChange {
+                addr: 1,
+                old: 0,
+                new: 2
+            },
+            CellChange {
+                addr: 2,
+                old: 0,
+                new: 254
+            },
+        ]
+    );
+    assert_eq!(diff.data_p_delta, 0);
+    assert_eq!(diff.inst_p_delta, 11);
+    assert_eq!(diff.auxi_r, Some((0, 254)));
+    assert_eq!(diff.stack_popped, Vec::<u8>::new());
+    assert_eq!(diff.stack_pushed, vec![1]);
+
+    assert!(after.diff(&after).is_empty());
+}
+
+#[test]
+fn test_load_data_at() {
+    let source = format!("{}.>.>.<<<.", ">".repeat(100));
+    let program = source_to_tape(&source);
+    let mut output = make_output_vec();
+    {
+        let mut machine =
+            SBrainVM::new(None, Some(&mut output), &program).expect("Could not build machine");
+        machine.load_data(&[9]).unwrap();
+        machine.load_data_at(100, &[1, 2, 3]).unwrap();
+        machine.run(Some(1000)).expect("I/O failed");
+    }
+    assert_eq!(output.into_inner(), vec![1, 2, 3, 0]);
+}
+
+#[test]
+fn test_load_data_at_overflow() {
+    let mut machine = SBrainVM::new(None, None, &[]).expect("Could not build machine");
+    assert!(machine.load_data_at(65534, &[1, 2]).is_ok());
+    assert!(matches!(
+        machine.load_data_at(65535, &[1, 2]),
+        Err(SBrainError::DataTooLong {
+            offset: 65535,
+            len: 2
+        })
+    ));
+}
+
+#[test]
+fn test_error_variants() {
+    let program = vec![0; 65537];
+    let error = SBrainVM::new(None, None, &program).err().unwrap();
+    assert!(matches!(
+        error,
+        SBrainError::ProgramTooLong { len: 65537, .. }
+    ));
+    assert_eq!(
+        error.to_string(),
+        "Program has 65537 instructions, but at most 65536 can be loaded."
+    );
+    assert!(std::error::Error::source(&error).is_none());
+
+    // Errors from the machine's I/O convert, keeping the original as the source
+    let io_error = std::io::Error::other("disk on fire");
+    let error = SBrainError::from(io_error);
+    assert!(matches!(error, SBrainError::Io(_)));
+    assert!(std::error::Error::source(&error).is_some());
+}
+
+#[test]
+fn test_output_buffer() {
+    let program = source_to_tape("+++.>++.@");
+    let mut machine = SBrainVM::new_buffered(None, &program).expect("Could not build machine");
+    machine.run(Some(1000)).expect("I/O failed");
+    assert_eq!(machine.output(), &[3, 2]);
+
+    let mut machine = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    machine.run(Some(1000)).expect("I/O failed");
+    assert_eq!(machine.output_string(), "");
+}
+
+#[test]
+fn test_halt_reason() {
+    // Halting with exit code 0 is distinct from running out of cycles
+    match run_source("@", 1000).0 {
+        HaltReason::Halted(code) => assert_eq!(code, 0),
+        other => panic!("Expected a clean halt, got {:?}", other),
+    }
+    assert_eq!(run_source("+[]", 1000).0, HaltReason::CycleLimit);
+
+    assert_eq!(run_source("(!)@", 1000).0.exit_code(), Some(255));
+    assert_eq!(run_source("+[]", 1000).0.exit_code(), None);
+}
+
+#[test]
+fn test_feed_input() {
+    // Echo input forever, skipping zeros (which is what an empty queue reads as)
+    let program = source_to_tape("+[>,[.,]<]");
+    let mut machine = SBrainVM::new_buffered(None, &program).expect("Could not build machine");
+    machine.run(Some(20)).expect("I/O failed");
+    assert_eq!(machine.output_string(), "");
+
+    machine.feed_input(b"hi");
+    machine.run(Some(50)).expect("I/O failed");
+    assert_eq!(machine.output_string(), "hi");
+
+    machine.feed_input(b"yo");
+    machine.run(Some(50)).expect("I/O failed");
+    assert_eq!(machine.output_string(), "hiyo");
+}
+
+#[test]
+fn test_stack_starts_empty() {
+    let program = source_to_tape("+}");
+    let mut before = SBrainVM::new(None, None, &program)
+        .expect("Could not build machine")
+        .with_stack_capacity(1024);
+    let mut after = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    before.run(Some(1)).expect("I/O failed");
+    after.run(Some(2)).expect("I/O failed");
+
+    // The pop cleared the cell, but there was nothing on the stack to remove
+    let diff = before.diff(&after);
+    assert_eq!(diff.cells[0].new, 0);
+    assert!(diff.stack_popped.is_empty());
+    assert!(format!("{}", after).contains("stack: 0 values"));
+}
+
+#[test]
+fn test_stack_pop_pushed_zero() {
+    let program = source_to_tape("{+}");
+    let mut before = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    let mut after = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    before.run(Some(2)).expect("I/O failed");
+    after.run(Some(3)).expect("I/O failed");
+
+    let diff = before.diff(&after);
+    assert_eq!(diff.cells[0].new, 0);
+    assert_eq!(diff.stack_popped, vec![0]);
+}
+
+fn run_arith(source: &str, mode: ArithMode) -> Vec<u8> {
+    let program = source_to_tape(source);
+    let mut machine = SBrainVM::new_buffered(None, &program).expect("Could not build machine");
+    machine.set_arithmetic_mode(mode);
+    machine.load_data(&[255]).unwrap();
+    machine.run(Some(1000)).expect("I/O failed");
+    machine.output().to_vec()
+}
+
+#[test]
+fn test_arithmetic_mode() {
+    assert_eq!(run_arith("+.>-.@", ArithMode::Wrapping), vec![0, 255]);
+    assert_eq!(run_arith("+.>-.@", ArithMode::Saturating), vec![255, 0]);
+}
+
+fn run_loop_mode(source: &str, input: &[u8], mode: LoopMode) -> Vec<u8> {
+    let program = source_to_tape(source);
+    let mut input = make_input_vec(input);
+    let mut machine =
+        SBrainVM::new_buffered(Some(&mut input), &program).expect("Could not build machine");
+    machine.set_loop_mode(mode);
+    machine.run(Some(10_000)).expect("I/O failed");
+    machine.output().to_vec()
+}
+
+#[test]
+fn test_jump_stack_matches_rescan() {
+    let cases: &[(&str, &[u8])] = &[
+        (",>,>,<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<] >>>>.", &[20, 0, 2]),
+        // Nested counting loops: print 3 * 4 countdowns
+        ("+++[>++++[>+.<-]<-]@", &[]),
+        (",[.,]", b"Hello"),
+        ("+[.", &[]),
+    ];
+    for &(source, input) in cases {
+        assert_eq!(
+            run_loop_mode(source, input, LoopMode::JumpStack),
+            run_loop_mode(source, input, LoopMode::Rescan),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn test_jump_stack_depth() {
+    let program = source_to_tape("+[>+[@]]");
+    let mut machine = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    machine.set_loop_mode(LoopMode::JumpStack);
+    assert_eq!(machine.run(Some(100)).expect("I/O failed").1, HaltReason::Halted(0));
+    assert_eq!(machine.jump_stack(), &[1, 4]);
+}
+
+#[test]
+fn test_run_stats() {
+    let program = source_to_tape("+++[>+<-]>.{}(!)@");
+    let mut machine = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    let (cycles, _) = machine.run(Some(1000)).expect("I/O failed");
+    let stats = machine.stats().clone();
+
+    // The halt instruction is counted, but is not a cycle
+    assert_eq!(stats.instructions, u64::from(cycles) + 1);
+    assert_eq!(stats.class_cycles.iter().sum::<u64>(), stats.instructions);
+    assert_eq!(stats.cycles_in(OpcodeClass::Pointer), 3 * 2 + 1);
+    assert_eq!(stats.cycles_in(OpcodeClass::Cell), 3 + 3 * 2);
+    assert_eq!(stats.cycles_in(OpcodeClass::Loop), 1 + 3);
+    assert_eq!(stats.cycles_in(OpcodeClass::Io), 1);
+    assert_eq!(stats.cycles_in(OpcodeClass::Stack), 2);
+    assert_eq!(stats.cycles_in(OpcodeClass::Aux), 2);
+    assert_eq!(stats.cycles_in(OpcodeClass::Bitwise), 1);
+    assert_eq!(stats.cycles_in(OpcodeClass::Other), 1);
+
+    // Per-opcode counts: `+` runs 3 times before the loop and 3 times in it
+    assert_eq!(stats.opcodes.iter().sum::<u64>(), stats.instructions);
+    assert_eq!(stats.executions_of(3), 6);
+    assert_eq!(stats.executions_of(2), 3);
+    assert_eq!(stats.executions_of(15), 1);
+    assert_eq!(stats.executions_of(18), 0);
+
+    machine.reset_stats();
+    assert_eq!(machine.stats().instructions, 0);
+}
+
+#[test]
+fn test_coverage() {
+    // The loop body is never entered
+    let program = source_to_tape(">[+++]<@");
+    let mut machine = SBrainVM::new(None, None, &program).expect("Could not build machine");
+    assert!(machine.coverage().is_empty());
+    machine.set_coverage(true);
+    machine.run(Some(1000)).expect("I/O failed");
+
+    assert_eq!(
+        machine.coverage(),
+        &[true, true, false, false, false, false, true, true]
+    );
+    assert_eq!(machine.coverage_percent(), 50.0);
+}
+
+#[test]
+fn test_reload_program() {
+    // Leaves 3 in cell 1 and 2 on the stack, then spins
+    let first = source_to_tape("++{>+++[]");
+    let second = source_to_tape(".}.@");
+    let mut machine = SBrainVM::new_buffered(None, &first).expect("Could not build machine");
+    machine.run(Some(100)).expect("I/O failed");
+
+    machine.set_breakpoint(3);
+    machine.set_breakpoint(4);
+    machine.set_breakpoint(8);
+    machine.reload_program(&second, true).unwrap();
+    // Only the breakpoint that still lands inside the new program survives
+    assert_eq!(machine.breakpoints().iter().collect::<Vec<_>>(), vec![&3]);
+    let (_, reason) = machine.run(Some(100)).expect("I/O failed");
+    assert_eq!(reason, HaltReason::Breakpoint { addr: 3 });
+    let (_, reason) = machine.run(Some(100)).expect("I/O failed");
+    assert_eq!(reason, HaltReason::Halted(0));
+    assert_eq!(machine.output(), &[3, 2]);
+}
+
+#[test]
+fn test_load_shorter_program() {
+    // The tail of the longer program must not run after the shorter one
+    let mut machine =
+        SBrainVM::new_buffered(None, &source_to_tape("+++++.@")).expect("Could not build machine");
+    machine.load_program(&source_to_tape("+.")).unwrap();
+    machine.run(Some(10)).expect("I/O failed");
+    assert_eq!(machine.output(), &[1]);
+}
+
+#[test]
+fn test_init_data() {
+    let program = source_to_tape(",.>.@");
+    let mut machine = SBrainVM::new_buffered(None, &program).expect("Could not build machine");
+    machine.init_data(InitPattern::Fill(0xFF));
+    machine.run(Some(100)).expect("I/O failed");
+    // The read overwrote cell 0 with EOF, but cell 1 was untouched
+    assert_eq!(machine.output(), &[0, 0xFF]);
+}
+
+#[test]
+fn test_init_data_random() {
+    let program = source_to_tape(".>.>.>.@");
+    let run = |seed| {
+        let mut machine =
+            SBrainVM::new_buffered(None, &program).expect("Could not build machine");
+        machine.init_data(InitPattern::Random { seed });
+        machine.run(Some(100)).expect("I/O failed");
+        machine.output().to_vec()
+    };
+    assert_eq!(run(7), run(7));
+    assert_ne!(run(7), run(8));
+}
+
+#[test]
+fn test_iterator_input() {
+    // Echo five inputs, including the leading zero
+    let program = source_to_tape(",.,.,.,.,.,.@");
+    let mut output = make_output_vec();
+    {
+        let mut machine = SBrainVM::new_from_iter(0u8..5, Some(&mut output), &program)
+            .expect("Could not build machine");
+        machine.run(Some(100)).expect("I/O failed");
+    }
+    // The sixth read is past the end of the iterator
+    assert_eq!(output.into_inner(), vec![0, 1, 2, 3, 4, 0]);
+}
+
+#[test]
+fn test_restart() {
+    // Swap each two-byte record, restarting for the next one until the input runs out
+    let program = source_to_tape(",[>,.<.~]@");
+    let mut input: &[u8] = b"abcd";
+    let mut machine = SBrainVM::new_buffered(Some(&mut input), &program).unwrap();
+    let (_, reason) = machine.run(Some(1000)).expect("I/O failed");
+    assert_eq!(reason, HaltReason::Halted(0));
+    assert_eq!(machine.output(), b"badc");
+
+    // A restart takes a cycle, so restarting forever still hits the limit
+    let (reason, _) = run_source("~", 1000);
+    assert_eq!(reason, HaltReason::CycleLimit);
+}
+
+struct AbortAfter {
+    cycles: u32,
+    ops: Vec<u8>,
+}
+
+impl Observer for AbortAfter {
+    fn on_cycle(&mut self, _vm: &SBrainVM, op: u8) -> ControlFlow {
+        self.ops.push(op);
+        if self.ops.len() as u32 >= self.cycles {
+            ControlFlow::Break
+        } else {
+            ControlFlow::Continue
+        }
+    }
+}
+
+#[test]
+fn test_run_observed() {
+    let program = source_to_tape("+[>+]");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    let mut observer = AbortAfter {
+        cycles: 10,
+        ops: Vec::new(),
+    };
+    let (cycles, reason) = machine
+        .run_observed(&mut observer, Some(1000))
+        .expect("I/O failed");
+    assert_eq!(reason, HaltReason::Aborted);
+    assert_eq!(cycles, 10);
+    assert_eq!(observer.ops, source_to_tape("+[>+]>+]>+"));
+    assert_eq!(reason.exit_code(), None);
+}
+
+#[test]
+fn test_self_modifying() {
+    // Move data_p to 100 in steps of 10, then write a halt instruction there. The program ends
+    // well before 100; without the write, the zeroed exec tape after it moves data_p left forever.
+    let program = source_to_tape("10+[[-10>+10<]10>-]15+;");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    assert_eq!(machine.run(Some(10000)).unwrap().1, HaltReason::CycleLimit);
+
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    machine.set_self_modifying(true);
+    assert_eq!(machine.run(Some(10000)).unwrap().1, HaltReason::Halted(0));
+
+    // A program can read its own code
+    let program = source_to_tape(":.@");
+    let mut machine = SBrainVM::new_buffered(None, &program).unwrap();
+    machine.set_self_modifying(true);
+    machine.run(Some(100)).unwrap();
+    assert_eq!(machine.output(), &[20]);
+}
+
+#[test]
+fn test_jump_stack_invalidation() {
+    // Stop program A inside its inner loop, with loops recorded on the jump stack
+    let mut machine = SBrainVM::new_buffered(None, &source_to_tape("++[>++[-]<-]")).unwrap();
+    machine.set_loop_mode(LoopMode::JumpStack);
+    machine.run(Some(7)).unwrap();
+    assert_eq!(machine.jump_stack(), &[2, 6]);
+
+    // Program B's loops are elsewhere; it must not follow A's recorded jumps. The data tape is
+    // kept, so B starts on a cell holding 2.
+    machine
+        .reload_program(&source_to_tape("+++[>+.<-]@"), true)
+        .unwrap();
+    assert!(machine.jump_stack().is_empty());
+    let (_, reason) = machine.run(Some(1000)).unwrap();
+    assert_eq!(reason, HaltReason::Halted(0));
+    assert_eq!(machine.output(), &[1, 2, 3, 4, 5]);
+
+    // A loop whose `[` is overwritten at runtime no longer loops
+    let mut machine = SBrainVM::new_buffered(None, &source_to_tape("++[>>;<<-].@")).unwrap();
+    machine.set_loop_mode(LoopMode::JumpStack);
+    machine.set_self_modifying(true);
+    machine.run(Some(1000)).unwrap();
+    assert_eq!(machine.output(), &[1]);
+}
+
+/// A reader which counts how many times it is read from.
+struct CountingReader<'a> {
+    data: &'a [u8],
+    reads: usize,
+}
+
+impl<'a> std::io::Read for CountingReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reads += 1;
+        self.data.read(buf)
+    }
+}
+
+#[test]
+fn test_buffered_input() {
+    let data: Vec<u8> = (0..100_000).map(|i| (i % 255) as u8 + 1).collect();
+    let mut reader = CountingReader {
+        data: &data,
+        reads: 0,
+    };
+    let program = source_to_tape(",[.,]@");
+    {
+        let mut machine = SBrainVM::new_buffered(Some(&mut reader), &program).unwrap();
+        let (_, reason) = machine.run(None).expect("I/O failed");
+        assert_eq!(reason, HaltReason::Halted(0));
+        assert_eq!(machine.output(), &data[..]);
+    }
+    assert!(reader.reads < 100, "{} reads", reader.reads);
+}
+
+#[test]
+fn test_comparisons() {
+    // (cell, aux, [==, <, >])
+    let cases: &[(u8, u8, [u8; 3])] = &[
+        (3, 3, [1, 0, 0]),
+        (2, 3, [0, 1, 0]),
+        (4, 3, [0, 0, 1]),
+        (0, 0, [1, 0, 0]),
+        (255, 255, [1, 0, 0]),
+        (0, 255, [0, 1, 0]),
+        (255, 0, [0, 0, 1]),
+    ];
+    for &(cell, aux, expected) in cases {
+        for (op, &result) in ["_", "'", "\""].iter().zip(expected.iter()) {
+            let source = format!("{}+(>{}+{}.@", aux, cell, op);
+            let (reason, output) = run_source(&source, 1000);
+            assert_eq!(reason, HaltReason::Halted(aux), "{}", source);
+            assert_eq!(output, vec![result], "{}", source);
+        }
+    }
+}
+
+#[test]
+fn test_output_encoding() {
+    let program = source_to_tape("171+.@");
+    let cases = [
+        (OutputEncoding::Byte, vec![0xab]),
+        (OutputEncoding::LittleEndian(4), vec![0xab, 0, 0, 0]),
+        (OutputEncoding::BigEndian(4), vec![0, 0, 0, 0xab]),
+        (OutputEncoding::BigEndian(1), vec![0xab]),
+    ];
+    for &(enc, ref expected) in cases.iter() {
+        let mut machine = SBrainVM::new_buffered(None, &program).unwrap();
+        machine.set_output_encoding(enc);
+        machine.run(Some(1000)).expect("I/O failed");
+        assert_eq!(machine.output(), &expected[..], "{:?}", enc);
+    }
+}
+
+#[test]
+fn test_try_clone() {
+    let mut template = SBrainVM::new_buffered(None, &[]).unwrap();
+    template.set_arithmetic_mode(ArithMode::Saturating);
+    template.feed_input(&[7]);
+
+    let run_copy = |source: &str| {
+        let mut machine = template.try_clone().unwrap();
+        machine.load_program(&source_to_tape(source)).unwrap();
+        machine.run(Some(100)).unwrap();
+        machine.output().to_vec()
+    };
+    assert_eq!(run_copy(",+.@"), vec![8]);
+    // Each copy starts from the template's state, including its saturating arithmetic
+    assert_eq!(run_copy(",--------.@"), vec![0]);
+    assert!(template.output().is_empty());
+}
+
+#[test]
+fn test_try_clone_borrowed() {
+    // Machines borrowing their I/O can't be copied
+    let mut output = make_output_vec();
+    let machine = SBrainVM::new(None, Some(&mut output), &[]).unwrap();
+    assert!(machine.try_clone().is_none());
+}
+
+#[test]
+fn test_signed_comparisons() {
+    // 250 is -6 when signed; compared against an aux of 2
+    let compare = |op: &str, signed: bool| {
+        let program = source_to_tape(&format!("2+(>250+{}.@", op));
+        let mut machine = SBrainVM::new_buffered(None, &program).unwrap();
+        machine.set_signedness(signed);
+        machine.run(Some(1000)).unwrap();
+        machine.output()[0]
+    };
+    assert_eq!(compare("'", false), 0);
+    assert_eq!(compare("\"", false), 1);
+    assert_eq!(compare("'", true), 1);
+    assert_eq!(compare("\"", true), 0);
+    // Equality doesn't depend on signedness
+    assert_eq!(compare("_", true), 0);
+}
+
+/// A writer which counts how many times it is written to and flushed.
+#[derive(Default)]
+struct CountingWriter {
+    data: Vec<u8>,
+    writes: usize,
+    flushes: usize,
+}
+
+impl std::io::Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writes += 1;
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flushes += 1;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_batched_output() {
+    let mut writer = CountingWriter::default();
+    let program = source_to_tape("+10000.");
+    {
+        let mut machine = SBrainVM::new(None, Some(&mut writer), &program).unwrap();
+        // Stops on the cycle limit, not a halt, but the output is still all written
+        let (_, reason) = machine.run(Some(10001)).expect("I/O failed");
+        assert_eq!(reason, HaltReason::CycleLimit);
+    }
+    assert_eq!(writer.data, vec![1; 10000]);
+    assert!(writer.writes <= 3, "{} writes", writer.writes);
+    assert_eq!(writer.flushes, 1);
+}
+
+#[test]
+fn test_run_report() {
+    // Divide 20 by 2, using cells 0 to 6 and no stack
+    let program = source_to_tape(",>,>,<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<] >>>>.@");
+
+    let mut input: &[u8] = &[20, 0, 2];
+    let mut machine = SBrainVM::new_buffered(Some(&mut input), &program).unwrap();
+    let (cycles, reason) = machine.run(Some(10000)).expect("I/O failed");
+    assert_eq!(machine.output(), &[10]);
+
+    let mut input: &[u8] = &[20, 0, 2];
+    let mut machine = SBrainVM::new_buffered(Some(&mut input), &program).unwrap();
+    let report = machine.run_report(Some(10000)).expect("I/O failed");
+    assert_eq!(
+        report,
+        RunReport {
+            cycles,
+            halt_reason: reason,
+            peak_stack: 0,
+            cells_touched: 7,
+            output_len: machine.output().len(),
+            input_reads: 3,
+            instructions_covered: program.len(),
+            program_len: program.len(),
+        }
+    );
+    assert_eq!(report.coverage(), 1.0);
+
+    // Coverage only counts what this run executed, but adds to coverage being kept
+    let program = source_to_tape("+[-]>[+]@");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    machine.set_coverage(true);
+    let report = machine.run_report(None).expect("I/O failed");
+    assert_eq!(report.instructions_covered, 7);
+    assert_eq!(report.coverage(), 7.0 / 9.0);
+    assert_eq!(machine.coverage().iter().filter(|&&c| c).count(), 7);
+    machine.reload_program(&program, true).unwrap();
+    assert_eq!(machine.run_report(Some(1)).unwrap().instructions_covered, 1);
+    assert_eq!(machine.coverage().iter().filter(|&&c| c).count(), 7);
+
+    // Cells past the end of the starting tape are counted by their own addresses
+    let config = VmConfig {
+        data_len: 4,
+        ..VmConfig::default()
+    };
+    let mut machine = SBrainVM::new_with_config(None, None, &source_to_tape(">>>>><@"), config)
+        .unwrap()
+        .with_address_width(AddressWidth::Growable);
+    assert_eq!(machine.run_report(None).unwrap().cells_touched, 6);
+
+    // Discarded output isn't counted
+    let program = source_to_tape("+.$.@");
+    let mut machine = SBrainVM::new_buffered(None, &program).unwrap();
+    assert_eq!(machine.run_report(None).unwrap().output_len, 1);
+    assert_eq!(machine.output(), &[1]);
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    assert_eq!(machine.run_report(None).unwrap().output_len, 0);
+}
+
+#[test]
+fn test_random_tapes_never_panic() {
+    // xorshift64, so the tapes are the same on every run
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    for i in 0..5000 {
+        let len = (next() % 64) as usize;
+        let tape: Vec<u8> = (0..len)
+            .map(|_| {
+                // Mostly implemented opcodes, but some of everything
+                let byte = next() as u8;
+                if byte < 224 {
+                    byte % 32
+                } else {
+                    byte
+                }
+            })
+            .collect();
+        let mut input: &[u8] = &[3, 0, 255, 7];
+        let mut machine = SBrainVM::new_buffered(Some(&mut input), &tape).unwrap();
+        machine.feed_input(&[1, 2]);
+        // Vary the configuration so every mode gets exercised
+        if i % 2 == 1 {
+            machine.set_loop_mode(LoopMode::JumpStack);
+        }
+        if i % 3 == 1 {
+            machine.set_bracket_mode(BracketMode::Strict);
+        }
+        if i % 5 < 2 {
+            machine.set_self_modifying(true);
+            machine.seed_rng(i);
+        }
+        if i % 7 == 1 {
+            machine.set_arithmetic_mode(ArithMode::Saturating);
+            machine.set_signedness(true);
+            machine.set_output_encoding(OutputEncoding::BigEndian(9));
+        }
+        if i % 11 == 1 {
+            machine.set_no_progress_detection(Some(NoProgressConfig::default()));
+        }
+        if i % 13 == 1 {
+            machine.run_metered(300, &[2; 32]).expect("I/O failed");
+        } else {
+            machine.run(Some(300)).expect("I/O failed");
+        }
+        let _ = machine.to_string();
+    }
+}
+
+#[test]
+fn test_skip() {
+    // The cell is 0, so the first + is skipped
+    let (reason, output) = run_source("?+.@", 1000);
+    assert_eq!(reason, HaltReason::Halted(0));
+    assert_eq!(output, vec![0]);
+    // The cell isn't 0, so the second + runs
+    let (_, output) = run_source("+?+.@", 1000);
+    assert_eq!(output, vec![2]);
+
+    // A skip takes one cycle, and the skipped instruction none
+    let program = source_to_tape("?++@");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    assert_eq!(
+        machine.run(None).expect("I/O failed"),
+        (2, HaltReason::Halted(0))
+    );
+}
+
+#[test]
+fn test_output_channels() {
+    // Output 1 to 4, switching between channel 1 for odd values and channel 0 for even ones
+    let program = source_to_tape(">+<+$>.+<-$>.+<+$>.+<-$>.@");
+    let mut even = Vec::new();
+    let mut odd = Vec::new();
+    {
+        let outputs: Vec<Box<dyn std::io::Write>> = vec![Box::new(&mut even), Box::new(&mut odd)];
+        let mut machine = SBrainVM::new_multi_output(None, outputs, &program).unwrap();
+        let (_, reason) = machine.run(Some(1000)).expect("I/O failed");
+        assert_eq!(reason, HaltReason::Halted(0));
+    }
+    assert_eq!(even, vec![2, 4]);
+    assert_eq!(odd, vec![1, 3]);
+
+    // With a single output, only channel 0 is kept
+    let (_, output) = run_source(">+<+$>.+<-$>.+<+$>.+<-$>.@", 1000);
+    assert_eq!(output, vec![2, 4]);
+}
+
+#[test]
+fn test_run_checkpointed() {
+    let program = source_to_tape("+[>+]");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    let mut calls = 0;
+    let mut last_state = String::new();
+    let (cycles, reason) = machine
+        .run_checkpointed(100, Some(1050), |vm| {
+            calls += 1;
+            last_state = vm.to_string();
+        })
+        .expect("I/O failed");
+    assert_eq!(reason, HaltReason::CycleLimit);
+    assert_eq!(calls, cycles / 100);
+    // The last checkpoint came before the run ended
+    assert_ne!(last_state, machine.to_string());
+}
+
+#[test]
+fn test_undo_last() {
+    let program = source_to_tape("+++>+{>-}@");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    machine.set_undo_depth(4);
+    let (cycles, reason) = machine.run(Some(100)).unwrap();
+    assert_eq!((cycles, reason), (9, HaltReason::Halted(0)));
+
+    // Undo the pop, the decrement, and the move, but not the push
+    machine.undo_last(3).unwrap();
+    let mut expected = SBrainVM::new(None, None, &program).unwrap();
+    expected.run(Some(6)).unwrap();
+    assert!(machine.diff(&expected).is_empty(), "{:?}", machine.diff(&expected));
+
+    // Only one more instruction is recorded
+    assert_eq!(
+        machine.undo_last(2),
+        Err(UndoError::NotEnoughHistory {
+            requested: 2,
+            available: 1
+        })
+    );
+    machine.undo_last(1).unwrap();
+    let mut expected = SBrainVM::new(None, None, &program).unwrap();
+    expected.run(Some(5)).unwrap();
+    assert!(machine.diff(&expected).is_empty());
+}
+
+#[test]
+fn test_halt_exit_code() {
+    // `@` is the one halt instruction, in source and on the tape alike
+    assert_eq!(source_to_tape("@"), vec![15]);
+    assert_eq!(Transliterator::new().character(15), Some('@'));
+
+    let (reason, _) = run_source("42+(@", 100);
+    assert_eq!(reason, HaltReason::Halted(42));
+    assert_eq!(reason.exit_code(), Some(42));
+    assert_eq!(simple_run_limited("42+(@", 100), Some(42));
+}
+
+#[test]
+fn test_input_exhausted() {
+    let program = source_to_tape(",.,.,.,.");
+    let mut input: &[u8] = &[1, 2, 3];
+    let mut machine = SBrainVM::new_buffered(Some(&mut input), &program).unwrap();
+    machine.run(Some(6)).unwrap();
+    assert!(!machine.input_exhausted());
+    machine.run(Some(1)).unwrap();
+    assert!(machine.input_exhausted());
+    machine.run(Some(1)).unwrap();
+    assert_eq!(machine.output(), &[1, 2, 3, 0]);
+
+    // Halting instead leaves the cell alone
+    let program = source_to_tape(",,,,.");
+    let mut input: &[u8] = &[1, 2, 3];
+    let mut machine = SBrainVM::new_buffered(Some(&mut input), &program).unwrap();
+    machine.set_halt_on_exhausted_input(true);
+    assert_eq!(
+        machine.run(Some(100)).unwrap(),
+        (3, HaltReason::InputExhausted)
+    );
+    assert!(machine.output().is_empty());
+}
+
+#[test]
+fn test_output_byte_order() {
+    let output = |enc| {
+        let mut machine = SBrainVM::new_buffered(None, &source_to_tape("201+.@")).unwrap();
+        machine.set_output_encoding(enc);
+        machine.run(Some(1000)).unwrap();
+        machine.output().to_vec()
+    };
+    // The same bytes whatever the host's byte order
+    let le = output(OutputEncoding::wide(4));
+    assert_eq!(le, vec![201, 0, 0, 0]);
+    assert_eq!(u32::from_le_bytes([le[0], le[1], le[2], le[3]]), 201);
+    let be = output(OutputEncoding::BigEndian(4));
+    assert_eq!(be, vec![0, 0, 0, 201]);
+    assert_eq!(u32::from_be_bytes([be[0], be[1], be[2], be[3]]), 201);
+    assert_eq!(OutputEncoding::default(), OutputEncoding::Byte);
+}
+
+#[test]
+fn test_loop_counts() {
+    // The outer loop runs 3 times, and the inner loop 4 times for each of those
+    let program = source_to_tape("+++[>++++[-]<-]@");
+    for &mode in &[LoopMode::Rescan, LoopMode::JumpStack] {
+        let mut machine = SBrainVM::new(None, None, &program).unwrap();
+        assert_eq!(machine.loop_counts(), None);
+        machine.set_loop_mode(mode);
+        machine.set_loop_counting(true);
+        machine.run(Some(1000)).unwrap();
+        let counts = machine.loop_counts().unwrap();
+        assert_eq!(counts.get(&3), Some(&2));
+        assert_eq!(counts.get(&9), Some(&(3 * 3)));
+        assert_eq!(counts.len(), 2);
+    }
+}
+
+#[test]
+fn test_exit_code() {
+    let program = source_to_tape("+[-](!)@");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    assert_eq!(machine.exit_code(), None);
+    // Stopping for any other reason leaves no exit code
+    assert_eq!(machine.run(Some(2)).unwrap(), (2, HaltReason::CycleLimit));
+    assert_eq!(machine.exit_code(), None);
+
+    let (_, reason) = machine.run(None).unwrap();
+    assert_eq!(reason, HaltReason::Halted(255));
+    assert_eq!(machine.exit_code(), reason.exit_code());
+
+    machine.load_program(&program).unwrap();
+    assert_eq!(machine.exit_code(), None);
+}
+
+#[test]
+fn test_last_tape_address() {
+    // A halt in the very last cell of the exec tape is executed
+    let mut program = vec![1; 65535];
+    program.push(15);
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    assert_eq!(
+        machine.run(Some(100_000)).unwrap(),
+        (65535, HaltReason::Halted(0))
+    );
+}
+
+#[test]
+fn test_ran_off_tape() {
+    let program = source_to_tape("+>+");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    assert_eq!(machine.run(Some(10)).unwrap(), (10, HaltReason::CycleLimit));
+
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    machine.set_halt_at_program_end(true);
+    assert_eq!(machine.run(Some(10)).unwrap(), (3, HaltReason::RanOffTape));
+    assert_eq!(machine.exit_code(), None);
+
+    // Restarting is not running off the end
+    let program = source_to_tape("+~");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    machine.set_halt_at_program_end(true);
+    assert_eq!(machine.run(Some(10)).unwrap(), (10, HaltReason::CycleLimit));
+
+    // Nor is a program filling the whole tape wrapping around
+    let program = vec![3; 65536];
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    machine.set_halt_at_program_end(true);
+    assert_eq!(
+        machine.run(Some(100_000)).unwrap(),
+        (65536, HaltReason::RanOffTape)
+    );
+}
+
+#[test]
+fn test_run_capturing_events() {
+    let program = source_to_tape(",[.-]@");
+    let mut input = make_input_vec(&[3]);
+    let mut machine = SBrainVM::new(Some(&mut input), None, &program).unwrap();
+    let (events, cycles, reason) = machine.run_capturing_events(None).unwrap();
+    // Read, then three passes of output, decrement and jump
+    assert_eq!(events, vec![(2, 3), (5, 2), (8, 1)]);
+    assert_eq!((cycles, reason), (11, HaltReason::Halted(0)));
+}
+
+#[test]
+fn test_address_width() {
+    // No program is long enough to move that far on its own, so run several in turn
+    fn run_in_turn(machine: &mut SBrainVM, sources: &[&str]) {
+        for source in sources {
+            machine.reload_program(&source_to_tape(source), true).unwrap();
+            machine.run(None).unwrap();
+        }
+    }
+    let mut output = make_output_vec();
+    {
+        let mut machine = SBrainVM::new(None, Some(&mut output), &[])
+            .unwrap()
+            .with_address_width(AddressWidth::Bits32);
+        // Write 7 to cell 65537, check that cell 1 is untouched, and come back to read it
+        run_in_turn(
+            &mut machine,
+            &["65535>@", ">>7+.@", "65534<@", "2<.@", "65535>@", ">.@"],
+        );
+        assert_eq!(machine.data_address(), 65537);
+    }
+    assert_eq!(output.into_inner(), vec![7, 0, 7]);
+
+    // A 16 bit pointer wraps around to cell 1 instead
+    let mut machine = SBrainVM::new(None, None, &[]).unwrap();
+    run_in_turn(&mut machine, &["65535>@", ">>7+(@"]);
+    assert_eq!(machine.data_address(), 1);
+    assert_eq!(machine.exit_code(), Some(7));
+
+    // A 32 bit pointer wraps around at its own width
+    let mut machine = SBrainVM::new(None, None, &[])
+        .unwrap()
+        .with_address_width(AddressWidth::Bits32);
+    run_in_turn(&mut machine, &["<<@"]);
+    assert_eq!(machine.data_address(), u32::MAX - 1);
+}
+
+#[test]
+fn test_initial_state() {
+    // Skip the first instruction, add one to cell 10, and halt with the preset aux
+    let program = source_to_tape(">+@");
+    let mut machine = SBrainVM::new(None, None, &program)
+        .unwrap()
+        .data_pointer(10)
+        .instruction_pointer(1)
+        .aux(42);
+    assert_eq!(machine.run(None).unwrap(), (1, HaltReason::Halted(42)));
+    assert_eq!(machine.data_address(), 10);
+    // Read cell 10 back as the exit code
+    machine.reload_program(&source_to_tape("(@"), true).unwrap();
+    assert_eq!(machine.run(None).unwrap(), (1, HaltReason::Halted(1)));
+}
+
+#[test]
+fn test_output_fn() {
+    let program = source_to_tape(",[.,]");
+    let mut collected = Vec::new();
+    {
+        let mut input = make_input_vec(b"Hello, world!");
+        let mut machine = SBrainVM::new(Some(&mut input), None, &program)
+            .unwrap()
+            .output_fn(|byte| collected.push(byte));
+        machine.run(Some(1000)).unwrap();
+        assert!(machine.try_clone().is_none());
+    }
+
+    let mut output = make_output_vec();
+    {
+        let mut input = make_input_vec(b"Hello, world!");
+        let mut machine = SBrainVM::new(Some(&mut input), Some(&mut output), &program).unwrap();
+        machine.run(Some(1000)).unwrap();
+    }
+    assert_eq!(collected, output.into_inner());
+    assert_eq!(collected, b"Hello, world!");
+}
+
+#[test]
+fn test_underflow_mode() {
+    // Push one value, then pop two
+    let program = source_to_tape("+{+}}(@");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    assert_eq!(machine.run(None).unwrap(), (6, HaltReason::Halted(0)));
+
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    machine.set_underflow_mode(UnderflowMode::Trap);
+    assert_eq!(machine.run(None).unwrap(), (4, HaltReason::StackUnderflow));
+    // The cell still holds the first value popped
+    machine.reload_program(&source_to_tape("(@"), true).unwrap();
+    assert_eq!(machine.run(None).unwrap(), (1, HaltReason::Halted(1)));
+}
+
+#[test]
+fn test_run_until() {
+    // Print 1, 2, 3, ... forever
+    let program = source_to_tape("+[.+]");
+    let mut machine = SBrainVM::new_buffered(None, &program).unwrap();
+    let (cycles, reason) = machine
+        .run_until(|vm| vm.output().len() >= 3, Some(1000))
+        .unwrap();
+    assert_eq!(reason, HaltReason::PredicateMet);
+    // Set up, then three passes of print, increment and jump, stopping after the third print
+    assert_eq!(cycles, 9);
+    assert_eq!(machine.output(), &[1, 2, 3]);
+
+    // The cycle limit still applies
+    let mut machine = SBrainVM::new_buffered(None, &program).unwrap();
+    assert_eq!(
+        machine.run_until(|_| false, Some(10)).unwrap(),
+        (10, HaltReason::CycleLimit)
+    );
+}
+
+/// Records every event as a string, in order
+struct Tracer(Vec<String>);
+
+impl Observer for Tracer {
+    fn on_cycle(&mut self, _vm: &SBrainVM, op: u8) -> ControlFlow {
+        self.0.push(format!("cycle {}", op));
+        ControlFlow::Continue
+    }
+
+    fn on_input(&mut self, _vm: &SBrainVM, value: u8) {
+        self.0.push(format!("input {}", value));
+    }
+
+    fn on_output(&mut self, _vm: &SBrainVM, value: u8) {
+        self.0.push(format!("output {}", value));
+    }
+
+    fn on_halt(&mut self, _vm: &SBrainVM, reason: HaltReason) {
+        self.0.push(format!("halt {:?}", reason));
+    }
+}
+
+#[test]
+fn test_observer_events() {
+    let program = source_to_tape(",+.,@");
+    let mut input = make_input_vec(&[41]);
+    let mut machine = SBrainVM::new(Some(&mut input), None, &program).unwrap();
+    let mut tracer = Tracer(Vec::new());
+    machine.run_observed(&mut tracer, None).unwrap();
+    assert_eq!(
+        tracer.0,
+        vec![
+            "input 41",
+            "cycle 7",
+            "cycle 3",
+            "output 42",
+            "cycle 6",
+            // Past the end of the input
+            "input 0",
+            "cycle 7",
+            "halt Halted(0)",
+        ]
+    );
+}
+
+#[test]
+fn test_breakpoints() {
+    // Count down from 3, stopping before each output
+    let program = source_to_tape("+++[.-]@");
+    let mut machine = SBrainVM::new_buffered(None, &program).unwrap();
+    machine.set_breakpoint(4);
+    assert_eq!(
+        machine.run(None).unwrap(),
+        (4, HaltReason::Breakpoint { addr: 4 })
+    );
+    assert!(machine.output().is_empty());
+    // Running again resumes past the breakpoint, until it is reached again
+    assert_eq!(
+        machine.run(None).unwrap(),
+        (3, HaltReason::Breakpoint { addr: 4 })
+    );
+    assert_eq!(machine.output(), &[3]);
+
+    assert!(machine.clear_breakpoint(4));
+    assert!(!machine.clear_breakpoint(4));
+    assert!(machine.breakpoints().is_empty());
+    assert_eq!(machine.run(None).unwrap().1, HaltReason::Halted(0));
+    assert_eq!(machine.output(), &[3, 2, 1]);
+}
+
+#[test]
+fn test_watchpoints() {
+    // Count cell 1 up to 3, and leave cell 0 at 0
+    let program = source_to_tape("+++[>+<-]>(@");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    machine.set_watchpoint(1, Watch::Value(2));
+    assert_eq!(
+        machine.run(None).unwrap(),
+        (11, HaltReason::Watchpoint { addr: 1 })
+    );
+
+    // Every write counts, even one that leaves the value unchanged
+    let mut machine = SBrainVM::new(None, None, &source_to_tape(">{}+@")).unwrap();
+    machine.set_watchpoint(1, Watch::Write);
+    machine.set_watchpoint(0, Watch::Write);
+    assert_eq!(
+        machine.run(None).unwrap(),
+        (3, HaltReason::Watchpoint { addr: 1 })
+    );
+    assert_eq!(
+        machine.run(None).unwrap(),
+        (1, HaltReason::Watchpoint { addr: 1 })
+    );
+    assert!(machine.clear_watchpoint(1));
+    assert_eq!(machine.watchpoints().len(), 1);
+    assert_eq!(machine.run(None).unwrap(), (0, HaltReason::Halted(0)));
+}
+
+#[test]
+fn test_snapshot() {
+    // Stop partway through summing 1 + 2 + ... + 10, inside both loops
+    let program = source_to_tape("10+[[->+>+<<]>>[-<<+>>]<<-]>.@");
+    let snapshot = {
+        let mut machine = SBrainVM::new(None, None, &program).unwrap();
+        machine.set_loop_mode(LoopMode::JumpStack);
+        machine.run(Some(100)).unwrap();
+        machine.snapshot()
+    };
+    assert_eq!(snapshot.program, program);
+    assert_eq!(snapshot.jump_stack.len(), 2);
+
+    let run_from = |snapshot: Option<&VmSnapshot>| {
+        let mut machine = SBrainVM::new_buffered(None, &[]).unwrap();
+        machine.set_loop_mode(LoopMode::JumpStack);
+        match snapshot {
+            Some(snapshot) => machine.restore(snapshot).unwrap(),
+            None => machine.load_program(&program).unwrap(),
+        }
+        let (cycles, reason) = machine.run(None).unwrap();
+        (cycles, reason, machine.output().to_vec())
+    };
+    let (cycles, reason, output) = run_from(None);
+    assert_eq!(reason, HaltReason::Halted(0));
+    assert_eq!(output, vec![55]);
+    assert_eq!(run_from(Some(&snapshot)), (cycles - 100, reason, output));
+
+}
+
+#[test]
+fn test_snapshot_address_width() {
+    // A snapshot of a wide pointer needs a wide machine
+    let snapshot = {
+        let mut wide = SBrainVM::new(None, None, &source_to_tape("<+@"))
+            .unwrap()
+            .with_address_width(AddressWidth::Bits32);
+        wide.run(None).unwrap();
+        wide.snapshot()
+    };
+    assert_eq!(snapshot.far_cells, vec![(u32::MAX, 1)]);
+    let mut narrow = SBrainVM::new(None, None, &[]).unwrap();
+    assert!(narrow.restore(&snapshot).is_err());
+}
+
+#[test]
+fn test_resume_after_cycle_limit() {
+    let program = source_to_tape("10+[[->+>+<<]>>[-<<+>>]<<-]>.@");
+    let mut whole = SBrainVM::new_buffered(None, &program).unwrap();
+    let (total, reason) = whole.run(None).unwrap();
+    assert_eq!(reason, HaltReason::Halted(0));
+    let expected = whole.output().to_vec();
+    drop(whole);
+
+    // Top up seven cycles at a time until the program halts
+    let mut machine = SBrainVM::new_buffered(None, &program).unwrap();
+    let mut used = 0;
+    loop {
+        let (cycles, reason) = machine.run(Some(7)).unwrap();
+        used += cycles;
+        if reason != HaltReason::CycleLimit {
+            assert_eq!(reason, HaltReason::Halted(0));
+            break;
+        }
+        assert_eq!(cycles, 7);
+    }
+    assert_eq!(used, total);
+    assert_eq!(machine.output(), &expected[..]);
+}
+
+#[test]
+fn test_state_accessors() {
+    let program = source_to_tape("+++{>++{>+(@");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    machine.run(None).unwrap();
+    assert_eq!(&machine.data_tape()[..4], &[3, 2, 1, 0]);
+    assert_eq!(machine.data_tape().len(), 65536);
+    assert_eq!(machine.data_stack(), &[3, 2]);
+    assert_eq!(machine.auxi_r(), 1);
+    assert_eq!(machine.data_p(), 2);
+    // Halting leaves the instruction pointer on the halt
+    assert_eq!(machine.inst_p(), 11);
+}
+
+#[test]
+fn test_vm_config() {
+    let config = VmConfig {
+        data_len: 10,
+        exec_len: 16,
+        stack_capacity: 4,
+        ..VmConfig::default()
+    };
+    let program = source_to_tape("<+<++@");
+    let mut machine = SBrainVM::new_with_config(None, None, &program, config).unwrap();
+    machine.run(None).unwrap();
+    // The data pointer wraps at the end of the shorter tape
+    assert_eq!(machine.data_tape(), &[0, 0, 0, 0, 0, 0, 0, 0, 2, 1]);
+    assert_eq!(machine.data_p(), 8);
+
+    // So does the instruction pointer
+    let program = source_to_tape("++++");
+    let config = VmConfig {
+        exec_len: 4,
+        ..VmConfig::default()
+    };
+    let mut machine = SBrainVM::new_with_config(None, None, &program, config).unwrap();
+    assert_eq!(machine.run(Some(6)).unwrap(), (6, HaltReason::CycleLimit));
+    assert_eq!(machine.data_tape()[0], 6);
+
+    let error = machine.load_program(&[3; 5]).unwrap_err();
+    assert!(matches!(error, SBrainError::ProgramTooLong { len: 5, max: 4 }));
+    for &len in &[0, 65537] {
+        let config = VmConfig {
+            data_len: len,
+            ..VmConfig::default()
+        };
+        let error = SBrainVM::new_with_config(None, None, &[], config).unwrap_err();
+        assert!(matches!(error, SBrainError::TapeLength { .. }));
+    }
+}
+
+#[test]
+fn test_wide_cells() {
+    let program = source_to_tape("-.>+(@");
+    let mut output = make_output_vec();
+    {
+        let mut machine =
+            SBrainVM::<u32>::new_wide(None, Some(&mut output), &program, VmConfig::default())
+                .unwrap();
+        machine.set_output_encoding(OutputEncoding::wide(4));
+        machine.load_data_at(1, &[255]).unwrap();
+        // The exit code is the low byte of auxi_r
+        assert_eq!(machine.run(None).unwrap(), (5, HaltReason::Halted(0)));
+        assert_eq!(&machine.data_tape()[..2], &[u32::MAX, 256]);
+        assert_eq!(machine.auxi_r(), 256);
+    }
+    assert_eq!(output.into_inner(), vec![255; 4]);
+}
+
+#[test]
+fn test_growable_tape() {
+    let config = VmConfig {
+        data_len: 4,
+        ..VmConfig::default()
+    };
+    let program = source_to_tape(">>>>>>+@");
+    let mut machine = SBrainVM::new_with_config(None, None, &program, config)
+        .unwrap()
+        .with_address_width(AddressWidth::Growable);
+    machine.run(None).unwrap();
+    assert_eq!(machine.data_tape().len(), GROWTH_SEGMENT);
+    assert_eq!(machine.data_tape()[6], 1);
+
+    // Moving left of the start of the tape stops the machine instead of wrapping
+    machine.reload_program(&source_to_tape("<<<<<<<@"), true).unwrap();
+    assert_eq!(machine.run(None).unwrap(), (6, HaltReason::PointerUnderflow));
+    assert_eq!(machine.data_address(), 0);
+
+    // Sweeping right never wraps back to cell 0
+    machine.reload_program(&source_to_tape("+[>+]"), true).unwrap();
+    machine.run(Some(200_000)).unwrap();
+    assert!(machine.data_address() > 65536);
+    assert!(machine.data_tape().len() > 65536);
+    assert_eq!(machine.data_tape()[0], 1);
+
+    // The tape stops growing at its maximum length, halting the machine
+    let config = VmConfig {
+        data_len: 4,
+        max_data_len: 10_000,
+        ..VmConfig::default()
+    };
+    let mut machine = SBrainVM::new_with_config(None, None, &source_to_tape("+[>+]"), config)
+        .unwrap()
+        .with_address_width(AddressWidth::Growable);
+    let (_, reason) = machine.run(Some(1_000_000)).unwrap();
+    assert_eq!(reason, HaltReason::PointerOverflow);
+    assert_eq!(machine.data_address(), 9_999);
+    assert_eq!(machine.data_tape().len(), 10_000);
+    assert!(machine.data_tape().iter().all(|&cell| cell == 1));
+}
+
+#[test]
+fn test_diff_wide() {
+    // A cell written past the end of the data tape, and a move to reach it, both count
+    let program = source_to_tape(">7+@");
+    let before = SBrainVM::new(None, None, &program)
+        .unwrap()
+        .with_address_width(AddressWidth::Bits32)
+        .data_pointer(65535);
+    let mut after = SBrainVM::new(None, None, &program)
+        .unwrap()
+        .with_address_width(AddressWidth::Bits32)
+        .data_pointer(65535);
+    after.run(None).unwrap();
+    let diff = before.diff(&after);
+    assert_eq!(
+        diff.cells,
+        vec![CellChange {
+            addr: 65536,
+            old: 0,
+            new: 7
+        }]
+    );
+    assert_eq!(diff.data_p_delta, 1);
+
+    // Cells past the end of the shorter tape are compared too
+    let config = VmConfig {
+        data_len: 4,
+        ..VmConfig::default()
+    };
+    let program = source_to_tape(">>>>>>+@");
+    let before = SBrainVM::new_with_config(None, None, &program, config)
+        .unwrap()
+        .with_address_width(AddressWidth::Growable);
+    let mut after = SBrainVM::new_with_config(None, None, &program, config)
+        .unwrap()
+        .with_address_width(AddressWidth::Growable);
+    after.run(None).unwrap();
+    let change = CellChange {
+        addr: 6,
+        old: 1,
+        new: 0,
+    };
+    assert_eq!(after.diff(&before).cells, vec![change]);
+    assert_eq!(after.diff(&before).data_p_delta, -6);
+}
+
+#[test]
+fn test_strict_bounds() {
+    let config = VmConfig {
+        data_len: 4,
+        exec_len: 8,
+        ..VmConfig::default()
+    };
+    let program = source_to_tape(">>>>@");
+    let mut machine = SBrainVM::new_with_config(None, None, &program, config).unwrap();
+    machine.set_strict_bounds(true);
+    assert_eq!(machine.run(None).unwrap(), (3, HaltReason::PointerOverflow));
+    assert_eq!(machine.data_p(), 3);
+
+    machine.reload_program(&source_to_tape("<@"), true).unwrap();
+    machine.run(None).unwrap();
+    assert_eq!(machine.data_p(), 2);
+    machine.reload_program(&source_to_tape("<<<<@"), true).unwrap();
+    assert_eq!(machine.run(None).unwrap(), (2, HaltReason::PointerUnderflow));
+
+    // The instruction pointer halts at the end of the exec tape
+    machine.reload_program(&source_to_tape("++++++++"), true).unwrap();
+    assert_eq!(machine.run(Some(100)).unwrap(), (8, HaltReason::RanOffTape));
+    // Restarting is not running off the tape
+    machine.reload_program(&source_to_tape("+~"), true).unwrap();
+    assert_eq!(machine.run(Some(100)).unwrap(), (100, HaltReason::CycleLimit));
+}
+
+#[test]
+fn test_reset() {
+    let program = source_to_tape("+++{>++(.@");
+    let mut machine = SBrainVM::new_buffered(None, &program).unwrap();
+    machine.init_data(InitPattern::Fill(1));
+    machine.feed_input(b"unread");
+    let first = machine.run(None).unwrap();
+    assert_eq!(machine.output(), &[3]);
+
+    machine.reset(None).unwrap();
+    assert!(machine.data_tape().iter().all(|&cell| cell == 1));
+    assert_eq!(machine.data_stack(), &[] as &[u8]);
+    assert_eq!((machine.auxi_r(), machine.data_p(), machine.inst_p()), (0, 0, 0));
+    assert_eq!(machine.output(), &[] as &[u8]);
+    assert_eq!(machine.exit_code(), None);
+    // Running again gives the same result as the first run
+    assert_eq!(machine.run(None).unwrap(), first);
+    assert_eq!(machine.output(), &[3]);
+
+    machine.reset(Some(&source_to_tape(",.@"))).unwrap();
+    machine.run(None).unwrap();
+    assert_eq!(machine.output(), &[0]);
+    assert!(machine.input_exhausted());
+    assert!(machine.reset(Some(&[0; 65537])).is_err());
+    assert_eq!(machine.output(), &[0]);
+}
+
+#[test]
+fn test_vm_pool() {
+    let mut pool: VmPool = VmPool::new(VmConfig::default()).unwrap();
+    let mut input = make_input_vec(b"x");
+    let mut machine = pool.get(Some(&mut input), &source_to_tape(",.+{>+++(;@")).unwrap();
+    machine.init_data(InitPattern::Fill(9));
+    machine.set_self_modifying(true);
+    machine.set_bracket_mode(BracketMode::Strict);
+    assert_eq!(machine.run(None).unwrap(), (10, HaltReason::Halted(12)));
+    assert_eq!(machine.output(), b"x");
+    pool.reclaim(machine);
+    assert_eq!(pool.spare(), 1);
+
+    // A machine built on reclaimed tapes is just like a new one, whatever was done to the last
+    let program = source_to_tape("]>.{}.@");
+    let mut machine = pool.get(None, &program).unwrap();
+    assert_eq!(pool.spare(), 0);
+    let mut fresh = SBrainVM::new_buffered(None, &program).unwrap();
+    assert_eq!(machine.run(None).unwrap(), fresh.run(None).unwrap());
+    assert_eq!(machine.output(), fresh.output());
+    assert_eq!(machine.data_tape(), fresh.data_tape());
+    assert_eq!(machine.snapshot(), fresh.snapshot());
+    assert_eq!(machine.stats(), fresh.stats());
+    pool.reclaim(machine);
+
+    // Machines of another size are not kept, and a failed get keeps the spare
+    let small = VmConfig {
+        data_len: 16,
+        ..VmConfig::default()
+    };
+    pool.reclaim(SBrainVM::new_wide(None, None, &[], small).unwrap());
+    assert_eq!(pool.spare(), 1);
+    assert!(pool.get(None, &[0; 65537]).is_err());
+    assert_eq!(pool.spare(), 1);
+
+    let result = pool.evaluate(&source_to_tape(",[.,]@"), b"abc", Some(1000)).unwrap();
+    assert_eq!(result, evaluate(&source_to_tape(",[.,]@"), b"abc", Some(1000)).unwrap());
+    assert_eq!(pool.spare(), 1);
+    assert!(VmPool::<u8>::new(VmConfig {
+        exec_len: 0,
+        ..VmConfig::default()
+    })
+    .is_err());
+}
+
+#[test]
+fn test_builder() {
+    let mut input = make_input_vec(b"ab");
+    let mut output = make_output_vec();
+    {
+        let mut machine = SBrainVM::builder()
+            .input(&mut input)
+            .output(&mut output)
+            .program(&source_to_tape(",.,.>.+[]"))
+            .data(&[0, 7])
+            .cycle_limit(50)
+            .build()
+            .unwrap();
+        // The machine's cycle limit stops the endless loop
+        assert_eq!(machine.run(None).unwrap(), (50, HaltReason::CycleLimit));
+        assert_eq!(machine.run(Some(3)).unwrap(), (3, HaltReason::CycleLimit));
+        machine.set_cycle_limit(None);
+        assert_eq!(machine.data_tape()[1], 8);
+    }
+    assert_eq!(output.into_inner(), b"ab\x07");
+
+    let machine = VmBuilder::<u32>::new()
+        .config(VmConfig {
+            data_len: 16,
+            ..VmConfig::default()
+        })
+        .init_pattern(InitPattern::Fill(9))
+        .data(&[70000])
+        .build()
+        .unwrap();
+    assert_eq!(machine.data_tape()[..2], [70000, 9]);
+    assert!(SBrainVM::builder().data(&[0; 65537]).build().is_err());
+}
+
+#[test]
+fn test_time_limit() {
+    let program = source_to_tape("+[]");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    machine.set_time_limit(Some(std::time::Duration::from_millis(20)));
+    let (cycles, reason) = machine.run(None).unwrap();
+    assert_eq!(reason, HaltReason::TimedOut);
+    assert_eq!(cycles % 4096, 0);
+    // The limit applies to each run, and a cycle limit still applies alongside it
+    assert_eq!(machine.run(Some(10)).unwrap(), (10, HaltReason::CycleLimit));
+    let (_, reason) = machine.run_metered(u64::MAX, &[1; 32]).unwrap();
+    assert_eq!(reason, HaltReason::TimedOut);
+}
+
+#[test]
+fn test_cancel_token() {
+    let program = source_to_tape("+[]");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    let token = CancelToken::new();
+    machine.set_cancel_token(Some(token.clone()));
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        token.cancel();
+    });
+    assert_eq!(machine.run(None).unwrap().1, HaltReason::Cancelled);
+    canceller.join().unwrap();
+
+    // A token can also wrap an existing flag
+    let flag = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+    let token = CancelToken::from(flag.clone());
+    machine.set_cancel_token(Some(token.clone()));
+    assert_eq!(machine.run(Some(5)).unwrap(), (0, HaltReason::Cancelled));
+    token.reset();
+    assert!(!flag.load(std::sync::atomic::Ordering::Relaxed));
+    assert_eq!(machine.run(Some(5)).unwrap(), (5, HaltReason::CycleLimit));
+}
+
+#[test]
+fn test_loop_detection() {
+    // Counts cell 1 around forever, coming back to the same state every 1024 cycles
+    let program = source_to_tape("+[>+<]");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    machine.set_loop_detection(Some(256));
+    let (cycles, reason) = machine.run(Some(1_000_000)).unwrap();
+    assert_eq!(reason, HaltReason::LoopDetected);
+    assert!(cycles <= 2048, "{} cycles", cycles);
+
+    // A long but finite loop is never stopped
+    let program = source_to_tape("-[>+<-]@");
+    let mut machine = SBrainVM::new(None, None, &program).unwrap();
+    machine.set_loop_detection(Some(3));
+    assert_eq!(machine.run(None).unwrap().1, HaltReason::Halted(0));
+
+    // Reading input changes what the program will do, so it resets detection
+    let program = source_to_tape(",[.,]@");
+    let mut machine = SBrainVM::new_from_iter(vec![1; 1000], None, &program).unwrap();
+    machine.set_loop_detection(Some(1));
+    assert_eq!(machine.run(None).unwrap().1, HaltReason::Halted(0));
+}
+
+/// Run `program` with fusion enabled or not, in runs of `budget` cycles until it stops for any
+/// other reason or `total` cycles have run, and return everything the run can be observed by.
+fn fusion_outcome(
+    program: &[u8],
+    fusion: bool,
+    budget: u32,
+    total: u32,
+) -> (u32, HaltReason, Vec<u8>, Vec<u8>, u16, u16, RunStats) {
+    let machine = SBrainVM::new_buffered(None, program).unwrap();
+    fusion_outcome_with(machine, fusion, budget, total)
+}
+
+/// Like `fusion_outcome`, for a machine already built and configured.
+fn fusion_outcome_with(
+    mut machine: SBrainVM,
+    fusion: bool,
+    budget: u32,
+    total: u32,
+) -> (u32, HaltReason, Vec<u8>, Vec<u8>, u16, u16, RunStats) {
+    machine.set_fusion(fusion);
+    let mut cycles = 0;
+    let mut reason = HaltReason::CycleLimit;
+    while reason == HaltReason::CycleLimit && cycles < total {
+        let (ran, stopped) = machine.run(Some(budget.min(total - cycles))).unwrap();
+        cycles += ran;
+        reason = stopped;
+    }
+    let data = machine.data_tape()[..64].to_vec();
+    (
+        cycles,
+        reason,
+        machine.output().to_vec(),
+        data,
+        machine.data_p(),
+        machine.inst_p(),
+        machine.stats().clone(),
+    )
+}
+
+#[test]
+fn test_fusion() {
+    let hello = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.\
+                 +++.------.--------.>>+.>++.@";
+    assert_eq!(
+        fusion_outcome(&source_to_tape(hello), true, u32::MAX, 100_000).2,
+        b"Hello World!\n"
+    );
+
+    let mut sources = vec![
+        hello.to_string(),
+        "+++++[-]>---[+]<<<<[-]>>>>+++@".to_string(),
+        "-[>+>++<<-]>[-]>[+]<<<<<@".to_string(),
+    ];
+    let mut rng = gp::SplitMix64::new(7);
+    for _ in 0..50 {
+        let source: String = (0..40)
+            .map(|_| b"+-<>[].@"[(rng.next_u64() % 8) as usize] as char)
+            .collect();
+        sources.push(source);
+    }
+
+    // Every budget, including ones which end in the middle of a fused run, gives the same result
+    for source in &sources {
+        let program = source_to_tape(source);
+        for &budget in &[u32::MAX, 1, 7, 100] {
+            assert_eq!(
+                fusion_outcome(&program, true, budget, 20_000),
+                fusion_outcome(&program, false, budget, 20_000),
+                "{} in runs of {}",
+                source,
+                budget
+            );
+        }
+    }
+
+    // A machine which halts at the end of its program halts there with fusion too, including
+    // when the program fills the exec tape, so that execution wraps around
+    let config = VmConfig {
+        exec_len: 8,
+        ..VmConfig::default()
+    };
+    for source in &["++++++++", "++[>+<-]", "+[->+<]+", ">+[-]<++"] {
+        let program = source_to_tape(source);
+        for &budget in &[u32::MAX, 1, 3] {
+            let outcome = |fusion| {
+                let builder = VmBuilder::new().program(&program).config(config);
+                let mut machine = builder.buffered().build().unwrap();
+                machine.set_halt_at_program_end(true);
+                fusion_outcome_with(machine, fusion, budget, 100)
+            };
+            let fused = outcome(true);
+            assert_eq!(fused, outcome(false), "{} in runs of {}", source, budget);
+            assert_eq!(fused.1, HaltReason::RanOffTape);
+        }
+    }
+
+    // Wider cells clear with `[+]` just the same
+    let program = source_to_tape("---[+]>+++[-]@");
+    let mut machine: SBrainVM<u32> = VmBuilder::new().program(&program).build().unwrap();
+    assert_eq!(machine.run(None).unwrap(), (21, HaltReason::Halted(0)));
+    assert_eq!(machine.data_tape()[..2], [0, 0]);
+    assert_eq!(machine.stats().instructions, 22);
+}
+
+#[test]
+fn test_decoded_dispatch() {
+    // Every instruction runs the same from the compiled program as it does one at a time, in
+    // every bracket and loop mode, including unmatched brackets
+    let outcome = |program: &[u8], fusion: bool, modes: (BracketMode, LoopMode), budget: u32| {
+        let mut input = make_input_vec(b"Sbrain");
+        let mut machine = SBrainVM::new_buffered(Some(&mut input), program).unwrap();
+        machine.set_fusion(fusion);
+        machine.set_bracket_mode(modes.0);
+        machine.set_loop_mode(modes.1);
+        let mut cycles = 0;
+        let mut reason = HaltReason::CycleLimit;
+        while reason == HaltReason::CycleLimit && cycles < 2_000 {
+            let (ran, stopped) = machine.run(Some(budget.min(2_000 - cycles))).unwrap();
+            cycles += ran;
+            reason = stopped;
+        }
+        (
+            cycles,
+            reason,
+            machine.output().to_vec(),
+            machine.data_tape()[..64].to_vec(),
+            machine.data_stack().to_vec(),
+            machine.auxi_r(),
+            machine.inst_p(),
+            machine.stats().clone(),
+        )
+    };
+    let mut sources = vec!["+[>+<-]]>[-]<[@".to_string(), "~+>[-[+]{}]".to_string()];
+    let mut rng = gp::SplitMix64::new(5);
+    for _ in 0..30 {
+        let source: String = (0..30)
+            .map(|_| {
+                let alphabet = b"<>-+[].,{}()^!&@/\\%~:;_'\"?$";
+                alphabet[(rng.next_u64() % alphabet.len() as u64) as usize] as char
+            })
+            .collect();
+        sources.push(source);
+    }
+
+    let modes = [
+        (BracketMode::Lenient, LoopMode::Rescan),
+        (BracketMode::Strict, LoopMode::Rescan),
+        (BracketMode::Lenient, LoopMode::JumpStack),
+    ];
+    for source in &sources {
+        let program = source_to_tape(source);
+        for &modes in &modes {
+            for &budget in &[u32::MAX, 3] {
+                assert_eq!(
+                    outcome(&program, true, modes, budget),
+                    outcome(&program, false, modes, budget),
+                    "{} in {:?}",
+                    source,
+                    modes
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,17 @@
+//! Runs against in-memory buffers only, so it also passes without the `std` feature:
+//! `cargo test --no-default-features --test no_std`
+extern crate sbrain;
+use sbrain::*;
+
+#[test]
+fn test_cat_in_memory() {
+    let program = source_to_tape(",[.,]");
+    let mut input: &[u8] = b"Hello, world!";
+    let mut output: Vec<u8> = Vec::new();
+    {
+        let mut machine = SBrainVM::new(Some(&mut input), Some(&mut output), &program)
+            .expect("Could not build machine");
+        machine.run(Some(1000)).expect("I/O failed");
+    }
+    assert_eq!(&output, b"Hello, world!");
+}
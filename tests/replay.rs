@@ -0,0 +1,44 @@
+extern crate sbrain;
+use sbrain::*;
+
+fn sample_case() -> ReplayCase {
+    ReplayCase {
+        program: source_to_tape(",[.-]"),
+        input: vec![3],
+        limit: Some(1000),
+    }
+}
+
+#[test]
+fn test_replay_is_deterministic() {
+    let case = sample_case();
+    let result = case.replay().expect("Could not build machine");
+    assert_eq!(result.output, vec![3, 2, 1]);
+    assert_eq!(result.halt_reason, HaltReason::CycleLimit);
+    assert_eq!(case.replay().unwrap(), result);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_replay_round_trip() {
+    let case = sample_case();
+    let mut saved = Vec::new();
+    case.save(&mut saved).expect("Could not save case");
+    let loaded = ReplayCase::load(&mut &saved[..]).expect("Could not load case");
+
+    assert_eq!(loaded, case);
+    assert_eq!(loaded.replay().unwrap(), case.replay().unwrap());
+}
+
+#[test]
+fn test_evaluate_halted() {
+    // Halting with code 0 is not the same as being cut off
+    let result = evaluate(&source_to_tape(",.@"), &[0], Some(1000)).unwrap();
+    assert!(result.halted);
+    assert_eq!(result.exit_code, Some(0));
+
+    let result = evaluate(&source_to_tape("+[]"), &[], Some(1000)).unwrap();
+    assert!(!result.halted);
+    assert_eq!(result.exit_code, None);
+    assert_eq!(result.cycles, 1000);
+}
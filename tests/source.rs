@@ -1,9 +1,10 @@
+#![cfg(feature = "std")]
 extern crate sbrain;
 use sbrain::*;
 use std::io::Cursor;
 
 fn compare_output(source: &str, expected: &[u8]) {
-    let program = source_to_tape(&source);
+    let program = source_to_tape(source);
     let mut output = sbrain::make_output_vec();
     {
         let mut machine = SBrainVM::new(None, Some(&mut output), &program)
@@ -24,7 +25,7 @@ fn compare_output(source: &str, expected: &[u8]) {
 }
 
 fn compare_output_ext(source: &str, input: Vec<u8>, expected: &[u8]) {
-    let program = source_to_tape(&source);
+    let program = source_to_tape(source);
     let mut output = sbrain::make_output_vec();
     let mut input = Box::new(Cursor::new(input));
     {
@@ -116,3 +117,241 @@ fn test_auxi_bitwise_binary() {
     // read, load register, read, operate, write to tape, write out
     compare_output_ext(",(,&).", vec![2, 128], &[0]);
 }
+
+#[test]
+fn test_simple_run_limited() {
+    // Loops forever without touching stdin or stdout
+    assert_eq!(simple_run_limited("+[]", 1000), None);
+    assert_eq!(simple_run_limited("(!)@", 1000), Some(255));
+}
+
+#[test]
+fn test_simple_run_with_timeout() {
+    // Terminates whether stdin is closed, empty, or an idle terminal
+    let wait = std::time::Duration::from_millis(50);
+    assert_eq!(simple_run_with_timeout(",,,@", None, wait), Some(0));
+}
+
+#[test]
+fn test_auxi_rotate() {
+    // The high bit wraps around to the low bit and vice versa
+    compare_output_ext(",(/).", vec![0b1000_0001], &[0b0000_0011]);
+    compare_output_ext(",(\\).", vec![0b1000_0001], &[0b1100_0000]);
+    compare_output_ext(",(////////).", vec![0b1011_0001], &[0b1011_0001]);
+}
+
+/// A reader which hands out at most three bytes per call
+struct Trickle(Cursor<Vec<u8>>);
+
+impl std::io::Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(3);
+        self.0.read(&mut buf[..len])
+    }
+}
+
+#[test]
+fn test_compile_reader() {
+    let source = "+[->+<] 3ü+ 4+ #a comment with [brackets] and ünïcödé# ,.@ # and another #>";
+    let mut reader = Trickle(Cursor::new(source.as_bytes().to_vec()));
+    let tape = compile_reader(&mut reader).expect("Read failed");
+    assert_eq!(tape, source_to_tape(source));
+}
+
+#[test]
+fn test_compile_many() {
+    let source = "# cat # ,[.,]
+====
+# unterminated comment [ ] . , +
+====
++++. # done # @";
+    let programs = compile_many(source, DEFAULT_SEPARATOR);
+    assert_eq!(
+        programs,
+        vec![vec![7, 4, 6, 7, 5], vec![], vec![3, 3, 3, 6, 15]]
+    );
+    assert_eq!(compile_many(",|.", "|"), vec![vec![7], vec![6]]);
+}
+
+#[test]
+fn test_repeat_count() {
+    assert_eq!(source_to_tape("5+"), vec![3; 5]);
+    assert!(source_to_tape("0+").is_empty());
+    assert_eq!(source_to_tape("3>2<"), vec![1, 1, 1, 0, 0]);
+    assert_eq!(source_to_tape("12-"), vec![2; 12]);
+    // The count must directly precede an instruction
+    assert_eq!(source_to_tape("3 +"), vec![3]);
+    assert_eq!(source_to_tape("3#comment 4#+"), vec![3]);
+    assert_eq!(source_to_tape("#10#+"), vec![3]);
+    compare_output("65+.", b"A");
+}
+
+#[test]
+fn test_source_to_tape_with_map() {
+    let source = "+[# loop\n ends #\n\u{df}3-]@";
+    let (program, map) = source_to_tape_with_map(source);
+    assert_eq!(program, source_to_tape(source));
+    assert_eq!(map.positions().len(), program.len());
+    let at = |line, column| Some(SourcePosition { line, column });
+    assert_eq!(map.position(0), at(1, 1));
+    assert_eq!(map.position(1), at(1, 2));
+    // Columns count characters, and a repeated instruction maps to its character every time
+    assert_eq!(map.position(2), at(3, 3));
+    assert_eq!(map.position(4), at(3, 3));
+    assert_eq!(map.position(5), at(3, 4));
+    assert_eq!(map.position(6), at(3, 5));
+    assert_eq!(map.position(7), None);
+    assert_eq!(SourcePosition { line: 3, column: 4 }.to_string(), "3:4");
+
+    // Custom mappings are followed too
+    let mut mapping = Transliterator::spec_mapping();
+    mapping.insert('x', 15);
+    let (program, map) = Transliterator::with_mapping(mapping).compile_with_map("a\nbx");
+    assert_eq!(program, vec![15]);
+    assert_eq!(map.position(0), at(2, 2));
+}
+
+#[test]
+fn test_parse() {
+    let source = "+++[>++<-]>.@ # prints 6 # some words";
+    let program = parse(source).unwrap();
+    assert_eq!(program.tape(), &source_to_tape(source)[..]);
+    // Unbalanced brackets are left to `verify`
+    assert!(parse("[").is_ok());
+    assert!(parse("").unwrap().is_empty());
+
+    let at = |line, column| SourcePosition { line, column };
+    assert_eq!(
+        parse("+*.\n2@ ``").unwrap_err(),
+        vec![
+            Diagnostic::NotAnInstruction {
+                character: '*',
+                suggestion: None,
+                position: at(1, 2),
+            },
+            Diagnostic::RepeatedHalt { position: at(2, 2) },
+            Diagnostic::NotAnInstruction {
+                character: '`',
+                suggestion: Some('\''),
+                position: at(2, 4),
+            },
+            Diagnostic::NotAnInstruction {
+                character: '`',
+                suggestion: Some('\''),
+                position: at(2, 5),
+            },
+        ]
+    );
+    // Lookalikes inside comments are fine, but the last comment is never closed
+    let errors = parse("# a * b #@.@@ # \u{201c}").unwrap_err();
+    assert_eq!(
+        errors,
+        vec![
+            Diagnostic::RepeatedHalt {
+                position: at(1, 13)
+            },
+            Diagnostic::UnterminatedComment {
+                position: at(1, 15)
+            },
+        ]
+    );
+    assert_eq!(
+        errors[1].to_string(),
+        "1:15: Comment is never closed with a '#'."
+    );
+
+    assert!(parse("65536+").is_ok());
+    assert_eq!(
+        parse("*65536+\n+\u{2014}").unwrap_err(),
+        vec![
+            Diagnostic::NotAnInstruction {
+                character: '*',
+                suggestion: None,
+                position: at(1, 1),
+            },
+            Diagnostic::ProgramTooLong {
+                len: 65537,
+                position: at(2, 1),
+            },
+            Diagnostic::NotAnInstruction {
+                character: '\u{2014}',
+                suggestion: Some('-'),
+                position: at(2, 2),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_custom_mapping() {
+    let mut mapping = Transliterator::spec_mapping();
+    mapping.insert('+', 2);
+    mapping.insert('-', 3);
+    mapping.insert('ß', 15);
+    let transliterator = Transliterator::with_mapping(mapping);
+
+    assert_eq!(transliterator.compile("++-ß"), vec![2, 2, 3, 15]);
+    assert_eq!(transliterator.character(2), Some('+'));
+    assert_eq!(transliterator.character(15), Some('@'));
+    assert_eq!(Transliterator::new().character(3), Some('+'));
+
+    // Multi-byte characters survive being split across reads
+    let mut reader = Trickle(Cursor::new("ßß#ß#ß".as_bytes().to_vec()));
+    assert_eq!(
+        transliterator.compile_reader(&mut reader).unwrap(),
+        vec![15, 15, 15]
+    );
+}
+
+#[test]
+fn test_compile_reader_invalid_utf8() {
+    // An invalid byte between a count and an instruction cancels the count
+    let mut reader = Cursor::new(b"3\xff+2+".to_vec());
+    assert_eq!(compile_reader(&mut reader).unwrap(), vec![3, 3, 3]);
+}
+
+#[test]
+fn test_compile_too_long() {
+    let source = "+".repeat(65537);
+    assert_eq!(
+        compile(&source),
+        Err(CompileError::ProgramTooLong { len: 65537 })
+    );
+    // The length reported is the program's whole length, however long its repeat counts
+    assert_eq!(
+        compile("99999+>"),
+        Err(CompileError::ProgramTooLong { len: 100000 })
+    );
+    assert_eq!(
+        compile("99999999999999999999999+"),
+        Err(CompileError::ProgramTooLong { len: usize::MAX })
+    );
+    // An unchecked tape stops growing once it can no longer be loaded
+    assert_eq!(source_to_tape("99999999999999999999999+").len(), 65537);
+    assert_eq!(compile(&source[1..]).map(|tape| tape.len()), Ok(65536));
+}
+
+#[test]
+fn test_compile_limited() {
+    let source = "+[>[>[>[>[-]<]<]<]<]";
+    assert_eq!(compile_limited(source, 5), Ok(source_to_tape(source)));
+    assert_eq!(
+        compile_limited(source, 4),
+        Err(CompileError::TooDeeplyNested { depth: 5, addr: 9 })
+    );
+    // Unmatched closing brackets don't let later loops nest deeper
+    assert!(compile_limited("]][[", 2).is_ok());
+}
+
+#[test]
+fn test_compile_file() {
+    let source = "[.>]@ # print the tape #";
+    let path = std::env::temp_dir().join(format!("sbrain-compile-{}.sb", std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    let tape = compile_file(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(tape.unwrap(), source_to_tape(source));
+
+    let missing = compile_file(&path).unwrap_err();
+    assert_eq!(missing.kind(), std::io::ErrorKind::NotFound);
+}
@@ -0,0 +1,18 @@
+extern crate sbrain;
+use sbrain::*;
+
+#[test]
+fn test_widen_narrow() {
+    let tape = vec![0, 1, 127, 128, 255];
+    let wide = widen_data(&tape);
+    assert_eq!(wide, vec![0u32, 1, 127, 128, 255]);
+    assert_eq!(narrow_data(&wide), Ok(tape));
+
+    assert_eq!(
+        narrow_data(&[1, 256, 2, 70000]),
+        Err(NarrowError {
+            addr: 1,
+            value: 256
+        })
+    );
+}
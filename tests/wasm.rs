@@ -0,0 +1,140 @@
+#![cfg(feature = "std")]
+extern crate sbrain;
+extern crate wasmi;
+use sbrain::gp::SplitMix64;
+use sbrain::*;
+use wasmi::{Caller, Engine, Instance, Linker, Module, Store};
+
+struct Host {
+    input: Vec<u8>,
+    read: usize,
+    output: Vec<u8>,
+}
+
+/// Instantiate the module compiled from `program`, reading `input`.
+fn instantiate(program: &[u8], input: &[u8]) -> (Store<Host>, Instance) {
+    let wasm = to_wasm(program).expect("Could not compile program");
+    let engine = Engine::default();
+    let module = Module::new(&engine, &wasm[..]).expect("Invalid module");
+    let host = Host {
+        input: input.to_vec(),
+        read: 0,
+        output: Vec::new(),
+    };
+    let mut store = Store::new(&engine, host);
+    let mut linker = <Linker<Host>>::new(&engine);
+    linker
+        .func_wrap("env", "input", |mut caller: Caller<Host>| -> i32 {
+            let host = caller.data_mut();
+            match host.input.get(host.read) {
+                Some(&byte) => {
+                    host.read += 1;
+                    i32::from(byte)
+                }
+                None => -1,
+            }
+        })
+        .unwrap();
+    linker
+        .func_wrap(
+            "env",
+            "output",
+            |mut caller: Caller<Host>, byte: i32, channel: i32| {
+                if channel == 0 {
+                    caller.data_mut().output.push(byte as u8);
+                }
+            },
+        )
+        .unwrap();
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .unwrap()
+        .start(&mut store)
+        .unwrap();
+    (store, instance)
+}
+
+/// Run `program` to completion as WebAssembly, returning its exit code (or -1 if it didn't halt
+/// within `limit` cycles), its output, and the start of its data tape.
+fn run_wasm(program: &[u8], input: &[u8], limit: i64) -> (i32, Vec<u8>, Vec<u8>) {
+    let (mut store, instance) = instantiate(program, input);
+    let run = instance.get_typed_func::<i64, i32>(&store, "run").unwrap();
+    let code = run.call(&mut store, limit).unwrap();
+    let memory = instance.get_memory(&store, "memory").unwrap();
+    let data = memory.data(&store)[..64].to_vec();
+    (code, store.data().output.clone(), data)
+}
+
+const ALPHABET: &[u8] = b"<>-+[].,{}()^!&@/\\%~:;_'\"?$";
+
+#[test]
+fn test_to_wasm_matches_machine() {
+    let mut sources = vec![
+        "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.\
+         +++.------.--------.>>+.>++.@"
+            .to_string(),
+        ",[.,]@".to_string(),
+        // Stack, aux register, comparisons, rotations, skips and channels
+        ",{,}>,(<_>-\"/+?.-.\\+$.!&)@".to_string(),
+        "+++{{}}}}<[-]>([>+<-]>)@".to_string(),
+        // Restarting
+        "+>+<[->@]~".to_string(),
+    ];
+    let mut rng = SplitMix64::new(3);
+    for _ in 0..200 {
+        let source: String = (0..30)
+            .map(|_| ALPHABET[(rng.next_u64() % ALPHABET.len() as u64) as usize] as char)
+            .collect();
+        sources.push(source);
+    }
+
+    let mut compared = 0;
+    for source in &sources {
+        let program = source_to_tape(source);
+        let mut input = make_input_vec(b"Sbrain");
+        let mut machine = SBrainVM::new_buffered(Some(&mut input), &program).unwrap();
+        let (cycles, reason) = machine.run(Some(5_000)).unwrap();
+        let code = match reason {
+            HaltReason::Halted(code) => i32::from(code),
+            _ => continue,
+        };
+        // With a limit of exactly the cycles the program takes, it still halts
+        assert_eq!(
+            run_wasm(&program, b"Sbrain", i64::from(cycles)),
+            (
+                code,
+                machine.output().to_vec(),
+                machine.data_tape()[..64].to_vec()
+            ),
+            "{}",
+            source
+        );
+        compared += 1;
+    }
+    assert!(compared > 50, "only {} programs halted", compared);
+}
+
+#[test]
+fn test_to_wasm_limit() {
+    // A program which never halts stops at its limit, and can carry on from there
+    let program = source_to_tape("+[>+<]");
+    let (mut store, instance) = instantiate(&program, b"");
+    let run = instance.get_typed_func::<i64, i32>(&store, "run").unwrap();
+    assert_eq!(run.call(&mut store, 1000).unwrap(), -1);
+    assert_eq!(run.call(&mut store, 1000).unwrap(), -1);
+    let memory = instance.get_memory(&store, "memory").unwrap();
+    let counted = memory.data(&store)[1];
+    assert!((200..=250).contains(&counted), "{}", counted);
+
+    // Halted programs stay halted
+    let program = source_to_tape("+++(@");
+    let (mut store, instance) = instantiate(&program, b"");
+    let run = instance.get_typed_func::<i64, i32>(&store, "run").unwrap();
+    assert_eq!(run.call(&mut store, 100).unwrap(), 3);
+    assert_eq!(run.call(&mut store, 100).unwrap(), 3);
+
+    assert!(matches!(
+        to_wasm(&vec![0; MAX_PROGRAM_LEN + 1]),
+        Err(SBrainError::ProgramTooLong { .. })
+    ));
+}